@@ -4,7 +4,7 @@ import SearchResult from '../contracts/SearchResult';
 
 const SendSearchQuery = async (queryString: string) => {
     const result = await axios(`http://127.0.0.1:8080/search/${queryString}`);
-    const podResults = result.data as PodiumSearchResult[];
+    const podResults = result.data.results as PodiumSearchResult[];
     const searchResults: SearchResult[] = [];
     podResults.forEach(podResult => {
         podResult.location.forEach(l => {
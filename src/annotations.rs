@@ -0,0 +1,287 @@
+use common::anyhow::{Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::info;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+/// Tags and a note a user attached to an indexed file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+}
+
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.note.is_none()
+    }
+}
+
+/// A line of the annotations file, the last line of a hash replaces the ones before it
+#[derive(Serialize, Deserialize)]
+struct AnnotationRecord {
+    hash: String,
+    /// Where the file was when it was annotated, used to find the annotation once the file changed
+    locations: Vec<PathBuf>,
+    #[serde(flatten)]
+    annotation: Annotation,
+}
+
+#[derive(Default)]
+struct AnnotationsState {
+    by_hash: HashMap<String, Annotation>,
+    hashes_by_location: HashMap<PathBuf, String>,
+    /// How much of the file has been read, lines appended by another podium process are read on the next access
+    read_length: u64,
+}
+
+/// The annotations of files, by the hash of their contents
+/// Every change is appended to a file as a line of JSON, so annotations survive restarts and reindexing
+/// A file whose contents changed since it was annotated keeps its annotation through its location
+pub struct Annotations {
+    state: RwLock<AnnotationsState>,
+    file: Mutex<File>,
+    path: PathBuf,
+}
+
+impl Annotations {
+    /// Loads the annotations stored at this path, the file is created if it doesn't exist
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| {
+                log_and_return_error_string(format!(
+                    "annotations: Failed to open the annotations at path: {:?}",
+                    path
+                ))
+            })?;
+
+        let annotations = Annotations {
+            state: RwLock::new(AnnotationsState::default()),
+            file: Mutex::new(file),
+            path: path.to_path_buf(),
+        };
+        annotations.refresh()?;
+        info!(
+            "Loaded the annotations of {} files from {:?}",
+            annotations.state.read().unwrap().by_hash.len(),
+            path
+        );
+        Ok(annotations)
+    }
+
+    /// The annotation of the file with this hash, or else of the file which was at one of its locations
+    pub fn get(&self, hash: &str, locations: &[PathBuf]) -> Result<Option<Annotation>> {
+        self.refresh()?;
+        let state = self.state.read().unwrap();
+        if let Some(annotation) = state.by_hash.get(hash) {
+            return Ok(Some(annotation.clone()));
+        }
+        Ok(locations
+            .iter()
+            .filter_map(|location| state.hashes_by_location.get(location))
+            .find_map(|location_hash| state.by_hash.get(location_hash))
+            .cloned())
+    }
+
+    /// Replaces the annotation of a file and writes it to disk, an empty annotation removes it
+    pub fn set(&self, hash: &str, locations: &[PathBuf], annotation: Annotation) -> Result<()> {
+        let record = AnnotationRecord {
+            hash: hash.to_string(),
+            locations: locations.to_vec(),
+            annotation,
+        };
+        let line = serde_json::to_string(&record)?;
+        writeln!(self.file.lock().unwrap(), "{}", line).with_context(|| {
+            log_and_return_error_string(format!(
+                "annotations: Failed to annotate document: {:?}",
+                hash
+            ))
+        })?;
+        self.refresh()
+    }
+
+    /// The hashes and the locations of the annotations with this tag
+    /// The files at the locations may have changed since, so they're checked with `get` before they count
+    pub fn tagged(&self, tag: &str) -> Result<(Vec<String>, Vec<PathBuf>)> {
+        self.refresh()?;
+        let state = self.state.read().unwrap();
+        let hashes = state
+            .by_hash
+            .iter()
+            .filter(|(_, annotation)| annotation.tags.iter().any(|other| other == tag))
+            .map(|(hash, _)| hash.clone())
+            .collect::<Vec<String>>();
+        let locations = state
+            .hashes_by_location
+            .iter()
+            .filter(|(_, hash)| hashes.contains(hash))
+            .map(|(location, _)| location.clone())
+            .collect();
+        Ok((hashes, locations))
+    }
+
+    /// Reads the lines which were appended since the file was last read
+    fn refresh(&self) -> Result<()> {
+        let length = std::fs::metadata(&self.path)?.len();
+        if length == self.state.read().unwrap().read_length {
+            return Ok(());
+        }
+
+        let mut file = self.file.lock().unwrap();
+        let mut state = self.state.write().unwrap();
+        file.seek(SeekFrom::Start(state.read_length))?;
+        let mut reader = BufReader::new(&*file);
+        let mut line = String::new();
+        // A line which is still being written by another process is read once it's complete
+        while reader.read_line(&mut line)? > 0 && line.ends_with('\n') {
+            state.read_length += line.len() as u64;
+            match serde_json::from_str::<AnnotationRecord>(&line) {
+                Ok(record) => state.apply(record),
+                Err(e) => info!("Skipping an invalid annotation {:?}: {:?}", line, e),
+            }
+            line.clear();
+        }
+        Ok(())
+    }
+}
+
+impl AnnotationsState {
+    fn apply(&mut self, record: AnnotationRecord) {
+        for location in record.locations {
+            self.hashes_by_location
+                .insert(location, record.hash.clone());
+        }
+        if record.annotation.is_empty() {
+            self.by_hash.remove(&record.hash);
+        } else {
+            self.by_hash.insert(record.hash, record.annotation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn annotations_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("podium_{}_{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn annotation(tags: &[&str], note: Option<&str>) -> Annotation {
+        Annotation {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            note: note.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_annotations_are_replayed_from_the_file() {
+        let path = annotations_path("annotations_replayed");
+        let report = [PathBuf::from("/docs/report.pdf")];
+        {
+            let annotations = Annotations::open(&path).unwrap();
+            annotations
+                .set("a", &report, annotation(&["work"], None))
+                .unwrap();
+            annotations
+                .set(
+                    "a",
+                    &report,
+                    annotation(&["work", "to pay"], Some("Friday")),
+                )
+                .unwrap();
+        }
+
+        let annotations = Annotations::open(&path).unwrap();
+        assert_eq!(
+            annotations.get("a", &[]).unwrap(),
+            Some(annotation(&["work", "to pay"], Some("Friday")))
+        );
+        assert_eq!(
+            annotations.tagged("to pay").unwrap(),
+            (vec![String::from("a")], report.to_vec())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_changes_of_another_process_are_read() {
+        let path = annotations_path("annotations_other_process");
+        let annotations = Annotations::open(&path).unwrap();
+        let other_process = Annotations::open(&path).unwrap();
+        other_process
+            .set("a", &[], annotation(&["work"], None))
+            .unwrap();
+        assert_eq!(
+            annotations.get("a", &[]).unwrap(),
+            Some(annotation(&["work"], None))
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_an_empty_annotation_removes_it() {
+        let path = annotations_path("annotations_removed");
+        let annotations = Annotations::open(&path).unwrap();
+        annotations
+            .set("a", &[], annotation(&["work"], Some("Friday")))
+            .unwrap();
+        annotations.set("a", &[], Annotation::default()).unwrap();
+        assert_eq!(annotations.get("a", &[]).unwrap(), None);
+        assert_eq!(annotations.tagged("work").unwrap(), (vec![], vec![]));
+
+        let annotations = Annotations::open(&path).unwrap();
+        assert_eq!(annotations.get("a", &[]).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_a_changed_file_keeps_its_annotation_through_its_location() {
+        let path = annotations_path("annotations_location");
+        let annotations = Annotations::open(&path).unwrap();
+        let report = [PathBuf::from("/docs/report.pdf")];
+        annotations
+            .set("a", &report, annotation(&["work"], None))
+            .unwrap();
+
+        assert_eq!(
+            annotations.get("b", &report).unwrap(),
+            Some(annotation(&["work"], None))
+        );
+        assert_eq!(
+            annotations
+                .get("b", &[PathBuf::from("/docs/other.pdf")])
+                .unwrap(),
+            None
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_lines_are_skipped() {
+        let path = annotations_path("annotations_invalid");
+        fs::write(
+            &path,
+            "not json\n{\"hash\":\"a\",\"locations\":[],\"tags\":[\"work\"],\"note\":null}\n",
+        )
+        .unwrap();
+        let annotations = Annotations::open(&path).unwrap();
+        assert_eq!(
+            annotations.get("a", &[]).unwrap(),
+            Some(annotation(&["work"], None))
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,19 @@
 extern crate podium_lib;
-use podium_lib::config::{get_config, AppConfig};
+use podium_lib::config::{get_config, AnnotateCommand, AppConfig, DuplicatesReport};
+use podium_lib::indexers::{configure_image_classifier, configure_text_indexer, ClassifierConfig};
 use podium_lib::routes::app_state::*;
 use podium_lib::routes::search;
-use podium_lib::tantivy_process::{start_tantivy, tantivy_init, TantivyConfig};
+use podium_lib::searcher::{IndexedFile, DEFAULT_MAX_SIMHASH_DISTANCE};
+use podium_lib::tantivy_process::{searcher_init, start_tantivy, tantivy_init, TantivyConfig};
 
+use std::ffi::OsStr;
 use std::io;
+use std::path::Path;
 
 use actix_web::{web, App, HttpServer};
 use app_dirs::*;
 use tokio;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, prelude::*};
 
 use tracing_flame::FlameLayer;
@@ -29,7 +33,32 @@ async fn async_main() -> io::Result<()> {
     // Get or create settings
     let settings = get_or_create_settings(&config);
 
-    let (searcher, mut tantivy_wrapper) = tantivy_init(&settings).unwrap();
+    if let Some(report) = config.duplicates {
+        return print_duplicates(&settings, report);
+    }
+
+    if let Some(command) = config.annotate {
+        return annotate(&settings, command);
+    }
+
+    let classifier_config = get_classifier_config(&config);
+    #[cfg(feature = "ocr")]
+    podium_lib::indexers::configure_ocr(classifier_config.models_directory.clone()).unwrap();
+    #[cfg(feature = "semantic")]
+    podium_lib::embeddings::configure(classifier_config.models_directory.clone()).unwrap();
+    configure_image_classifier(classifier_config).unwrap();
+    configure_text_indexer(config.max_text_size).unwrap();
+
+    let (searcher, mut tantivy_wrapper) = tantivy_init(&settings).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "Failed to open the index at {:?}: {}",
+                settings.index_path, e
+            ),
+        )
+    })?;
+    let searcher = searcher.with_relevance(config.relevance.clone());
 
     let _tantivy_thread = tokio::spawn(async move {
         start_tantivy(&settings, &mut tantivy_wrapper)
@@ -70,6 +99,84 @@ fn get_or_create_settings(app_config: &AppConfig) -> TantivyConfig {
     }
 }
 
+fn print_duplicates(settings: &TantivyConfig, report: DuplicatesReport) -> io::Result<()> {
+    let searcher = searcher_init(settings).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let result = match report {
+        DuplicatesReport::Exact => searcher
+            .exact_duplicates()
+            .map(|groups| serde_json::to_string_pretty(&groups)),
+        DuplicatesReport::Near => searcher
+            .near_duplicates(DEFAULT_MAX_SIMHASH_DISTANCE)
+            .map(|groups| serde_json::to_string_pretty(&groups)),
+    }
+    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    println!("{}", result?);
+    Ok(())
+}
+
+fn annotate(settings: &TantivyConfig, command: AnnotateCommand) -> io::Result<()> {
+    let searcher = searcher_init(settings).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    // Indexed locations are absolute
+    let file = IndexedFile::Location(std::fs::canonicalize(&command.path)?);
+    let update = command.update;
+    let result = if update.tags.is_none() && update.note.is_none() {
+        searcher.annotation(&file)
+    } else {
+        searcher.annotate(&file, update)
+    }
+    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+fn get_classifier_config(app_config: &AppConfig) -> ClassifierConfig {
+    let models_directory = match &app_config.models_directory {
+        Some(models_directory) => models_directory.clone(),
+        None => {
+            let data_models_directory =
+                app_dir(AppDataType::UserData, &APP_INFO, "models").unwrap();
+            let bundled_models_directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("models");
+            // Nothing is installed in the data directory yet, the manifests which come with podium are used instead
+            if !has_model_manifests(&data_models_directory)
+                && has_model_manifests(&bundled_models_directory)
+            {
+                bundled_models_directory
+            } else {
+                data_models_directory
+            }
+        }
+    };
+    if !has_model_manifests(&models_directory) {
+        warn!(
+            "No model manifests in {:?}, images won't be labelled. To label them, add a model with its <name>.json manifest there or pass --models-directory",
+            models_directory
+        );
+    }
+    info!(
+        "Loading image classification models from: {:?}",
+        models_directory
+    );
+
+    ClassifierConfig {
+        models_directory,
+        top_k: app_config.top_labels,
+        threshold: app_config.label_threshold,
+    }
+}
+
+/// If the directory has a `<name>.json` manifest of a model
+fn has_model_manifests(models_directory: &Path) -> bool {
+    std::fs::read_dir(models_directory)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .any(|entry| entry.path().extension() == Some(OsStr::new("json")))
+        })
+        .unwrap_or(false)
+}
+
 fn setup_global_subscriber(config: &AppConfig) -> impl Drop {
     let (flame_layer, _guard) = FlameLayer::with_file("./tracing.folded").unwrap();
     let _t = tracing_subscriber::fmt()
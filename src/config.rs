@@ -1,4 +1,9 @@
+use crate::relevance::Relevance;
+use crate::searcher::AnnotationUpdate;
+
 use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg};
+use common::anyhow::Result;
+use serde::Deserialize;
 
 use tracing::Level;
 
@@ -9,6 +14,43 @@ pub struct AppConfig {
     pub scan_directories: Vec<PathBuf>,
     pub verbosity: Level,
     pub port: u16,
+    /// Where image classification models are loaded from, defaults to the app data directory
+    pub models_directory: Option<PathBuf>,
+    /// How many labels each model can give an image
+    pub top_labels: usize,
+    /// Labels with a lower confidence than this aren't indexed
+    pub label_threshold: f32,
+    /// How many bytes of a text file are indexed
+    pub max_text_size: usize,
+    /// Print a report of the duplicate files in the index instead of starting the server
+    pub duplicates: Option<DuplicatesReport>,
+    /// Print or change the tags and the note of a file instead of starting the server
+    pub annotate: Option<AnnotateCommand>,
+    /// How search results are ranked, from the config file
+    pub relevance: Relevance,
+}
+
+/// The settings which are read from the config file instead of the command line
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    relevance: Relevance,
+}
+
+/// The file to annotate and what changes, nothing changes when neither tags nor a note are given
+#[derive(Debug)]
+pub struct AnnotateCommand {
+    pub path: PathBuf,
+    pub update: AnnotationUpdate,
+}
+
+/// Which duplicates are reported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicatesReport {
+    /// Identical files in more than one location
+    Exact,
+    /// Documents whose text differs trivially
+    Near,
 }
 
 pub fn get_config() -> AppConfig {
@@ -42,6 +84,77 @@ pub fn get_config() -> AppConfig {
                 .validator(port_validator)
                 .help("Port to host query resolver"),
         )
+        .arg(
+            Arg::with_name("models-directory")
+                .long("models-directory")
+                .required(false)
+                .takes_value(true)
+                .help("Directory with the image classification models and their manifests"),
+        )
+        .arg(
+            Arg::with_name("top-labels")
+                .long("top-labels")
+                .default_value("5")
+                .required(false)
+                .validator(top_labels_validator)
+                .help("How many labels each image classification model can give an image"),
+        )
+        .arg(
+            Arg::with_name("label-threshold")
+                .long("label-threshold")
+                .default_value("0.1")
+                .required(false)
+                .validator(threshold_validator)
+                .help("Minimum confidence, between 0 and 1, of an image label to be indexed"),
+        )
+        .arg(
+            Arg::with_name("max-text-size")
+                .long("max-text-size")
+                .default_value("10")
+                .required(false)
+                .validator(max_text_size_validator)
+                .help("How many megabytes of a text file are indexed, the rest is skipped"),
+        )
+        .arg(
+            Arg::with_name("duplicates")
+                .long("duplicates")
+                .required(false)
+                .takes_value(true)
+                .possible_values(&["exact", "near"])
+                .help("Print the duplicate files in the index as JSON, then exit"),
+        )
+        .arg(
+            Arg::with_name("annotate")
+                .long("annotate")
+                .required(false)
+                .takes_value(true)
+                .validator(path_validator)
+                .help("Print the tags and the note of an indexed file as JSON, changed by --tags and --note, then exit"),
+        )
+        .arg(
+            Arg::with_name("tags")
+                .long("tags")
+                .required(false)
+                .takes_value(true)
+                .requires("annotate")
+                .help("Comma separated tags which replace the tags of the file, empty to remove them"),
+        )
+        .arg(
+            Arg::with_name("note")
+                .long("note")
+                .required(false)
+                .takes_value(true)
+                .requires("annotate")
+                .help("Replaces the note of the file, empty to remove it"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .required(false)
+                .takes_value(true)
+                .validator(path_validator)
+                .help("TOML config file with the relevance settings, defaults to Podium/config.toml in the config directory"),
+        )
         .get_matches();
 
     let scan_directories = matches
@@ -65,13 +178,81 @@ pub fn get_config() -> AppConfig {
         None => 8080,
     };
 
+    let models_directory = matches.value_of("models-directory").map(PathBuf::from);
+
+    let top_labels = matches
+        .value_of("top-labels")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+
+    let label_threshold = matches
+        .value_of("label-threshold")
+        .unwrap()
+        .parse::<f32>()
+        .unwrap();
+
+    let max_text_size = matches
+        .value_of("max-text-size")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap()
+        * 1024
+        * 1024;
+
+    let duplicates = match matches.value_of("duplicates") {
+        Some("exact") => Some(DuplicatesReport::Exact),
+        Some("near") => Some(DuplicatesReport::Near),
+        _ => None,
+    };
+
+    let annotate = matches.value_of("annotate").map(|path| AnnotateCommand {
+        path: PathBuf::from(path),
+        update: AnnotationUpdate {
+            tags: matches
+                .value_of("tags")
+                .map(|tags| tags.split(',').map(String::from).collect()),
+            note: matches.value_of("note").map(String::from),
+        },
+    });
+
+    // The default config file is optional, one passed on the command line has to be valid
+    let config_path = matches
+        .value_of("config")
+        .map(PathBuf::from)
+        .or_else(|| {
+            dirs::config_dir().map(|config_dir| config_dir.join("Podium").join("config.toml"))
+        })
+        .filter(|config_path| config_path.exists());
+    let config_file = match config_path {
+        Some(config_path) => read_config_file(&config_path).unwrap_or_else(|e| {
+            panic!("Failed to read the config file at {:?}: {}", config_path, e)
+        }),
+        None => ConfigFile::default(),
+    };
+
     AppConfig {
         scan_directories,
         verbosity,
         port,
+        models_directory,
+        top_labels,
+        label_threshold,
+        max_text_size,
+        duplicates,
+        annotate,
+        relevance: config_file.relevance,
     }
 }
 
+fn read_config_file(path: &Path) -> Result<ConfigFile> {
+    let mut config = ::config::Config::default();
+    config.merge(::config::File::from(path))?;
+    let config_file: ConfigFile = config.try_into()?;
+    config_file.relevance.validate()?;
+    Ok(config_file)
+}
+
 fn path_validator(v: String) -> Result<(), String> {
     let broken_paths: Vec<&str> = v
         .split(',')
@@ -99,3 +280,30 @@ fn port_validator(v: String) -> Result<(), String> {
         "The port value needs to be a number >= 1 and <= 65535",
     ))
 }
+
+fn top_labels_validator(v: String) -> Result<(), String> {
+    match v.parse::<usize>() {
+        Ok(top_labels) if top_labels >= 1 => Ok(()),
+        _ => Err(String::from(
+            "The number of labels needs to be a number >= 1",
+        )),
+    }
+}
+
+fn threshold_validator(v: String) -> Result<(), String> {
+    match v.parse::<f32>() {
+        Ok(threshold) if (0.0..=1.0).contains(&threshold) => Ok(()),
+        _ => Err(String::from(
+            "The label threshold needs to be a number >= 0 and <= 1",
+        )),
+    }
+}
+
+fn max_text_size_validator(v: String) -> Result<(), String> {
+    match v.parse::<usize>() {
+        Ok(megabytes) if megabytes >= 1 => Ok(()),
+        _ => Err(String::from(
+            "The maximum text size needs to be a number of megabytes >= 1",
+        )),
+    }
+}
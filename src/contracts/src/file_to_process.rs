@@ -1,41 +1,107 @@
 use blake2b_simd::blake2b;
 use common::tokio::fs;
+use common::tokio::io::AsyncReadExt;
+use common::tracing::instrument;
 use common::tracing::*;
-use common::tracing::{info_span, instrument};
 
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Only the start of bigger files is kept in memory, eg: videos are hashed as they're read
+/// Indexers which need all of a big file either read it from `path` or are skipped, see `Indexer::indexes_truncated_files`
+pub const MAX_CONTENTS_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How much of a file is read at a time while hashing it
+const CHUNK_SIZE: usize = 1024 * 1024;
 
 #[derive(Debug, Clone)]
 pub struct FileToProcess {
     pub path: std::path::PathBuf,
     pub hash: blake2b_simd::Hash,
+    /// At most the first MAX_CONTENTS_SIZE bytes of the file
     pub contents: Vec<u8>,
+    /// The size of the whole file
+    pub size: u64,
 }
 
 impl FileToProcess {
     pub fn path(&self) -> String {
         self.path.to_string_lossy().to_string()
     }
+
+    /// If only the start of the file is in `contents`
+    pub fn is_truncated(&self) -> bool {
+        (self.contents.len() as u64) < self.size
+    }
 }
 
 #[instrument]
 pub async fn new_file_to_process<T: AsRef<Path> + Debug>(path: T) -> FileToProcess {
-    let contents = fs::read(&path).await.unwrap();
+    read_file_to_process(path, MAX_CONTENTS_SIZE).await
+}
+
+/// Doesn't read the file, for files which no indexer reads and are only indexed by their name
+/// Hashing every byte of big files like videos or disk images would be slow, so the hash is made of the path, size and modification time
+/// Copies of these files aren't recognized, each location has its own document
+#[instrument]
+pub async fn new_unread_file_to_process<T: AsRef<Path> + Debug>(path: T) -> FileToProcess {
+    let metadata = fs::metadata(&path).await.unwrap();
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_nanos())
+        .unwrap_or_default();
+
+    let mut state = blake2b_simd::State::new();
+    state.update(path.as_ref().to_string_lossy().as_bytes());
+    state.update(&size.to_le_bytes());
+    state.update(&modified.to_le_bytes());
+    let hash = state.finalize();
+    info!("Hash of the name of the file is: {:?}", hash);
 
-    let span = info_span!("calculating hash");
-    let _enter = span.enter();
-    let hash = calculate_hash(&contents);
-    drop(_enter);
+    FileToProcess {
+        path: PathBuf::from(path.as_ref()),
+        hash,
+        contents: Vec::new(),
+        size,
+    }
+}
+
+/// Hashes the whole file and keeps at most its first `max_contents_size` bytes
+async fn read_file_to_process<T: AsRef<Path> + Debug>(
+    path: T,
+    max_contents_size: u64,
+) -> FileToProcess {
+    let mut file = fs::File::open(&path).await.unwrap();
+    let size = file.metadata().await.unwrap().len();
+
+    let mut state = blake2b_simd::State::new();
+    let mut contents = Vec::with_capacity(size.min(max_contents_size) as usize);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut chunk).await.unwrap();
+        if read == 0 {
+            break;
+        }
+        state.update(&chunk[..read]);
+        let kept = (max_contents_size as usize - contents.len()).min(read);
+        contents.extend_from_slice(&chunk[..kept]);
+    }
+    let hash = state.finalize();
+    info!("Hash of file is: {:?}", hash);
 
     FileToProcess {
         path: PathBuf::from(path.as_ref()),
-        hash: hash,
-        contents: contents,
+        hash,
+        contents,
+        size,
     }
 }
 
-fn calculate_hash(input: &[u8]) -> blake2b_simd::Hash {
+pub fn calculate_hash(input: &[u8]) -> blake2b_simd::Hash {
     let file_hash = blake2b(input);
     info!("Hash of file is: {:?}", file_hash);
     file_hash
@@ -5,10 +5,47 @@ use std::ffi::{OsStr, OsString};
 use crate::file_to_process::FileToProcess;
 
 /// The schema of the information that an Indexer extracts from a file
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct DocumentSchema {
     pub name: String,
     pub body: String,
+    /// Structured information about the file
+    /// Each value is stored in the tantivy field with the same name
+    pub metadata: Vec<(String, MetadataValue)>,
+    /// The parts of the file which results can point to, eg: the sheets of a workbook
+    pub sections: Vec<Section>,
+}
+
+/// A part of a file, eg: a sheet of a workbook or a slide of a presentation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// Where the section is in the file, eg: "Sheet1" or "Slide 3"
+    pub label: String,
+    pub text: String,
+}
+
+/// A structured value extracted from a file, eg: the sender of an email
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataValue {
+    Text(String),
+    I64(i64),
+    F64(f64),
+    /// The components of a facet path, eg: ["RO", "Ilfov"] is stored as /RO/Ilfov
+    Facet(Vec<String>),
+    /// Raw bytes, eg: an image embedding as little endian f32s
+    Bytes(Vec<u8>),
+}
+
+/// A document which lives inside of a file, eg: a single message in an mbox archive
+#[derive(Debug)]
+pub struct EntrySchema {
+    /// Identifies the entry within its file
+    pub entry: String,
+    /// Hash of the raw contents of the entry, used to de-duplicate entries the same way as files
+    pub hash: blake2b_simd::Hash,
+    /// Size in bytes of the raw contents of the entry
+    pub size: u64,
+    pub document: DocumentSchema,
 }
 
 /// Each Indexer needs to be able to say if a file extension is supported and extract information from a supported file
@@ -21,4 +58,16 @@ pub trait Indexer: Send + Sync {
     fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema>;
 
     fn supported_extensions(&self) -> Vec<OsString>;
+
+    /// If the Indexer can index a file of which only the start is in `contents`, see `FileToProcess::is_truncated`
+    /// Most formats need the whole file, eg: a zip has its directory at the end, so by default they're skipped
+    fn indexes_truncated_files(&self) -> bool {
+        false
+    }
+
+    /// Extracts the documents nested inside of a file
+    /// Most files are a single document, so by default there are no entries
+    fn index_entries(&self, _file_to_process: &FileToProcess) -> Result<Vec<EntrySchema>> {
+        Ok(Vec::new())
+    }
 }
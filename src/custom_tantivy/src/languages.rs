@@ -0,0 +1,333 @@
+use common::tantivy::schema::*;
+use common::tantivy::tokenizer::{
+    BoxTokenStream, Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer, TextAnalyzer,
+    Token, TokenStream, Tokenizer,
+};
+use common::tantivy::Index;
+
+use whatlang::Lang;
+
+/// Languages with a stemmer, the body of a document in one of them is also indexed stemmed
+/// Fields are named after the ISO 639-3 code of the language, eg: `body_fra`
+const STEMMED_LANGUAGES: [(Lang, Language); 18] = [
+    (Lang::Ara, Language::Arabic),
+    (Lang::Dan, Language::Danish),
+    (Lang::Nld, Language::Dutch),
+    (Lang::Eng, Language::English),
+    (Lang::Fin, Language::Finnish),
+    (Lang::Fra, Language::French),
+    (Lang::Deu, Language::German),
+    (Lang::Ell, Language::Greek),
+    (Lang::Hun, Language::Hungarian),
+    (Lang::Ita, Language::Italian),
+    (Lang::Nob, Language::Norwegian),
+    (Lang::Por, Language::Portuguese),
+    (Lang::Ron, Language::Romanian),
+    (Lang::Rus, Language::Russian),
+    (Lang::Spa, Language::Spanish),
+    (Lang::Swe, Language::Swedish),
+    (Lang::Tam, Language::Tamil),
+    (Lang::Tur, Language::Turkish),
+];
+
+/// Chinese, Japanese and Korean aren't written with spaces between words, their body is indexed as characters and pairs of characters
+const CJK_LANGUAGES: [Lang; 3] = [Lang::Cmn, Lang::Jpn, Lang::Kor];
+
+/// Suffix of the field with the body of CJK documents
+const CJK: &str = "cjk";
+
+/// Suffix of the field with the body of documents in any other language, or whose language wasn't detected
+/// It's analyzed like `body`, which isn't searched by queries so a body only counts once, in a single field
+const OTHER: &str = "other";
+
+/// The language is detected from the start of the body, which is enough and keeps big files fast
+const DETECTION_LENGTH: usize = 4096;
+
+/// Words longer than this are skipped, like the default tokenizer does
+const MAX_WORD_LENGTH: usize = 40;
+
+/// The field with the body analyzed for a language, eg: `body_eng` or `body_cjk`
+fn body_field_name(suffix: &str) -> String {
+    format!("body_{}", suffix)
+}
+
+fn tokenizer_name(suffix: &str) -> String {
+    format!("podium_{}", suffix)
+}
+
+/// Adds a field for the body of every stemmed language, one for CJK documents and one for the other documents
+/// They're only indexed, the body itself is stored once in `body`
+pub fn add_language_fields(schema_builder: &mut SchemaBuilder) {
+    let suffixes = STEMMED_LANGUAGES
+        .iter()
+        .map(|(lang, _)| lang.code())
+        .chain(std::iter::once(CJK));
+    for suffix in suffixes {
+        let indexing = TextFieldIndexing::default()
+            .set_tokenizer(&tokenizer_name(suffix))
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+        schema_builder.add_text_field(
+            &body_field_name(suffix),
+            TextOptions::default().set_indexing_options(indexing),
+        );
+    }
+    schema_builder.add_text_field(&body_field_name(OTHER), TEXT);
+}
+
+/// Registers the analyzers of the language fields, has to be done every time the index is opened
+pub fn register_tokenizers(index: &Index) {
+    let tokenizers = index.tokenizers();
+    for (lang, language) in STEMMED_LANGUAGES.iter() {
+        tokenizers.register(
+            &tokenizer_name(lang.code()),
+            TextAnalyzer::from(SimpleTokenizer)
+                .filter(RemoveLongFilter::limit(MAX_WORD_LENGTH))
+                .filter(LowerCaser)
+                .filter(Stemmer::new(*language)),
+        );
+    }
+    tokenizers.register(
+        &tokenizer_name(CJK),
+        TextAnalyzer::from(CjkBigramTokenizer)
+            .filter(RemoveLongFilter::limit(MAX_WORD_LENGTH))
+            .filter(LowerCaser),
+    );
+}
+
+/// The language the text is written in, detected from its start
+/// Returns None when the text is too short or mixed to tell
+pub fn detect_language(text: &str) -> Option<Lang> {
+    let end = text
+        .char_indices()
+        .nth(DETECTION_LENGTH)
+        .map_or(text.len(), |(index, _)| index);
+    whatlang::detect(&text[..end])
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang())
+}
+
+/// The field the body of a document in this language is analyzed in
+/// Languages without an analyzer and documents whose language wasn't detected use `body_other`
+pub fn language_body_field(schema: &Schema, lang: Option<Lang>) -> Option<Field> {
+    let suffix = match lang {
+        Some(lang) if CJK_LANGUAGES.contains(&lang) => CJK,
+        Some(lang) => STEMMED_LANGUAGES
+            .iter()
+            .find(|(stemmed_lang, _)| *stemmed_lang == lang)
+            .map_or(OTHER, |(stemmed_lang, _)| stemmed_lang.code()),
+        None => OTHER,
+    };
+    schema.get_field(&body_field_name(suffix))
+}
+
+/// Every language field, queries are analyzed for all of them so they match documents in any language
+pub fn language_body_fields(schema: &Schema) -> Vec<Field> {
+    STEMMED_LANGUAGES
+        .iter()
+        .map(|(lang, _)| lang.code())
+        .chain(vec![CJK, OTHER])
+        .filter_map(|suffix| schema.get_field(&body_field_name(suffix)))
+        .collect()
+}
+
+/// Splits runs of CJK characters into single characters and overlapping pairs, eg: 東京都 into 東, 東京, 京, 京都 and 都
+/// A pair is at the same position as its first character, so a query of a single character or of a phrase matches
+/// Other words are kept whole, like the default tokenizer does
+#[derive(Clone)]
+pub struct CjkBigramTokenizer;
+
+impl Tokenizer for CjkBigramTokenizer {
+    fn token_stream<'a>(&self, text: &'a str) -> BoxTokenStream<'a> {
+        let mut tokens = Vec::new();
+        let mut add_token = |offset_from: usize, offset_to: usize, position: usize| {
+            tokens.push(Token {
+                offset_from,
+                offset_to,
+                position,
+                text: text[offset_from..offset_to].to_string(),
+                position_length: 1,
+            });
+        };
+
+        let mut position = 0;
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if is_cjk(c) {
+                add_token(start, start + c.len_utf8(), position);
+                if let Some(&(next_start, next)) = chars.peek().filter(|(_, next)| is_cjk(*next)) {
+                    add_token(start, next_start + next.len_utf8(), position);
+                }
+                position += 1;
+            } else if c.is_alphanumeric() {
+                let mut end = start + c.len_utf8();
+                while let Some(&(next_start, next)) = chars.peek() {
+                    if !next.is_alphanumeric() || is_cjk(next) {
+                        break;
+                    }
+                    end = next_start + next.len_utf8();
+                    chars.next();
+                }
+                add_token(start, end, position);
+                position += 1;
+            }
+        }
+
+        BoxTokenStream::from(CjkBigramTokenStream {
+            tokens,
+            index: None,
+        })
+    }
+}
+
+struct CjkBigramTokenStream {
+    tokens: Vec<Token>,
+    index: Option<usize>,
+}
+
+impl TokenStream for CjkBigramTokenStream {
+    fn advance(&mut self) -> bool {
+        let index = self.index.map_or(0, |index| index + 1);
+        self.index = Some(index);
+        index < self.tokens.len()
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.index.unwrap()]
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.tokens[self.index.unwrap()]
+    }
+}
+
+/// Han, kana and hangul characters
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // Hiragana and katakana
+        | '\u{3400}'..='\u{4DBF}' // CJK unified ideographs extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK unified ideographs
+        | '\u{AC00}'..='\u{D7AF}' // Hangul syllables
+        | '\u{F900}'..='\u{FAFF}' // CJK compatibility ideographs
+        | '\u{20000}'..='\u{2FA1F}' // CJK unified ideographs extensions B to F
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tantivy::collector::Count;
+    use common::tantivy::query::QueryParser;
+
+    fn tokens(analyzer: &TextAnalyzer, text: &str) -> Vec<String> {
+        let mut token_stream = analyzer.token_stream(text);
+        let mut tokens = Vec::new();
+        while token_stream.advance() {
+            tokens.push(token_stream.token().text.clone());
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_cjk_runs_are_split_into_characters_and_pairs() {
+        let analyzer = TextAnalyzer::from(CjkBigramTokenizer);
+        assert_eq!(
+            tokens(&analyzer, "東京都に猫 Tokyo cats"),
+            vec![
+                "東", "東京", "京", "京都", "都", "都に", "に", "に猫", "猫", "Tokyo", "cats"
+            ]
+        );
+        assert_eq!(tokens(&analyzer, "猫, 犬"), vec!["猫", "犬"]);
+
+        let mut token_stream = analyzer.token_stream("東京 cats");
+        let mut positions = Vec::new();
+        while token_stream.advance() {
+            positions.push(token_stream.token().position);
+        }
+        assert_eq!(positions, vec![0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_cjk_queries_of_one_character_and_of_phrases_match() {
+        let mut schema_builder = Schema::builder();
+        add_language_fields(&mut schema_builder);
+        let schema = schema_builder.build();
+        let body_cjk = language_body_field(&schema, Some(Lang::Jpn)).unwrap();
+        let index = Index::create_in_ram(schema);
+        register_tokenizers(&index);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        let mut doc = Document::default();
+        doc.add_text(body_cjk, "東京都に猫がいる");
+        index_writer.add_document(doc);
+        index_writer.commit().unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let query_parser = QueryParser::for_index(&index, vec![body_cjk]);
+        let count = |query: &str| {
+            searcher
+                .search(&query_parser.parse_query(query).unwrap(), &Count)
+                .unwrap()
+        };
+        assert_eq!(count("猫"), 1);
+        assert_eq!(count("京都"), 1);
+        assert_eq!(count("東京都"), 1);
+        assert_eq!(count("犬"), 0);
+        assert_eq!(count("\"都東\""), 0);
+    }
+
+    #[test]
+    fn test_stemmed_analyzers() {
+        let index = Index::create_in_ram(Schema::builder().build());
+        register_tokenizers(&index);
+
+        let english = index.tokenizers().get(&tokenizer_name("eng")).unwrap();
+        assert_eq!(tokens(&english, "Cats running"), vec!["cat", "run"]);
+
+        let french = index.tokenizers().get(&tokenizer_name("fra")).unwrap();
+        assert_eq!(tokens(&french, "les chats"), vec!["le", "chat"]);
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(
+            detect_language(
+                "Le chat dort sur le canapé pendant que les enfants jouent dans le jardin."
+            ),
+            Some(Lang::Fra)
+        );
+        assert_eq!(
+            detect_language(
+                "Die Katze schläft auf dem Sofa, während die Kinder im Garten spielen."
+            ),
+            Some(Lang::Deu)
+        );
+        assert_eq!(
+            detect_language("吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。"),
+            Some(Lang::Jpn)
+        );
+    }
+
+    #[test]
+    fn test_language_body_fields() {
+        let mut schema_builder = Schema::builder();
+        add_language_fields(&mut schema_builder);
+        let schema = schema_builder.build();
+
+        assert_eq!(
+            language_body_field(&schema, Some(Lang::Fra)),
+            schema.get_field("body_fra")
+        );
+        assert_eq!(
+            language_body_field(&schema, Some(Lang::Kor)),
+            schema.get_field("body_cjk")
+        );
+        assert_eq!(
+            language_body_field(&schema, Some(Lang::Vie)),
+            schema.get_field("body_other")
+        );
+        assert_eq!(
+            language_body_field(&schema, None),
+            schema.get_field("body_other")
+        );
+        assert_eq!(language_body_fields(&schema).len(), 20);
+    }
+}
@@ -1,3 +1,6 @@
+pub mod languages;
 pub mod path_facet_convert;
+pub mod simhash;
+pub mod spelling;
 pub mod utils;
 pub mod wrapper;
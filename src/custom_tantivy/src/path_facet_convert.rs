@@ -1,6 +1,61 @@
 use common::tantivy::schema::*;
 use std::path::{Path, PathBuf};
 
+/// Separates the location of a file from the entry nested inside of it
+/// Eg: the 3rd message of an mbox archive is found at `/home/mail/inbox.mbox!/3`
+pub const ENTRY_SEPARATOR: &str = "!";
+
+/// Location facet of an entry nested inside of the file at `file_facet_value`
+pub fn entry_facet_value(file_facet_value: &str, entry: &str) -> String {
+    format!("{}{}/{}", file_facet_value, ENTRY_SEPARATOR, entry)
+}
+
+/// Parent facet of all the entries nested inside of the file at `file_facet_value`
+/// Facets are indexed with all their ancestors, so this matches every entry of the file
+pub fn entries_facet_value(file_facet_value: &str) -> String {
+    format!("{}{}", file_facet_value, ENTRY_SEPARATOR)
+}
+
+/// Splits a location inside of a file into the file on disk and the entry, eg: `/home/mail/inbox.mbox` and `!/3`
+/// Returns None if the location isn't inside of a file which exists
+fn split_entry_location(location: &str) -> Option<(&Path, &str)> {
+    let separator = format!("{}/", ENTRY_SEPARATOR);
+    location
+        .match_indices(&separator)
+        .map(|(index, _)| index)
+        .find(|&index| Path::new(&location[..index]).is_file())
+        .map(|index| (Path::new(&location[..index]), &location[index..]))
+}
+
+/// The file on disk a location is in, eg: `/home/mail/inbox.mbox` for `/home/mail/inbox.mbox!/3`
+/// A location which isn't inside of a file is returned as is
+pub fn containing_file(location: &Path) -> &Path {
+    location
+        .to_str()
+        .and_then(split_entry_location)
+        .map(|(file, _)| file)
+        .unwrap_or(location)
+}
+
+/// Canonicalizes a location which may point inside of a file, eg: `./archive.zip!/inner/doc.csv`
+/// Only the part of the location which exists on disk is canonicalized, the entry is kept as is
+fn canonicalize_location(path: &Path) -> PathBuf {
+    if let Ok(canonical_path) = path.canonicalize() {
+        return canonical_path;
+    }
+
+    if let Some((file, entry)) = split_entry_location(path.to_str().unwrap()) {
+        if let Ok(canonical_file) = file.canonicalize() {
+            let mut canonical_location = canonical_file.into_os_string();
+            canonical_location.push(entry);
+            return PathBuf::from(canonical_location);
+        }
+    }
+
+    // The file was deleted, its location is kept as it was given
+    path.to_path_buf()
+}
+
 /// Converts to/from Facet/PathBuf
 pub trait TantivyConvert {
     fn to_facet_value(&self) -> String;
@@ -10,8 +65,7 @@ pub trait TantivyConvert {
 impl TantivyConvert for Path {
     #[cfg(target_os = "windows")]
     fn to_facet_value(&self) -> String {
-        self.canonicalize()
-            .unwrap()
+        canonicalize_location(self)
             .to_str()
             .unwrap()
             .replace("\\", "/")
@@ -19,7 +73,7 @@ impl TantivyConvert for Path {
 
     #[cfg(not(target_os = "windows"))]
     fn to_facet_value(&self) -> String {
-        String::from(self.canonicalize().unwrap().to_str().unwrap())
+        String::from(canonicalize_location(self).to_str().unwrap())
     }
 
     #[cfg(target_os = "windows")]
@@ -63,4 +117,64 @@ mod test {
 
         File::open(dir_from_facet).unwrap();
     }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn test_entry_facet_conversion() {
+        use super::*;
+
+        let entry_facet_string = entry_facet_value("/home/mail/inbox.mbox", "3");
+        assert_eq!(entry_facet_string, "/home/mail/inbox.mbox!/3");
+
+        let facet = Facet::from_text(&entry_facet_string).unwrap();
+        let entries_facet =
+            Facet::from_text(&entries_facet_value("/home/mail/inbox.mbox")).unwrap();
+        assert!(entries_facet.is_prefix_of(&facet));
+
+        assert_eq!(
+            Path::from_facet_value(&facet),
+            PathBuf::from("/home/mail/inbox.mbox!/3")
+        );
+    }
+
+    #[test]
+    fn test_archive_entry_facet_conversion() {
+        use super::*;
+        use std::env;
+
+        let mut archive_path = env::current_dir().unwrap();
+        archive_path.push("../../test_files/Animals.zip");
+        let entry_path = PathBuf::from(format!("{}!/animals/data.csv", archive_path.display()));
+
+        let entry_facet_string = entry_path.to_facet_value();
+        assert!(entry_facet_string.ends_with("test_files/Animals.zip!/animals/data.csv"));
+        assert!(!entry_facet_string.contains(".."));
+
+        let facet = Facet::from_text(&entry_facet_string).unwrap();
+        let entry_from_facet = Path::from_facet_value(&facet);
+
+        assert_eq!(entry_from_facet.to_facet_value(), entry_facet_string);
+    }
+
+    #[test]
+    fn test_containing_file() {
+        use super::*;
+        use std::env;
+
+        let mut archive_path = env::current_dir().unwrap();
+        archive_path.push("../../test_files/Animals.zip");
+        let entry_path = PathBuf::from(format!("{}!/animals/data.csv", archive_path.display()));
+
+        assert_eq!(containing_file(&entry_path), archive_path);
+        assert_eq!(containing_file(&archive_path), archive_path);
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn test_deleted_path_facet_conversion() {
+        use super::*;
+
+        let deleted_path = Path::new("/a/deleted/file.txt");
+        assert_eq!(deleted_path.to_facet_value(), "/a/deleted/file.txt");
+    }
 }
@@ -0,0 +1,87 @@
+/// How many words are hashed together, so reordered sentences don't look the same
+const SHINGLE_SIZE: usize = 3;
+
+/// Shorter texts have too few shingles for their hashes to be meaningful
+const MIN_WORDS: usize = 20;
+
+/// A 64 bit SimHash of the words of a text, texts which differ trivially have hashes a few bits apart
+/// Each bit is the majority vote of that bit in the hashes of every shingle of the text
+pub fn simhash(text: &str) -> Option<u64> {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<String>>();
+    if words.len() < MIN_WORDS {
+        return None;
+    }
+
+    let mut votes = [0i64; 64];
+    for shingle in words.windows(SHINGLE_SIZE) {
+        let hash = shingle_hash(&shingle.join(" "));
+        for (bit, vote) in votes.iter_mut().enumerate() {
+            if hash & (1 << bit) != 0 {
+                *vote += 1;
+            } else {
+                *vote -= 1;
+            }
+        }
+    }
+
+    Some(
+        votes
+            .iter()
+            .enumerate()
+            .filter(|(_, vote)| **vote > 0)
+            .fold(0, |hash, (bit, _)| hash | (1 << bit)),
+    )
+}
+
+/// A hash which doesn't change between runs, so stored SimHashes stay comparable
+fn shingle_hash(shingle: &str) -> u64 {
+    let hash = blake2b_simd::Params::new()
+        .hash_length(8)
+        .hash(shingle.as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(hash.as_bytes());
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Cats are small carnivorous mammals that people have kept as pets for thousands of years. \
+        They sleep for most of the day, groom themselves often and hunt small animals such as mice and birds. \
+        Domestic cats communicate with meows, purrs and body language, and they recognise the voices of their owners. \
+        A healthy cat needs fresh water, a balanced diet and regular visits to the vet for vaccinations. \
+        Kittens learn to hunt by playing with their littermates and copying their mother.";
+
+    #[test]
+    fn test_similar_texts_have_close_hashes() {
+        let edited = TEXT.replace("thousands", "many thousands");
+        let distance = (simhash(TEXT).unwrap() ^ simhash(&edited).unwrap()).count_ones();
+        assert!(distance <= 8, "distance was {}", distance);
+    }
+
+    #[test]
+    fn test_different_texts_have_distant_hashes() {
+        let other = "Spreadsheets store numbers in cells arranged into rows and columns, \
+            formulas combine the cells and charts summarize them for the quarterly budget meeting.";
+        let distance = (simhash(TEXT).unwrap() ^ simhash(other).unwrap()).count_ones();
+        assert!(distance > 16, "distance was {}", distance);
+    }
+
+    #[test]
+    fn test_case_and_punctuation_are_ignored() {
+        assert_eq!(
+            simhash(TEXT),
+            simhash(&TEXT.to_uppercase().replace('.', "!"))
+        );
+    }
+
+    #[test]
+    fn test_short_texts_have_no_hash() {
+        assert_eq!(simhash("Cats are great"), None);
+    }
+}
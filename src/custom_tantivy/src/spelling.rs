@@ -0,0 +1,149 @@
+use common::anyhow::Result;
+use common::tantivy::schema::Field;
+use common::tantivy::Searcher;
+
+use levenshtein_automata::{Distance, LevenshteinAutomatonBuilder, DFA, SINK_STATE};
+use tantivy_fst::Automaton;
+
+use std::collections::HashMap;
+
+/// Words this long can have two typos, shorter ones only one, or too many unrelated words are close
+const TWO_TYPOS_MIN_LENGTH: usize = 6;
+
+/// Lets the term dictionary only visit the words which are close to the misspelled one
+struct LevenshteinDfa(DFA);
+
+impl Automaton for LevenshteinDfa {
+    type State = u32;
+
+    fn start(&self) -> Self::State {
+        self.0.initial_state()
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        matches!(self.0.distance(*state), Distance::Exact(_))
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        *state != SINK_STATE
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        self.0.transition(*state, byte)
+    }
+}
+
+/// The query with every word which isn't in the index replaced by the closest indexed word
+/// The words have to be tokenized the way the fields were, returns None if every word is indexed or has no close word
+pub fn did_you_mean(
+    searcher: &Searcher,
+    fields: &[Field],
+    words: &[String],
+) -> Result<Option<String>> {
+    let mut corrected = false;
+    let mut corrected_words = Vec::with_capacity(words.len());
+    for word in words {
+        let doc_freqs = close_words(searcher, fields, word)?;
+        match closest_word(word, doc_freqs) {
+            Some(correction) if &correction != word => {
+                corrected = true;
+                corrected_words.push(correction);
+            }
+            _ => corrected_words.push(word.clone()),
+        }
+    }
+
+    Ok(if corrected {
+        Some(corrected_words.join(" "))
+    } else {
+        None
+    })
+}
+
+/// How many documents of the index contain each word close to this one, the word itself included
+fn close_words(searcher: &Searcher, fields: &[Field], word: &str) -> Result<HashMap<String, u32>> {
+    let max_distance = if word.chars().count() >= TWO_TYPOS_MIN_LENGTH {
+        2
+    } else {
+        1
+    };
+    let automaton =
+        LevenshteinDfa(LevenshteinAutomatonBuilder::new(max_distance, true).build_dfa(word));
+
+    let mut doc_freqs = HashMap::new();
+    for segment_reader in searcher.segment_readers() {
+        for field in fields {
+            let inverted_index = segment_reader.inverted_index(*field)?;
+            let mut terms = inverted_index.terms().search(&automaton).into_stream()?;
+            while terms.advance() {
+                if let Ok(term) = std::str::from_utf8(terms.key()) {
+                    *doc_freqs.entry(term.to_string()).or_insert(0) += terms.value().doc_freq;
+                }
+            }
+        }
+    }
+    Ok(doc_freqs)
+}
+
+/// The word itself if it's indexed, or else the indexed word with the fewest edits and then in the most documents
+fn closest_word(word: &str, doc_freqs: HashMap<String, u32>) -> Option<String> {
+    if doc_freqs.contains_key(word) {
+        return Some(word.to_string());
+    }
+
+    let dfa = LevenshteinAutomatonBuilder::new(2, true).build_dfa(word);
+    doc_freqs
+        .into_iter()
+        .map(|(candidate, doc_freq)| {
+            let distance = match dfa.eval(&candidate) {
+                Distance::Exact(distance) => distance,
+                Distance::AtLeast(distance) => distance,
+            };
+            (distance, doc_freq, candidate)
+        })
+        .min_by(|(a_distance, a_freq, a), (b_distance, b_freq, b)| {
+            a_distance
+                .cmp(b_distance)
+                .then(b_freq.cmp(a_freq))
+                .then(a.cmp(b))
+        })
+        .map(|(_, _, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc_freqs(words: &[(&str, u32)]) -> HashMap<String, u32> {
+        words
+            .iter()
+            .map(|(word, doc_freq)| (word.to_string(), *doc_freq))
+            .collect()
+    }
+
+    #[test]
+    fn test_indexed_words_are_kept() {
+        let closest = closest_word("cats", doc_freqs(&[("cats", 1), ("cat", 20)]));
+        assert_eq!(closest, Some(String::from("cats")));
+    }
+
+    #[test]
+    fn test_fewest_edits_win_over_most_documents() {
+        let closest = closest_word(
+            "spredsheet",
+            doc_freqs(&[("spreadsheets", 50), ("spreadsheet", 2)]),
+        );
+        assert_eq!(closest, Some(String::from("spreadsheet")));
+    }
+
+    #[test]
+    fn test_most_documents_win_between_equal_edits() {
+        let closest = closest_word("cst", doc_freqs(&[("cat", 3), ("cut", 9)]));
+        assert_eq!(closest, Some(String::from("cut")));
+    }
+
+    #[test]
+    fn test_no_close_words() {
+        assert_eq!(closest_word("cats", HashMap::new()), None);
+    }
+}
@@ -1,5 +1,10 @@
 use common::tantivy::schema::*;
 
+use crate::languages::add_language_fields;
+use crate::path_facet_convert::TantivyConvert;
+
+use std::path::Path;
+
 pub fn destructure_schema(schema: &Schema) -> (Field, Field, Field, Field) {
     (
         schema.get_field("title").unwrap(),
@@ -9,6 +14,66 @@ pub fn destructure_schema(schema: &Schema) -> (Field, Field, Field, Field) {
     )
 }
 
+/// Facet fields for the place names of a location, from the most to the least specific
+pub const PLACE_FIELDS: [&str; 4] = ["city", "admin2", "admin1", "country"];
+
+/// How many bytes of a file name are used to sort by name
+const NAME_KEY_LENGTH: usize = 8;
+
+/// The first bytes of the lowercased file name as a big endian number, so sorting the numbers sorts the names
+/// Names which start with the same bytes have the same key and need to be compared whole
+pub fn name_key(name: &str) -> u64 {
+    let mut key = [0; NAME_KEY_LENGTH];
+    for (key_byte, name_byte) in key.iter_mut().zip(name.to_lowercase().bytes()) {
+        *key_byte = name_byte;
+    }
+    u64::from_be_bytes(key)
+}
+
+/// Tags are matched regardless of case and surrounding spaces, each tag is kept once
+pub fn normalize_tags<I, S>(tags: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.as_ref().trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Indexes the file name of every location of the document
+/// The file names are only indexed, so they're added again whenever a document is rebuilt from its stored fields
+pub fn add_filenames(doc: &mut Document, schema: &Schema) {
+    let (_, _, location, _) = destructure_schema(schema);
+    let filenames = doc
+        .get_all(location)
+        .filter_map(|value| match value {
+            Value::Facet(facet) => Path::from_facet_value(facet)
+                .file_name()
+                .map(|filename| filename.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<String>>();
+    let filename_field = schema.get_field("filename").unwrap();
+    for filename in filenames {
+        doc.add_text(filename_field, &filename);
+    }
+}
+
+/// The facet of a place name, places are matched regardless of case, eg: `otopeni` finds photos taken in Otopeni
+pub fn place_facet(place: &str) -> Facet {
+    Facet::from_path(vec![place.trim().to_lowercase()])
+}
+
+/// Version of the fields `build_schema` makes and of how they're filled, has to be bumped every time they change
+/// An index built with another version is rebuilt from scratch, tantivy can't open it
+pub const SCHEMA_VERSION: u32 = 7;
+
 /// Builds the tantivy schema
 pub fn build_schema() -> Schema {
     let mut schema_builder = Schema::builder();
@@ -23,5 +88,143 @@ pub fn build_schema() -> Schema {
 
     schema_builder.add_text_field("body", TEXT | STORED);
 
+    // The file name of every location, so files are suggested by their name as it's typed, see `add_filenames`
+    schema_builder.add_text_field("filename", TEXT);
+
+    // Metadata fields, filled in by the indexers which know about them
+    schema_builder.add_text_field("from", TEXT | STORED);
+
+    schema_builder.add_text_field("to", TEXT | STORED);
+
+    schema_builder.add_text_field("cc", TEXT | STORED);
+
+    schema_builder.add_i64_field("date", INDEXED | STORED | FAST);
+
+    // When the file was last modified, in seconds since the unix epoch
+    schema_builder.add_i64_field("modified", INDEXED | STORED | FAST);
+
+    // In bytes
+    schema_builder.add_i64_field("size", INDEXED | STORED | FAST);
+
+    // Text can't be a fast field, so the start of the file name is, see `name_key`
+    schema_builder.add_u64_field("name_key", FAST);
+
+    schema_builder.add_text_field("columns", TEXT | STORED);
+
+    schema_builder.add_text_field("headings", TEXT | STORED);
+
+    schema_builder.add_text_field("author", TEXT | STORED);
+
+    // Photo metadata, the capture time is stored in `date`
+    schema_builder.add_text_field("camera_make", TEXT | STORED);
+
+    schema_builder.add_text_field("camera_model", TEXT | STORED);
+
+    schema_builder.add_text_field("lens", TEXT | STORED);
+
+    schema_builder.add_i64_field("orientation", INDEXED | STORED);
+
+    schema_builder.add_i64_field("width", INDEXED | STORED | FAST);
+
+    schema_builder.add_i64_field("height", INDEXED | STORED | FAST);
+
+    schema_builder.add_f64_field("latitude", INDEXED | STORED | FAST);
+
+    schema_builder.add_f64_field("longitude", INDEXED | STORED | FAST);
+
+    // Audio and video tags, the resolution of a video is stored in `width` and `height`
+    schema_builder.add_text_field("media_title", TEXT | STORED);
+
+    schema_builder.add_text_field("artist", TEXT | STORED);
+
+    schema_builder.add_text_field("album", TEXT | STORED);
+
+    schema_builder.add_i64_field("year", INDEXED | STORED | FAST);
+
+    // In seconds
+    schema_builder.add_i64_field("duration", INDEXED | STORED | FAST);
+
+    schema_builder.add_text_field("codec", TEXT | STORED);
+
+    // What an image classification model saw in an image
+    // Each label is stored at the same position as its confidence
+    schema_builder.add_text_field("labels", TEXT | STORED);
+
+    schema_builder.add_f64_field("label_scores", STORED);
+
+    // How an image looks, used to find similar images
+    // The perceptual hash is a u64 stored as its i64 bits, the embedding is little endian f32s
+    // Both are fast fields so images can be compared without loading their documents
+    schema_builder.add_i64_field("perceptual_hash", INDEXED | STORED | FAST);
+
+    schema_builder.add_bytes_field("image_embedding", STORED | FAST);
+
+    // SimHash of the body, a u64 stored as its i64 bits, used to find documents which are nearly the same
+    schema_builder.add_i64_field("simhash", INDEXED | STORED | FAST);
+
+    // Where a photo was taken, as named by the reverse geocoder
+    // Each level is its own facet so places can be searched without knowing the regions above them
+    for place_field in PLACE_FIELDS.iter() {
+        schema_builder.add_facet_field(
+            place_field,
+            FacetOptions::default().set_indexed().set_stored(),
+        );
+    }
+
+    // Sections of a file, each label is stored at the same position as its text
+    // The text is already part of the body so it only needs to be stored
+    // Labels are indexed so sections can be searched by name, eg: a sheet name
+    schema_builder.add_text_field("section_label", TEXT | STORED);
+
+    schema_builder.add_text_field("section_text", STORED);
+
+    // The language the body is written in, as its ISO 639-3 code, eg: "eng"
+    // The body is also indexed in the field of its language, stemmed or split into CJK characters and pairs
+    // Documents in other languages or without one have it in `body_other`
+    schema_builder.add_text_field("language", STRING | STORED);
+
+    add_language_fields(&mut schema_builder);
+
+    // 1 when the file was too big to be indexed whole and only its start is searched
+    schema_builder.add_i64_field("truncated", INDEXED | STORED);
+
+    // Tags file managers stored in the `user.xdg.tags` extended attribute of a file
+    // Tags and notes added through podium are kept next to the index instead, so they survive reindexing
+    schema_builder.add_text_field("tag", STRING | STORED);
+
     schema_builder.build()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_keys_sort_like_names() {
+        let mut names = vec!["report.pdf", "Cats.txt", "b", "cat.png", "budget 2021.xlsx"];
+        names.sort_by_key(|name| name_key(name));
+        assert_eq!(
+            names,
+            vec!["b", "budget 2021.xlsx", "cat.png", "Cats.txt", "report.pdf"]
+        );
+    }
+
+    #[test]
+    fn test_names_with_the_same_start_have_the_same_key() {
+        assert_eq!(name_key("Invoice-2021.pdf"), name_key("invoice-2020.pdf"));
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        assert_eq!(
+            normalize_tags(vec![" Work", "to read", "", "work ", "TO READ"]),
+            vec!["work", "to read"]
+        );
+    }
+
+    #[test]
+    fn test_place_facet() {
+        assert_eq!(place_facet("Otopeni"), place_facet(" otopeni"));
+        assert_eq!(place_facet("New York").to_string(), "/new york");
+    }
+}
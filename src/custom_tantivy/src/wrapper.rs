@@ -1,25 +1,43 @@
 use blake2b_simd;
 use common::tantivy;
-use common::tantivy::collector::{Count, TopDocs};
+use common::tantivy::collector::{Count, DocSetCollector, TopDocs};
 use common::tantivy::query::TermQuery;
 use common::tantivy::schema::*;
 use common::tantivy::DocAddress;
 use common::tantivy::{IndexReader, IndexWriter};
 use common::tracing::{error, info, instrument};
 
+use crate::languages::{detect_language, language_body_field};
 use crate::path_facet_convert::*;
-use crate::utils::destructure_schema;
+use crate::simhash::simhash;
+use crate::utils::{
+    add_filenames, destructure_schema, name_key, normalize_tags, place_facet, PLACE_FIELDS,
+};
 use contracts::file_to_process::FileToProcess;
+use contracts::indexer::{DocumentSchema, EntrySchema, MetadataValue, Section};
 use indexers::*;
 
 use common::async_trait::async_trait;
 
+#[cfg(feature = "semantic")]
+use embeddings::VectorStore;
+
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
+#[cfg(feature = "semantic")]
+use std::sync::Arc;
+use std::sync::Mutex;
 
 pub struct TantivyWrapper {
     pub index_reader: IndexReader,
     pub index_writer: IndexWriter,
     pub schema: Schema,
+    /// Documents added since the last commit, by hash
+    /// The reader only sees committed documents, so copies of a file in the same commit are found here
+    pending_docs: Mutex<HashMap<String, Document>>,
+    /// Where the embeddings of new documents are stored
+    #[cfg(feature = "semantic")]
+    pub vector_store: Option<Arc<VectorStore>>,
 }
 
 impl TantivyWrapper {
@@ -28,46 +46,195 @@ impl TantivyWrapper {
             index_reader,
             index_writer,
             schema,
+            pending_docs: Mutex::new(HashMap::new()),
+            #[cfg(feature = "semantic")]
+            vector_store: None,
+        }
+    }
+
+    /// Commits the added and deleted documents and reloads the reader, so they're found by the next lookups
+    pub fn commit(&mut self) -> tantivy::Result<()> {
+        self.index_writer.commit()?;
+        self.index_reader.reload()?;
+        self.pending_docs.get_mut().unwrap().clear();
+        Ok(())
+    }
+
+    /// Adds a document, replacing the document with the same hash
+    fn add_doc(&self, hash: &str, doc: Document) {
+        let (_title, hash_field, _location, _body) = destructure_schema(&self.schema);
+        self.index_writer
+            .delete_term(Term::from_field_text(hash_field, hash));
+        self.index_writer.add_document(doc.clone());
+        self.pending_docs
+            .lock()
+            .unwrap()
+            .insert(hash.to_string(), doc);
+    }
+
+    /// The latest document with this hash, including the ones which aren't committed yet
+    fn indexed_doc_by_hash(&self, hash: &str) -> Option<Document> {
+        if let Some(doc) = self.pending_docs.lock().unwrap().get(hash) {
+            return Some(doc.clone());
         }
+        let (_title, hash_field, _location, _body) = destructure_schema(&self.schema);
+        let doc_address = self.get_doc_by_hash(hash_field, hash)?;
+        Some(self.index_reader.searcher().doc(doc_address).unwrap())
+    }
+
+    #[cfg(feature = "semantic")]
+    pub fn with_vector_store(mut self, vector_store: Arc<VectorStore>) -> Self {
+        self.vector_store = Some(vector_store);
+        self
     }
 
+    /// Embeds the title and body of a document which doesn't have a vector yet
+    /// Does nothing when there's no vector store or no embedding model
+    #[cfg(feature = "semantic")]
+    fn store_embedding(&self, hash: &str, title: &str, body: &str) {
+        let (vector_store, embedder) = match (&self.vector_store, embeddings::embedder()) {
+            (Some(vector_store), Some(embedder)) => (vector_store, embedder),
+            _ => return,
+        };
+        if vector_store.contains(hash) {
+            return;
+        }
+
+        let embedding = embedder
+            .embed(&format!("{} {}", title, body))
+            .and_then(|vector| vector_store.insert(hash, vector));
+        if let Err(e) = embedding {
+            error!("Failed to embed document {:?}: {:?}", hash, e);
+        }
+    }
+
+    /// Adds this location to the document of a file which has been seen before, eg: a copy of it
+    /// Returns the document when the file is already indexed, None when it has to be indexed
     #[instrument(skip(self, hash))]
     pub fn update_doc_by_hash(
         &self,
-        entry_path: &Path,
+        location_facet: &str,
         hash: &blake2b_simd::Hash,
     ) -> Option<Document> {
-        let searcher = self.index_reader.searcher();
-        let location_facet = &entry_path.to_facet_value();
-        let (_title, hash_field, location, _body) = destructure_schema(&self.schema);
-        if let Some(doc_address) = self.get_doc_by_hash(hash_field, hash.to_hex().as_str()) {
-            info!("We've seen this file before! {:?}", location_facet);
-            let mut retrieved_doc = searcher.doc(doc_address).unwrap();
-            let is_found = !retrieved_doc
-                .get_all(location)
-                .any(|value| value == &Value::from(Facet::from_text(location_facet).unwrap()));
-            // .contains(&&Value::from(Facet::from_text(location_facet).unwrap()));
-            info!(
-                "Is this current file's location already in the document? {:?}",
-                is_found
+        self.add_location(hash.to_hex().as_str(), location_facet)
+    }
+
+    /// Adds this location to the document with this hash, if there's one
+    fn add_location(&self, hash: &str, location_facet: &str) -> Option<Document> {
+        let (_title, _hash_field, location, _body) = destructure_schema(&self.schema);
+        let retrieved_doc = self.indexed_doc_by_hash(hash)?;
+        info!("We've seen this file before! {:?}", location_facet);
+        let location_value = Value::from(Facet::from_text(location_facet).unwrap());
+        if retrieved_doc
+            .get_all(location)
+            .any(|value| value == &location_value)
+        {
+            // This location of the file is already stored in the document
+            return Some(retrieved_doc);
+        }
+
+        // The location is added by replacing the document, stored documents can't be changed
+        let mut locations = retrieved_doc.get_all(location).collect::<Vec<&Value>>();
+        locations.push(&location_value);
+        let mut new_doc = Document::default();
+        new_doc_for_update(&mut new_doc, &retrieved_doc, locations, &self.schema);
+        info!("The new document with the added location is: {:?}", new_doc);
+        self.add_doc(hash, new_doc.clone());
+        Some(new_doc)
+    }
+
+    /// Deletes the document with this hash, including one which isn't committed yet
+    fn delete_doc(&self, hash: &str) {
+        let (_title, hash_field, _location, _body) = destructure_schema(&self.schema);
+        self.index_writer
+            .delete_term(Term::from_field_text(hash_field, hash));
+        self.pending_docs.lock().unwrap().remove(hash);
+    }
+
+    /// The latest documents with a location nested inside of the file at `file_facet`, eg: the messages of an mbox
+    /// An entry which is the same as another file or entry shares its document, so the documents can have other locations too
+    fn entry_docs(&self, file_facet: &str) -> Vec<(String, Document)> {
+        let (_title, hash_field, location_field, _body) = destructure_schema(&self.schema);
+        let entries_facet = Facet::from_text(&entries_facet_value(file_facet)).unwrap();
+        let is_entry = |doc: &Document| {
+            doc.get_all(location_field).any(|value| match value {
+                Value::Facet(facet) => entries_facet.is_prefix_of(facet),
+                _ => false,
+            })
+        };
+
+        // The searcher is returned before the documents are looked up, which lease their own
+        let mut hashes = {
+            let searcher = self.index_reader.searcher();
+            let query = TermQuery::new(
+                Term::from_facet(location_field, &entries_facet),
+                IndexRecordOption::Basic,
             );
-            if !retrieved_doc
-                .get_all(location)
-                .any(|value| value == &Value::from(Facet::from_text(location_facet).unwrap()))
-            // .contains(&&Value::from(Facet::from_text(location_facet).unwrap()))
-            {
-                // If this location of the file isn't already stored in the document, add it
-                retrieved_doc.add_facet(location, location_facet);
-                info!(
-                    "The new document with the added location is: {:?}",
-                    retrieved_doc
-                );
-                return Some(retrieved_doc);
+            searcher
+                .search(&query, &DocSetCollector)
+                .unwrap()
+                .into_iter()
+                .filter_map(|doc_address| {
+                    let doc = searcher.doc(doc_address).ok()?;
+                    doc.get_first(hash_field)?.text().map(String::from)
+                })
+                .collect::<BTreeSet<String>>()
+        };
+        hashes.extend(
+            self.pending_docs
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, doc)| is_entry(doc))
+                .map(|(hash, _)| hash.clone()),
+        );
+
+        // The committed documents may have been replaced since
+        hashes
+            .into_iter()
+            .filter_map(|hash| {
+                let doc = self.indexed_doc_by_hash(&hash)?;
+                if is_entry(&doc) {
+                    Some((hash, doc))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Gives the entries of the copy at `copy_facet` the locations the entries of the file it's a copy of have
+    /// Eg: the messages of `/backup/inbox.mbox` are also found at `/backup/inbox.mbox!/3` when `/mail/inbox.mbox` was indexed first
+    fn add_copied_entries(&self, doc: &Document, copy_facet: &str) {
+        let (_title, _hash_field, location_field, _body) = destructure_schema(&self.schema);
+        for value in doc.get_all(location_field) {
+            let original_facet = match value {
+                Value::Facet(facet) => facet_location(facet),
+                _ => continue,
+            };
+            if original_facet == copy_facet {
+                continue;
+            }
+            let entries_prefix = format!("{}/", entries_facet_value(&original_facet));
+            let entry_docs = self.entry_docs(&original_facet);
+            for (hash, entry_doc) in &entry_docs {
+                let entries = entry_doc
+                    .get_all(location_field)
+                    .filter_map(|value| match value {
+                        Value::Facet(facet) => facet_location(facet)
+                            .strip_prefix(&entries_prefix)
+                            .map(String::from),
+                        _ => None,
+                    })
+                    .collect::<Vec<String>>();
+                for entry in entries {
+                    self.add_location(hash, &entry_facet_value(copy_facet, &entry));
+                }
+            }
+            if !entry_docs.is_empty() {
+                return;
             }
-            // Otherwise, we can ignore
-            return None;
         }
-        None
     }
 
     #[instrument(skip(self, location_field, location_facet))]
@@ -76,16 +243,43 @@ impl TantivyWrapper {
         location_field: Field,
         location_facet: &Facet,
     ) -> Option<Document> {
-        if let Some(old_address) = self.get_doc_by_location(location_field, location_facet) {
-            let searcher = self.index_reader.searcher();
-            let location_term = Term::from_facet(location_field, location_facet);
-            let old_document = Some(searcher.doc(old_address).unwrap());
-            self.index_writer.delete_term(location_term);
-            info!("Deleting document by location: {:?}", old_document);
-            old_document
-        } else {
-            None
-        }
+        let (_title, hash_field, _location, _body) = destructure_schema(&self.schema);
+        let location_value = Value::from(location_facet.clone());
+        let hash = match self.pending_hash_by_location(location_field, &location_value) {
+            Some(hash) => hash,
+            None => {
+                let old_address = self.get_doc_by_location(location_field, location_facet)?;
+                let committed_doc = self.index_reader.searcher().doc(old_address).unwrap();
+                committed_doc.get_first(hash_field)?.text()?.to_string()
+            }
+        };
+        // The committed document may have been replaced by one without this location since
+        let old_document = self.indexed_doc_by_hash(&hash).filter(|doc| {
+            doc.get_all(location_field)
+                .any(|value| value == &location_value)
+        })?;
+        self.pending_docs.lock().unwrap().remove(&hash);
+        let location_term = Term::from_facet(location_field, location_facet);
+        self.index_writer.delete_term(location_term);
+        info!("Deleting document by location: {:?}", old_document);
+        Some(old_document)
+    }
+
+    /// The hash of the document with this location which was added since the last commit
+    fn pending_hash_by_location(
+        &self,
+        location_field: Field,
+        location_value: &Value,
+    ) -> Option<String> {
+        self.pending_docs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, doc)| {
+                doc.get_all(location_field)
+                    .any(|value| value == location_value)
+            })
+            .map(|(hash, _)| hash.clone())
     }
 
     #[instrument(skip(self, hash_field, hash))]
@@ -99,7 +293,7 @@ impl TantivyWrapper {
             let hash_term = Term::from_field_text(hash_field, hash);
             let old_document = Some(searcher.doc(old_address).unwrap());
             self.index_writer.delete_term(hash_term);
-            self.index_writer.commit()?;
+            self.commit()?;
             info!("Deleting document by hash: {:?}", old_document);
             Ok(old_document)
         } else {
@@ -166,7 +360,8 @@ impl TantivyWrapper {
     pub fn remove(&self, path_buf: &PathBuf) {
         // Remove the old document
         let location_facet = Facet::from_text(path_buf.as_path().to_str().unwrap()).unwrap();
-        let (_title, _hash_field, location, _body) = destructure_schema(&self.schema);
+        let (_title, hash_field, location, _body) = destructure_schema(&self.schema);
+        self.delete_entries(location, path_buf);
         if let Some(old_doc) = self.delete_doc_by_location(location, &location_facet) {
             info!("Deleted old document succesfully");
             let mut locations = old_doc.get_all(location).collect::<Vec<&Value>>();
@@ -202,8 +397,68 @@ impl TantivyWrapper {
                 new_doc_for_update(&mut new_doc, &old_doc, locations, &self.schema);
 
                 info!("The new doc after modifications {:?}", new_doc);
-                self.index_writer.add_document(new_doc.clone());
+                let hash = old_doc.get_first(hash_field).and_then(Value::text).unwrap();
+                self.add_doc(hash, new_doc);
+            }
+        }
+    }
+
+    /// Removes the locations nested inside of this path (eg: messages of an mbox archive) from their documents
+    /// Documents which are also found elsewhere, eg: the same message in another mbox, keep their other locations
+    fn delete_entries(&self, location_field: Field, path: &Path) {
+        let entries_facet = Facet::from_text(&entries_facet_value(path.to_str().unwrap())).unwrap();
+        info!("Deleting entries under: {:?}", entries_facet);
+        for (hash, old_doc) in self.entry_docs(path.to_str().unwrap()) {
+            let locations = old_doc
+                .get_all(location_field)
+                .filter(|value| match value {
+                    Value::Facet(facet) => !entries_facet.is_prefix_of(facet),
+                    _ => true,
+                })
+                .collect::<Vec<&Value>>();
+            if locations.is_empty() {
+                self.delete_doc(&hash);
+            } else {
+                let mut new_doc = Document::default();
+                new_doc_for_update(&mut new_doc, &old_doc, locations, &self.schema);
+                self.add_doc(&hash, new_doc);
+            }
+        }
+    }
+
+    /// Adds a document for every entry nested inside of the file at `location_facet`
+    /// Entries which have been seen before are skipped, the same way as files
+    /// Entries are dated with the modification time of the file they're in
+    fn add_entries(&self, location_facet: &str, modified: Option<i64>, entries: Vec<EntrySchema>) {
+        let (title_field, hash_field, location_field, body_field) =
+            destructure_schema(&self.schema);
+
+        for entry in entries {
+            let entry_facet = entry_facet_value(location_facet, &entry.entry);
+            if self.update_doc_by_hash(&entry_facet, &entry.hash).is_some() {
+                continue;
             }
+
+            info!("Adding entry at: {:?}", entry_facet);
+            let mut new_doc = Document::default();
+            new_doc.add_text(title_field, &entry.document.name);
+            new_doc.add_facet(location_field, entry_facet.as_str());
+            add_filenames(&mut new_doc, &self.schema);
+            new_doc.add_text(hash_field, entry.hash.to_hex().as_str());
+            new_doc.add_text(body_field, &entry.document.body);
+            add_metadata(&mut new_doc, &entry.document.metadata, &self.schema);
+            add_sections(&mut new_doc, &entry.document.sections, &self.schema);
+            add_simhash(&mut new_doc, &entry.document.body, &self.schema);
+            add_language(&mut new_doc, &entry.document.body, &self.schema);
+            add_modified(&mut new_doc, modified, &self.schema);
+            add_sort_fields(&mut new_doc, &entry.entry, entry.size, &self.schema);
+            self.add_doc(entry.hash.to_hex().as_str(), new_doc);
+            #[cfg(feature = "semantic")]
+            self.store_embedding(
+                entry.hash.to_hex().as_str(),
+                &entry.document.name,
+                &entry.document.body,
+            );
         }
     }
 }
@@ -218,93 +473,410 @@ impl FileProcessor for TantivyWrapper {
     #[instrument(skip(self, file_to_process))]
     async fn process_file(&self, file_to_process: FileToProcess) -> Option<Document> {
         let entry_path = file_to_process.path.clone();
-        let path = entry_path.as_path();
         let file_hash = file_to_process.hash;
-        if entry_path.extension() == None {
-            info!("Skipping, no file extension: {:?}", entry_path);
-            return None;
-        }
-
         let location_facet = &entry_path.to_facet_value();
 
         info!("Processing: {:?}", entry_path);
         info!("Hash of file is: {:?}", file_hash);
 
-        // Check if the file has already been indexed
-        if let Some(doc) = self.update_doc_by_hash(path, &file_to_process.hash) {
-            // TODO: Since this file has been seen before, we should simply add the location of this current field to process to the document
+        // A file which has been seen before only gets this location added to its document, and its entries theirs
+        if let Some(doc) = self.update_doc_by_hash(location_facet, &file_to_process.hash) {
+            self.add_copied_entries(&doc, location_facet);
             return Some(doc);
         }
 
         // We're indexing the file for the first time
-        let results = analyze(
-            entry_path.extension().unwrap().to_os_string(),
-            file_to_process,
-        )
-        .await;
-        if !results.is_empty() {
-            info!("This is a new file, we need to process it");
-            let title = &results[0].name;
-            let body = results.iter().fold(String::new(), |mut acc, x| {
-                acc.push_str(&x.body);
-                acc.push_str(" ");
-                acc
-            });
-            info!(
-                "Title: {:?} Body: {:?} Location: {:?}",
-                title, body, location_facet
-            );
-            assert!(location_facet.starts_with('/'));
+        let modified = modified_time(&entry_path);
+        let size = file_to_process.size;
+        let extension = entry_path.extension().unwrap_or_default().to_os_string();
+        let (results, entries) = analyze(extension, file_to_process).await;
 
-            let (title_field, hash_field, location_field, body_field) =
-                destructure_schema(&self.schema);
-            let mut new_doc = Document::default();
+        self.add_entries(location_facet, modified, entries);
 
-            new_doc.add_text(title_field, &title);
-            new_doc.add_facet(location_field, location_facet);
-            new_doc.add_text(hash_field, file_hash.to_hex().as_str());
-            new_doc.add_text(body_field, &body);
-            self.index_writer.add_document(new_doc.clone());
-            // self.index_writer.commit().unwrap();
-            return Some(new_doc);
-        } else {
-            info!("Couldn't find any results for file at: {:?}", entry_path);
+        // Files no indexer could read are still found by their name, size and modification time
+        let result = merge_documents(results).unwrap_or_else(|| {
+            info!("Indexing only the name of the file at: {:?}", entry_path);
+            DocumentSchema {
+                name: entry_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                ..Default::default()
+            }
+        });
+
+        info!("This is a new file, we need to process it");
+        let title = &result.name;
+        let body = &result.body;
+        info!(
+            "Title: {:?} Body: {:?} Location: {:?}",
+            title, body, location_facet
+        );
+        assert!(location_facet.starts_with('/'));
+
+        let (title_field, hash_field, location_field, body_field) =
+            destructure_schema(&self.schema);
+        let mut new_doc = Document::default();
+
+        new_doc.add_text(title_field, title);
+        new_doc.add_facet(location_field, location_facet);
+        add_filenames(&mut new_doc, &self.schema);
+        new_doc.add_text(hash_field, file_hash.to_hex().as_str());
+        new_doc.add_text(body_field, body);
+        add_metadata(&mut new_doc, &result.metadata, &self.schema);
+        add_sections(&mut new_doc, &result.sections, &self.schema);
+        add_simhash(&mut new_doc, body, &self.schema);
+        add_language(&mut new_doc, body, &self.schema);
+        add_xattr_tags(&mut new_doc, &entry_path, &self.schema);
+        add_modified(&mut new_doc, modified, &self.schema);
+        add_sort_fields(&mut new_doc, &entry_path, size, &self.schema);
+        self.add_doc(file_hash.to_hex().as_str(), new_doc.clone());
+        #[cfg(feature = "semantic")]
+        self.store_embedding(file_hash.to_hex().as_str(), title, body);
+        // self.index_writer.commit().unwrap();
+        Some(new_doc)
+    }
+}
+
+/// Stores each metadata value in the schema field with the same name
+/// Values for fields which aren't part of the schema are ignored
+fn add_metadata(doc: &mut Document, metadata: &[(String, MetadataValue)], schema: &Schema) {
+    for (field_name, value) in metadata {
+        let field = match schema.get_field(field_name) {
+            Some(field) => field,
+            None => {
+                info!("No field in schema for metadata: {:?}", field_name);
+                continue;
+            }
+        };
+        match value {
+            MetadataValue::Text(text) => doc.add_text(field, text),
+            MetadataValue::I64(number) => doc.add_i64(field, *number),
+            MetadataValue::F64(number) => doc.add_f64(field, *number),
+            MetadataValue::Facet(path) if PLACE_FIELDS.contains(&field_name.as_str()) => {
+                doc.add_facet(field, place_facet(&path.concat()))
+            }
+            MetadataValue::Facet(path) => doc.add_facet(field, Facet::from_path(path)),
+            MetadataValue::Bytes(bytes) => doc.add_bytes(field, bytes.clone()),
         }
+    }
+}
+
+/// Stores the label and text of each section at the same position in their fields
+fn add_sections(doc: &mut Document, sections: &[Section], schema: &Schema) {
+    let section_label = schema.get_field("section_label").unwrap();
+    let section_text = schema.get_field("section_text").unwrap();
+    for section in sections {
+        doc.add_text(section_label, &section.label);
+        doc.add_text(section_text, &section.text);
+    }
+}
+
+/// Stores the SimHash of the body, used to find documents which are nearly the same
+fn add_simhash(doc: &mut Document, body: &str, schema: &Schema) {
+    if let Some(hash) = simhash(body) {
+        doc.add_i64(schema.get_field("simhash").unwrap(), hash as i64);
+    }
+}
+
+/// Stores the language of the body and indexes the body again with the analyzer of that language
+fn add_language(doc: &mut Document, body: &str, schema: &Schema) {
+    let lang = detect_language(body);
+    if let Some(lang) = lang {
+        doc.add_text(schema.get_field("language").unwrap(), lang.code());
+    }
+    add_language_body(doc, lang, body, schema);
+}
+
+fn add_language_body(
+    doc: &mut Document,
+    lang: Option<whatlang::Lang>,
+    body: &str,
+    schema: &Schema,
+) {
+    if let Some(language_body_field) = language_body_field(schema, lang) {
+        doc.add_text(language_body_field, body);
+    }
+}
+
+/// Stores the tags file managers saved in the `user.xdg.tags` extended attribute, separated by commas
+fn add_xattr_tags(doc: &mut Document, path: &Path, schema: &Schema) {
+    let tag_field = schema.get_field("tag").unwrap();
+    for tag in xattr_tags(path) {
+        doc.add_text(tag_field, &tag);
+    }
+}
+
+#[cfg(unix)]
+fn xattr_tags(path: &Path) -> Vec<String> {
+    match xattr::get(path, "user.xdg.tags") {
+        Ok(Some(value)) => normalize_tags(String::from_utf8_lossy(&value).split(',')),
+        _ => Vec::new(),
+    }
+}
 
-        None
+#[cfg(not(unix))]
+fn xattr_tags(_path: &Path) -> Vec<String> {
+    Vec::new()
+}
+
+/// When the file at this path was last modified, in seconds since the unix epoch
+fn modified_time(path: &Path) -> Option<i64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    let since_epoch = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_secs() as i64)
+}
+
+/// Stores the modification time, used to rank recently changed files higher
+fn add_modified(doc: &mut Document, modified: Option<i64>, schema: &Schema) {
+    if let Some(modified) = modified {
+        doc.add_i64(schema.get_field("modified").unwrap(), modified);
     }
 }
 
+/// Stores the size and the name key of the file at `location`, used to sort results
+fn add_sort_fields<P: AsRef<Path>>(doc: &mut Document, location: P, size: u64, schema: &Schema) {
+    doc.add_i64(schema.get_field("size").unwrap(), size as i64);
+    add_name_key(doc, location, schema);
+}
+
+/// The name key is only a fast field, so it's added again whenever a document is rebuilt from its stored fields
+fn add_name_key<P: AsRef<Path>>(doc: &mut Document, location: P, schema: &Schema) {
+    if let Some(name) = location.as_ref().file_name() {
+        doc.add_u64(
+            schema.get_field("name_key").unwrap(),
+            name_key(&name.to_string_lossy()),
+        );
+    }
+}
+
+/// The location a facet was made from, as `to_facet_value` gives it
+fn facet_location(facet: &Facet) -> String {
+    Path::from_facet_value(facet).to_string_lossy().to_string()
+}
+
 /// Takes a default new doc, adds the values from old doc, but uses a different set of locations
-/// Used when removing 1 location from a list of locations
+/// Used when adding or removing a location of a file
 fn new_doc_for_update(
     new_doc: &mut Document,
     old_doc: &Document,
     locations: Vec<&Value>,
     schema: &Schema,
 ) {
-    let (title, hash_field, location, body) = destructure_schema(&schema);
-
-    info!("Setting title for new doc");
-    for title_value in old_doc.get_all(title) {
-        new_doc.add_text(title, title_value.text().unwrap());
-    }
+    let (_title, _hash_field, location, body_field) = destructure_schema(schema);
 
     info!("Setting locations for new doc");
     for location_value in locations {
         new_doc.add(FieldValue::new(location, location_value.clone()));
     }
 
-    info!("Setting hash for new doc");
+    info!("Copying the remaining fields for new doc");
+    for field_value in old_doc.field_values() {
+        if field_value.field() != location {
+            new_doc.add(field_value.clone());
+        }
+    }
+
+    // The language fields are only indexed, so they're rebuilt from the stored language and body
+    let language = old_doc
+        .get_first(schema.get_field("language").unwrap())
+        .and_then(Value::text)
+        .and_then(whatlang::Lang::from_code);
+    let body = old_doc.get_first(body_field).and_then(Value::text);
+    if let Some(body) = body {
+        add_language_body(new_doc, language, body, schema);
+    }
+    add_filenames(new_doc, schema);
+    if let Some(Value::Facet(first_location)) = new_doc.get_first(location) {
+        let first_location = Path::from_facet_value(first_location);
+        add_name_key(new_doc, first_location, schema);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages::register_tokenizers;
+    use crate::utils::build_schema;
+    use common::tantivy::query::AllQuery;
+    use common::tantivy::Index;
+    use common::tokio;
+    use contracts::file_to_process::new_file_to_process;
+
+    fn wrapper() -> TantivyWrapper {
+        let schema = build_schema();
+        let index = Index::create_in_ram(schema.clone());
+        register_tokenizers(&index);
+        let index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        let index_reader = index.reader().unwrap();
+        TantivyWrapper::new(index_reader, index_writer, schema)
+    }
+
+    /// A file no indexer reads, with the same hash as its copies
+    fn file(path: &str) -> FileToProcess {
+        FileToProcess {
+            path: PathBuf::from(path),
+            hash: blake2b_simd::blake2b(b"backup"),
+            contents: Vec::new(),
+            size: 6,
+        }
+    }
+
+    /// The locations of every indexed document
+    fn locations(tantivy_wrapper: &TantivyWrapper) -> Vec<Vec<PathBuf>> {
+        let (_title, _hash, location, _body) = destructure_schema(&tantivy_wrapper.schema);
+        let searcher = tantivy_wrapper.index_reader.searcher();
+        searcher
+            .search(&AllQuery, &TopDocs::with_limit(10))
+            .unwrap()
+            .into_iter()
+            .map(|(_, doc_address)| {
+                searcher
+                    .doc(doc_address)
+                    .unwrap()
+                    .get_all(location)
+                    .filter_map(|value| match value {
+                        Value::Facet(facet) => Some(Path::from_facet_value(facet)),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_copies_in_the_same_commit_share_a_document() {
+        let mut tantivy_wrapper = wrapper();
+        tantivy_wrapper.process_file(file("/a/backup.bin")).await;
+        tantivy_wrapper.process_file(file("/b/backup.bin")).await;
+        tantivy_wrapper.process_file(file("/b/backup.bin")).await;
+        tantivy_wrapper.commit().unwrap();
+        assert_eq!(
+            locations(&tantivy_wrapper),
+            vec![vec![
+                PathBuf::from("/a/backup.bin"),
+                PathBuf::from("/b/backup.bin")
+            ]]
+        );
+
+        // Copies found after a commit are added to the committed document
+        tantivy_wrapper.process_file(file("/c/backup.bin")).await;
+        tantivy_wrapper.commit().unwrap();
+        assert_eq!(locations(&tantivy_wrapper)[0].len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_removing_a_copy_before_a_commit() {
+        let mut tantivy_wrapper = wrapper();
+        tantivy_wrapper.process_file(file("/a/backup.bin")).await;
+        tantivy_wrapper.process_file(file("/b/backup.bin")).await;
+        tantivy_wrapper.remove(&PathBuf::from("/a/backup.bin"));
+        tantivy_wrapper.commit().unwrap();
+        assert_eq!(
+            locations(&tantivy_wrapper),
+            vec![vec![PathBuf::from("/b/backup.bin")]]
+        );
+
+        tantivy_wrapper.remove(&PathBuf::from("/b/backup.bin"));
+        tantivy_wrapper.commit().unwrap();
+        assert_eq!(locations(&tantivy_wrapper), Vec::<Vec<PathBuf>>::new());
+    }
+
+    /// Every location of every indexed document, sorted
+    fn all_locations(tantivy_wrapper: &TantivyWrapper) -> Vec<PathBuf> {
+        let mut all_locations = locations(tantivy_wrapper).concat();
+        all_locations.sort();
+        all_locations
+    }
+
+    fn message(body: &str) -> EntrySchema {
+        EntrySchema {
+            entry: String::from("1"),
+            hash: blake2b_simd::blake2b(body.as_bytes()),
+            size: body.len() as u64,
+            document: DocumentSchema {
+                name: String::from("Cats"),
+                body: body.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_removing_an_mbox_keeps_the_messages_found_elsewhere() {
+        let mut tantivy_wrapper = wrapper();
+        tantivy_wrapper.add_entries("/mail/one.mbox", None, vec![message("cats purr")]);
+        tantivy_wrapper.commit().unwrap();
+        tantivy_wrapper.add_entries(
+            "/mail/two.mbox",
+            None,
+            vec![message("cats purr"), message("dogs bark")],
+        );
+        tantivy_wrapper.commit().unwrap();
+
+        tantivy_wrapper.remove(&PathBuf::from("/mail/two.mbox"));
+        tantivy_wrapper.commit().unwrap();
+        assert_eq!(
+            all_locations(&tantivy_wrapper),
+            vec![PathBuf::from("/mail/one.mbox!/1")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_copies_of_an_mbox_have_the_locations_of_its_messages() {
+        let original = std::fs::canonicalize("../../test_files/Animals.mbox").unwrap();
+        let directory =
+            std::env::temp_dir().join(format!("podium_mbox_copy_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let copy = directory.join("Animals.mbox");
+        std::fs::copy(&original, &copy).unwrap();
+        let copy = std::fs::canonicalize(copy).unwrap();
+        let messages = |tantivy_wrapper: &TantivyWrapper, mbox: &Path| {
+            let entries = entries_facet_value(mbox.to_str().unwrap());
+            all_locations(tantivy_wrapper)
+                .into_iter()
+                .filter(|location| location.to_string_lossy().starts_with(&entries))
+                .count()
+        };
+
+        let mut tantivy_wrapper = wrapper();
+        tantivy_wrapper
+            .process_file(new_file_to_process(&original).await)
+            .await;
+        tantivy_wrapper.commit().unwrap();
+        tantivy_wrapper
+            .process_file(new_file_to_process(&copy).await)
+            .await;
+        tantivy_wrapper.commit().unwrap();
+        assert_eq!(messages(&tantivy_wrapper, &original), 2);
+        assert_eq!(messages(&tantivy_wrapper, &copy), 2);
+
+        tantivy_wrapper.remove(&original);
+        tantivy_wrapper.commit().unwrap();
+        assert_eq!(messages(&tantivy_wrapper, &original), 0);
+        assert_eq!(messages(&tantivy_wrapper, &copy), 2);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_removing_an_archive_keeps_the_files_found_elsewhere() {
+        // animals/data.csv inside of the archive is a copy of data.csv
+        let file = std::fs::canonicalize("../../test_files/data.csv").unwrap();
+        let archive = std::fs::canonicalize("../../test_files/Animals.zip").unwrap();
+        let entry = PathBuf::from(format!("{}!/animals/data.csv", archive.to_string_lossy()));
 
-    // There should only be 1 hash value
-    new_doc.add_text(
-        hash_field,
-        old_doc.get_first(hash_field).unwrap().text().unwrap(),
-    );
+        let mut tantivy_wrapper = wrapper();
+        tantivy_wrapper
+            .process_file(new_file_to_process(&file).await)
+            .await;
+        tantivy_wrapper
+            .process_file(new_file_to_process(&archive).await)
+            .await;
+        tantivy_wrapper.commit().unwrap();
+        assert!(locations(&tantivy_wrapper).contains(&vec![file.clone(), entry]));
 
-    info!("Setting body for new doc");
-    for body_value in old_doc.get_all(body) {
-        new_doc.add_text(body, body_value.text().unwrap());
+        tantivy_wrapper.remove(&archive);
+        tantivy_wrapper.commit().unwrap();
+        assert_eq!(all_locations(&tantivy_wrapper), vec![file]);
     }
 }
@@ -0,0 +1,230 @@
+use common::anyhow;
+use common::anyhow::{bail, Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::{error, info, span, Level};
+use once_cell::sync::{Lazy, OnceCell};
+use tract_core::ndarray;
+use tract_onnx::prelude::*;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::tokenizer::WordPieceTokenizer;
+
+/// File names of the text embedding model and its vocabulary inside of the models directory
+const EMBEDDING_MODEL: &str = "text-embedding.onnx";
+const EMBEDDING_VOCAB: &str = "text-embedding-vocab.txt";
+
+/// Every text is padded or cut off to this many tokens, so the model is only optimized once
+const MAX_TOKENS: usize = 256;
+
+static MODELS_DIRECTORY: OnceCell<PathBuf> = OnceCell::new();
+
+/// The embedder is only available when the model and its vocabulary are in the models directory
+static EMBEDDER: Lazy<Option<Embedder>> = Lazy::new(|| {
+    let models_directory = MODELS_DIRECTORY.get_or_init(|| PathBuf::from("models"));
+    span!(Level::INFO, "embedder: Loading model").in_scope(|| {
+        match Embedder::load(models_directory) {
+            Ok(embedder) => Some(embedder),
+            Err(e) => {
+                error!("Documents won't be embedded: {:?}", e);
+                None
+            }
+        }
+    })
+});
+
+/// Sets the directory the text embedding model is loaded from
+/// This has to happen before the first document is embedded
+pub fn configure(models_directory: PathBuf) -> Result<()> {
+    MODELS_DIRECTORY
+        .set(models_directory)
+        .map_err(|_| anyhow::anyhow!("embedder: The models directory was already configured"))
+}
+
+/// The embedder, if its model could be loaded
+pub fn embedder() -> Option<&'static Embedder> {
+    EMBEDDER.as_ref()
+}
+
+/// The inputs BERT-style models take, recognized by their names
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ModelInput {
+    InputIds,
+    AttentionMask,
+    TokenTypeIds,
+}
+
+impl ModelInput {
+    fn from_name(name: &str) -> Self {
+        if name.contains("mask") {
+            ModelInput::AttentionMask
+        } else if name.contains("type") {
+            ModelInput::TokenTypeIds
+        } else {
+            ModelInput::InputIds
+        }
+    }
+}
+
+/// Turns text into a normalized vector with a sentence embedding model, eg: all-MiniLM-L6-v2
+pub struct Embedder {
+    model: TypedModel,
+    inputs: Vec<ModelInput>,
+    tokenizer: WordPieceTokenizer,
+}
+
+impl Embedder {
+    pub fn load(models_directory: &Path) -> Result<Self> {
+        let model_path = models_directory.join(EMBEDDING_MODEL);
+        let vocab_path = models_directory.join(EMBEDDING_VOCAB);
+
+        let vocab = fs::read_to_string(&vocab_path).with_context(|| {
+            log_and_return_error_string(format!(
+                "embedder: Failed to read vocabulary at path: {:?}",
+                vocab_path
+            ))
+        })?;
+        let tokenizer = WordPieceTokenizer::from_vocab(&vocab)?;
+
+        let load_error = |e| {
+            anyhow::anyhow!(log_and_return_error_string(format!(
+                "embedder: Failed to load model at path: {:?} with additional error info {:?}",
+                model_path, e
+            )))
+        };
+        let mut model = onnx().model_for_path(&model_path).map_err(load_error)?;
+        let inputs = model
+            .input_outlets()
+            .map_err(load_error)?
+            .iter()
+            .map(|outlet| ModelInput::from_name(&model.node(outlet.node).name))
+            .collect::<Vec<ModelInput>>();
+        for index in 0..inputs.len() {
+            model = model
+                .with_input_fact(
+                    index,
+                    InferenceFact::dt_shape(i64::datum_type(), tvec!(1, MAX_TOKENS)),
+                )
+                .map_err(load_error)?;
+        }
+        let model = model.into_optimized().map_err(load_error)?;
+        info!("Loaded text embedding model from {:?}", model_path);
+
+        Ok(Embedder {
+            model,
+            inputs,
+            tokenizer,
+        })
+    }
+
+    /// The embedding of the text, only its first tokens are taken into account
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let (ids, attention_mask) = self.tokenizer.encode(text, MAX_TOKENS);
+        let to_tensor = |values: Vec<i64>| -> Result<Tensor> {
+            Ok(ndarray::Array2::from_shape_vec((1, MAX_TOKENS), values)?.into())
+        };
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| match input {
+                ModelInput::InputIds => to_tensor(ids.clone()),
+                ModelInput::AttentionMask => to_tensor(attention_mask.clone()),
+                ModelInput::TokenTypeIds => to_tensor(vec![0; MAX_TOKENS]),
+            })
+            .collect::<Result<TVec<Tensor>>>()?;
+
+        let result = SimplePlan::new(&self.model)
+            .and_then(|plan| plan.run(inputs))
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "embedder: Failed to run the text through the model with additional error info {:?}",
+                    e
+                )
+            })?;
+        let output = result[0].to_array_view::<f32>().map_err(|e| {
+            anyhow::anyhow!(
+                "embedder: Failed to convert the output of the model to an array view with additional error info {:?}",
+                e
+            )
+        })?;
+
+        let embedding = match output.ndim() {
+            // Token embeddings, averaged over the tokens which aren't padding
+            3 => {
+                let output = output.into_dimensionality::<ndarray::Ix3>()?;
+                mean_pool(output.index_axis(ndarray::Axis(0), 0), &attention_mask)
+            }
+            // The model already pools the tokens into a sentence embedding
+            2 => output.iter().cloned().collect(),
+            ndim => bail!("embedder: Unexpected model output with {} dimensions", ndim),
+        };
+        Ok(normalize(embedding))
+    }
+}
+
+fn mean_pool(token_embeddings: ndarray::ArrayView2<f32>, attention_mask: &[i64]) -> Vec<f32> {
+    let mut sum = vec![0.0; token_embeddings.ncols()];
+    let mut count = 0.0;
+    for (token_embedding, _) in token_embeddings
+        .outer_iter()
+        .zip(attention_mask)
+        .filter(|(_, mask)| **mask == 1)
+    {
+        for (total, value) in sum.iter_mut().zip(token_embedding.iter()) {
+            *total += value;
+        }
+        count += 1.0;
+    }
+    sum.iter()
+        .map(|total| total / f32::max(count, 1.0))
+        .collect()
+}
+
+fn normalize(vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return vector;
+    }
+    vector.iter().map(|value| value / norm).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padding_is_left_out_of_the_mean() {
+        let token_embeddings =
+            ndarray::Array2::from_shape_vec((3, 2), vec![1.0, 2.0, 3.0, 4.0, 100.0, 100.0])
+                .unwrap();
+        assert_eq!(
+            mean_pool(token_embeddings.view(), &[1, 1, 0]),
+            vec![2.0, 3.0]
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(vec![3.0, 4.0]), vec![0.6, 0.8]);
+        assert_eq!(normalize(vec![0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_recognizing_model_inputs() {
+        assert_eq!(ModelInput::from_name("input_ids"), ModelInput::InputIds);
+        assert_eq!(
+            ModelInput::from_name("attention_mask"),
+            ModelInput::AttentionMask
+        );
+        assert_eq!(
+            ModelInput::from_name("token_type_ids"),
+            ModelInput::TokenTypeIds
+        );
+    }
+
+    #[test]
+    fn test_missing_model() {
+        assert!(Embedder::load(Path::new("does/not/exist")).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+pub mod embedder;
+pub mod tokenizer;
+pub mod vector_store;
+
+pub use embedder::{configure, embedder, Embedder};
+pub use vector_store::{cosine_similarity, VectorStore};
@@ -0,0 +1,161 @@
+use common::anyhow::{anyhow, Result};
+
+use std::collections::HashMap;
+
+/// Words longer than this are never split into pieces, they become the unknown token
+const MAX_WORD_CHARS: usize = 100;
+
+/// Splits text into the WordPiece ids BERT-style models are trained on
+pub struct WordPieceTokenizer {
+    vocab: HashMap<String, i64>,
+    classify: i64,
+    separator: i64,
+    padding: i64,
+    unknown: i64,
+}
+
+impl WordPieceTokenizer {
+    /// Reads a vocabulary with one token per line, the line number is the id of the token
+    pub fn from_vocab(vocab: &str) -> Result<Self> {
+        let vocab: HashMap<String, i64> = vocab
+            .lines()
+            .enumerate()
+            .map(|(id, token)| (token.to_string(), id as i64))
+            .collect();
+        let special = |token: &str| {
+            vocab
+                .get(token)
+                .copied()
+                .ok_or_else(|| anyhow!("tokenizer: The vocabulary has no {} token", token))
+        };
+
+        Ok(WordPieceTokenizer {
+            classify: special("[CLS]")?,
+            separator: special("[SEP]")?,
+            padding: special("[PAD]")?,
+            unknown: special("[UNK]")?,
+            vocab,
+        })
+    }
+
+    /// The ids of the text between the classify and separator tokens, padded to `max_tokens`
+    /// Text which doesn't fit is cut off, along with the attention mask of the ids
+    pub fn encode(&self, text: &str, max_tokens: usize) -> (Vec<i64>, Vec<i64>) {
+        let mut ids = vec![self.classify];
+        ids.extend(
+            basic_tokens(text)
+                .iter()
+                .flat_map(|word| self.word_pieces(word))
+                .take(max_tokens.saturating_sub(2)),
+        );
+        ids.push(self.separator);
+
+        let mut attention_mask = vec![1; ids.len()];
+        ids.resize(max_tokens, self.padding);
+        attention_mask.resize(max_tokens, 0);
+        (ids, attention_mask)
+    }
+
+    /// Greedily splits a word into the longest pieces in the vocabulary
+    fn word_pieces(&self, word: &str) -> Vec<i64> {
+        let chars: Vec<char> = word.chars().collect();
+        if chars.len() > MAX_WORD_CHARS {
+            return vec![self.unknown];
+        }
+
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            let piece = (start + 1..=chars.len()).rev().find_map(|end| {
+                let mut piece: String = chars[start..end].iter().collect();
+                if start > 0 {
+                    piece.insert_str(0, "##");
+                }
+                self.vocab.get(&piece).map(|id| (*id, end))
+            });
+            match piece {
+                Some((id, end)) => {
+                    pieces.push(id);
+                    start = end;
+                }
+                None => return vec![self.unknown],
+            }
+        }
+        pieces
+    }
+}
+
+/// Lowercases the text and splits it on whitespace, punctuation and CJK characters
+fn basic_tokens(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_whitespace() || c.is_control() {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+        } else if is_punctuation(c) || is_cjk(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            tokens.push(c.to_string());
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || (!c.is_alphanumeric() && !c.is_whitespace())
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x4E00..=0x9FFF
+        | 0x3400..=0x4DBF
+        | 0x20000..=0x2A6DF
+        | 0xF900..=0xFAFF
+        | 0x2F800..=0x2FA1F)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOCAB: &str = "[PAD]\n[UNK]\n[CLS]\n[SEP]\ncat\n##s\nplay\n##ing\n!\n猫";
+
+    #[test]
+    fn test_splitting_words_into_pieces() {
+        let tokenizer = WordPieceTokenizer::from_vocab(VOCAB).unwrap();
+        let (ids, attention_mask) = tokenizer.encode("Cats playing!", 8);
+
+        assert_eq!(ids, vec![2, 4, 5, 6, 7, 8, 3, 0]);
+        assert_eq!(attention_mask, vec![1, 1, 1, 1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_unknown_words_and_cjk() {
+        let tokenizer = WordPieceTokenizer::from_vocab(VOCAB).unwrap();
+        let (ids, _) = tokenizer.encode("dogs猫", 5);
+
+        assert_eq!(ids, vec![2, 1, 9, 3, 0]);
+    }
+
+    #[test]
+    fn test_long_text_is_cut_off() {
+        let tokenizer = WordPieceTokenizer::from_vocab(VOCAB).unwrap();
+        let (ids, attention_mask) = tokenizer.encode("cat cat cat cat", 4);
+
+        assert_eq!(ids, vec![2, 4, 4, 3]);
+        assert_eq!(attention_mask, vec![1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_vocab_without_special_tokens() {
+        assert!(WordPieceTokenizer::from_vocab("cat\n##s").is_err());
+    }
+}
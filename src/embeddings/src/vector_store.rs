@@ -0,0 +1,306 @@
+use common::anyhow::{bail, Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::info;
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+
+/// Hashes are hex digests, anything longer is a corrupted record
+const MAX_HASH_LENGTH: usize = 128;
+
+/// Vectors are only as long as the output of a model, anything longer is a corrupted record
+const MAX_DIMENSION: usize = 8192;
+
+/// The embeddings of documents by their hash, kept in memory and appended to a file
+/// Vectors of files which were deleted stay in the file, searches skip them because their hash
+/// no longer matches a document
+pub struct VectorStore {
+    vectors: RwLock<HashMap<String, Vec<f32>>>,
+    /// The length of every vector, set by the first one stored
+    dimension: RwLock<Option<usize>>,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl VectorStore {
+    /// Loads the vectors stored at this path, the file is created if it doesn't exist
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| {
+                log_and_return_error_string(format!(
+                    "vector_store: Failed to open vectors at path: {:?}",
+                    path
+                ))
+            })?;
+
+        let (vectors, dimension) = read_records(BufReader::new(&file));
+        info!("Loaded {} vectors from {:?}", vectors.len(), path);
+
+        Ok(VectorStore {
+            vectors: RwLock::new(vectors),
+            dimension: RwLock::new(dimension),
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.vectors.read().unwrap().contains_key(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stores the vector of a document and writes it to disk
+    /// Every vector has to be as long as the first one, they come from the same model
+    pub fn insert(&self, hash: &str, vector: Vec<f32>) -> Result<()> {
+        if hash.len() > MAX_HASH_LENGTH || vector.is_empty() || vector.len() > MAX_DIMENSION {
+            bail!(
+                "vector_store: Invalid vector of length {} for document: {:?}",
+                vector.len(),
+                hash
+            );
+        }
+        let mut file = self.file.lock().unwrap();
+        let mut dimension = self.dimension.write().unwrap();
+        match *dimension {
+            Some(dimension) if dimension != vector.len() => bail!(
+                "vector_store: The vector of document {:?} has {} dimensions instead of {}",
+                hash,
+                vector.len(),
+                dimension
+            ),
+            _ => *dimension = Some(vector.len()),
+        }
+        write_record(&mut *file, hash, &vector)
+            .and_then(|_| file.flush())
+            .with_context(|| {
+                log_and_return_error_string(format!(
+                    "vector_store: Failed to write the vector of document: {:?}",
+                    hash
+                ))
+            })?;
+        self.vectors
+            .write()
+            .unwrap()
+            .insert(hash.to_string(), vector);
+        Ok(())
+    }
+
+    /// How similar the stored vector of a document is to this one
+    pub fn similarity(&self, hash: &str, vector: &[f32]) -> Option<f32> {
+        self.vectors
+            .read()
+            .unwrap()
+            .get(hash)
+            .map(|stored| cosine_similarity(stored, vector))
+    }
+
+    /// The hashes of the `limit` documents most similar to this vector, most similar first
+    pub fn nearest(&self, vector: &[f32], limit: usize) -> Vec<(String, f32)> {
+        let mut similarities: Vec<(String, f32)> = self
+            .vectors
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(hash, stored)| (hash.clone(), cosine_similarity(stored, vector)))
+            .collect();
+        similarities.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        similarities.truncate(limit);
+        similarities
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// Each record is the length of the hash, the hash, the length of the vector and the vector
+fn write_record(writer: &mut impl Write, hash: &str, vector: &[f32]) -> std::io::Result<()> {
+    writer.write_all(&(hash.len() as u32).to_le_bytes())?;
+    writer.write_all(hash.as_bytes())?;
+    writer.write_all(&(vector.len() as u32).to_le_bytes())?;
+    for value in vector {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads records until the end of the file, and the dimension of their vectors
+/// Reading stops at the first bad record, eg: one cut off by a crash, since what follows it can't be trusted
+/// Later records for the same hash replace earlier ones
+fn read_records(mut reader: impl Read) -> (HashMap<String, Vec<f32>>, Option<usize>) {
+    let mut vectors = HashMap::new();
+    let mut dimension = None;
+    while let Some((hash, vector)) = read_record(&mut reader, dimension) {
+        dimension = Some(vector.len());
+        vectors.insert(hash, vector);
+    }
+    (vectors, dimension)
+}
+
+/// Reads a record whose vector has this dimension, or any dimension up to the maximum when it isn't known yet
+fn read_record(reader: &mut impl Read, dimension: Option<usize>) -> Option<(String, Vec<f32>)> {
+    let hash_len = read_u32(reader)? as usize;
+    if hash_len > MAX_HASH_LENGTH {
+        return None;
+    }
+    let mut hash = vec![0; hash_len];
+    reader.read_exact(&mut hash).ok()?;
+    let hash = String::from_utf8(hash).ok()?;
+
+    let vector_len = read_u32(reader)? as usize;
+    let valid_len = match dimension {
+        Some(dimension) => vector_len == dimension,
+        None => vector_len > 0 && vector_len <= MAX_DIMENSION,
+    };
+    if !valid_len {
+        return None;
+    }
+    let mut bytes = vec![0; vector_len * 4];
+    reader.read_exact(&mut bytes).ok()?;
+    let vector = bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    Some((hash, vector))
+}
+
+fn read_u32(reader: &mut impl Read) -> Option<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes).ok()?;
+    Some(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn store_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("podium_{}_{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_vectors_are_reloaded() {
+        let path = store_path("vectors_reloaded");
+        {
+            let store = VectorStore::open(&path).unwrap();
+            store.insert("cats", vec![1.0, 0.0]).unwrap();
+            store.insert("dogs", vec![0.0, 1.0]).unwrap();
+            store.insert("cats", vec![0.6, 0.8]).unwrap();
+        }
+
+        let store = VectorStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert!(store.contains("dogs"));
+        assert!((store.similarity("cats", &[0.6, 0.8]).unwrap() - 1.0).abs() < 1e-6);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cut_off_record_is_dropped() {
+        let path = store_path("vectors_cut_off");
+        {
+            let store = VectorStore::open(&path).unwrap();
+            store.insert("cats", vec![1.0, 0.0]).unwrap();
+            store.insert("dogs", vec![0.0, 1.0]).unwrap();
+        }
+        let length = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(length - 2)
+            .unwrap();
+
+        let store = VectorStore::open(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert!(store.contains("cats"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reading_stops_at_bad_record() {
+        let path = store_path("vectors_bad_record");
+        {
+            let store = VectorStore::open(&path).unwrap();
+            store.insert("cats", vec![1.0, 0.0]).unwrap();
+        }
+        {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            // A record claiming a huge hash, then a valid one which can't be trusted after it
+            file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+            write_record(&mut file, "dogs", &[0.0, 1.0]).unwrap();
+        }
+
+        let store = VectorStore::open(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert!(store.contains("cats"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_vectors_of_another_dimension_are_rejected() {
+        let path = store_path("vectors_dimension");
+        {
+            let store = VectorStore::open(&path).unwrap();
+            store.insert("cats", vec![1.0, 0.0]).unwrap();
+            assert!(store.insert("dogs", vec![0.0, 1.0, 0.0]).is_err());
+            assert!(store.insert("birds", Vec::new()).is_err());
+        }
+        {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            write_record(&mut file, "dogs", &[0.0, 1.0, 0.0]).unwrap();
+        }
+
+        let store = VectorStore::open(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert!(store.insert("dogs", vec![0.0, 1.0, 0.0]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_nearest_vectors() {
+        let path = store_path("vectors_nearest");
+        let store = VectorStore::open(&path).unwrap();
+        store.insert("cats", vec![1.0, 0.0]).unwrap();
+        store.insert("kittens", vec![0.8, 0.6]).unwrap();
+        store.insert("dogs", vec![0.0, 1.0]).unwrap();
+
+        let nearest = store.nearest(&[1.0, 0.1], 2);
+        let hashes: Vec<&str> = nearest.iter().map(|(hash, _)| hash.as_str()).collect();
+        assert_eq!(hashes, vec!["cats", "kittens"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]), 1.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+    }
+}
@@ -1,5 +1,6 @@
-use contracts::file_to_process::{new_file_to_process, FileToProcess};
+use contracts::file_to_process::{new_file_to_process, new_unread_file_to_process, FileToProcess};
 use custom_tantivy::wrapper::*;
+use indexers::Analyzer;
 
 use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
 use tracing::info;
@@ -16,6 +17,7 @@ pub async fn start_watcher(directories: &Vec<PathBuf>, tantivy_wrapper: &mut Tan
     info!("Starting file watcher thread on: {:?}", directories);
     let (watcher_tx, watcher_rx) = channel();
     let mut watcher = watcher(watcher_tx, Duration::from_secs(10)).unwrap();
+    let analyzer = Analyzer::default();
 
     // Start watching all directories in the config file
     for directory in directories {
@@ -29,10 +31,10 @@ pub async fn start_watcher(directories: &Vec<PathBuf>, tantivy_wrapper: &mut Tan
                 info!("Received watcher event: {:?}", event);
                 match event {
                     DebouncedEvent::Create(path_buf) => {
-                        create_event(path_buf, tantivy_wrapper).await;
+                        create_event(path_buf, tantivy_wrapper, &analyzer).await;
                     }
                     DebouncedEvent::Write(path_buf) => {
-                        write_event(path_buf, tantivy_wrapper).await;
+                        write_event(path_buf, tantivy_wrapper, &analyzer).await;
                     }
                     DebouncedEvent::NoticeRemove(path_buf) => {
                         remove_event(&path_buf, tantivy_wrapper);
@@ -48,27 +50,44 @@ pub async fn start_watcher(directories: &Vec<PathBuf>, tantivy_wrapper: &mut Tan
             }
             Err(e) => error!("watch error: {:?}", e),
         }
-        tantivy_wrapper.index_writer.commit().unwrap();
+        tantivy_wrapper.commit().unwrap();
     }
 }
 
 /// Handles a create event from watch_dir
 /// If a folder is created, recursively process all files in the folder
 /// Otherwise process the single new file which was created
-async fn create_event(path_buf: PathBuf, tantivy_wrapper: &TantivyWrapper) {
+async fn create_event(path_buf: PathBuf, tantivy_wrapper: &TantivyWrapper, analyzer: &Analyzer) {
     if path_buf.is_dir() {
         // Traverse through all the files in the directory
         let walker = WalkDir::new(path_buf).into_iter();
         for entry in walker.filter_entry(|e| !is_hidden(e)) {
             let entry = entry.unwrap();
+            if !entry.file_type().is_file() {
+                continue;
+            }
             create(
-                new_file_to_process(entry.into_path()).await,
+                read_file(entry.into_path(), analyzer).await,
                 tantivy_wrapper,
             )
             .await;
         }
     } else {
-        create(new_file_to_process(path_buf).await, tantivy_wrapper).await;
+        create(read_file(path_buf, analyzer).await, tantivy_wrapper).await;
+    }
+}
+
+/// Every file is indexed by its name, the contents are only read when an indexer supports them
+/// The initial processing reads files the same way, so a file has the same hash whether it was found by the scan or the watcher
+pub async fn read_file(path_buf: PathBuf, analyzer: &Analyzer) -> FileToProcess {
+    let is_supported = matches!(
+        path_buf.extension(),
+        Some(extension) if analyzer.supported_extensions.contains(extension)
+    );
+    if is_supported {
+        new_file_to_process(path_buf).await
+    } else {
+        new_unread_file_to_process(path_buf).await
     }
 }
 
@@ -82,21 +101,24 @@ async fn create(file_to_process: FileToProcess, tantivy_wrapper: &TantivyWrapper
 /// Handles a write event from watch_dir
 /// If a folder is written, recursively process all files in the folder
 /// Otherwise process the single file which was written
-async fn write_event(path_buf: PathBuf, tantivy_wrapper: &TantivyWrapper) {
+async fn write_event(path_buf: PathBuf, tantivy_wrapper: &TantivyWrapper, analyzer: &Analyzer) {
     // Remove the old document, reprocess and add the new content
     if path_buf.is_dir() {
         // Traverse through all the files in the directory
         let walker = WalkDir::new(path_buf).into_iter();
         for entry in walker.filter_entry(|e| !is_hidden(e)) {
             let entry = entry.unwrap();
+            if !entry.file_type().is_file() {
+                continue;
+            }
             write(
-                new_file_to_process(entry.into_path()).await,
+                read_file(entry.into_path(), analyzer).await,
                 tantivy_wrapper,
             )
             .await;
         }
     } else {
-        write(new_file_to_process(path_buf).await, tantivy_wrapper).await;
+        write(read_file(path_buf, analyzer).await, tantivy_wrapper).await;
     }
 }
 
@@ -152,3 +174,37 @@ pub fn is_hidden(entry: &DirEntry) -> bool {
         .map(|s| s.starts_with('.'))
         .unwrap_or(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tantivy::Index;
+    use common::tokio;
+    use custom_tantivy::languages::register_tokenizers;
+    use custom_tantivy::utils::{build_schema, destructure_schema};
+
+    #[tokio::test]
+    async fn test_created_files_are_read_like_the_initial_processing() {
+        let schema = build_schema();
+        let index = Index::create_in_ram(schema.clone());
+        register_tokenizers(&index);
+        let index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        let mut tantivy_wrapper =
+            TantivyWrapper::new(index.reader().unwrap(), index_writer, schema.clone());
+        let (_, hash_field, _, _) = destructure_schema(&schema);
+
+        // No indexer reads this extension, so the file is hashed by its path, size and modification time
+        let directory = std::env::temp_dir().join(format!("podium_watcher_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("notes.unsupported");
+        std::fs::write(&path, "cats purr").unwrap();
+
+        create_event(path.clone(), &tantivy_wrapper, &Analyzer::default()).await;
+        tantivy_wrapper.commit().unwrap();
+        let hash = new_unread_file_to_process(&path).await.hash;
+        assert!(tantivy_wrapper
+            .get_doc_by_hash(hash_field, hash.to_hex().as_str())
+            .is_some());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -45,6 +45,7 @@ impl Indexer for CsvIndexer {
             Ok(DocumentSchema {
                 name: file_to_process.path(),
                 body: headers,
+                ..Default::default()
             })
         })
     }
@@ -24,6 +24,7 @@ impl Indexer for DocxIndexer {
         Ok(DocumentSchema {
             name: String::new(),
             body: String::new(),
+            ..Default::default()
         })
     }
 }
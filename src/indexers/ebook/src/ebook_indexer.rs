@@ -0,0 +1,435 @@
+use common::anyhow::{anyhow, Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::{span, Level};
+use contracts::file_to_process::FileToProcess;
+use contracts::indexer::{DocumentSchema, Indexer, MetadataValue, Section};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{Cursor, Read};
+
+use roxmltree::{Document, Node};
+
+const CONTAINER_NS: &str = "urn:oasis:names:tc:opendocument:xmlns:container";
+const OPF_NS: &str = "http://www.idpf.org/2007/opf";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+const XHTML_NS: &str = "http://www.w3.org/1999/xhtml";
+const FB2_NS: &str = "http://www.gribuser.ru/xml/fictionbook/2.0";
+
+/// Elements which start a new line of text in XHTML and FictionBook documents
+const BLOCK_ELEMENTS: [&str; 24] = [
+    "p",
+    "div",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "li",
+    "br",
+    "tr",
+    "td",
+    "th",
+    "blockquote",
+    "pre",
+    "dt",
+    "dd",
+    "section",
+    "title",
+    "subtitle",
+    "v",
+    "stanza",
+    "text-author",
+    "empty-line",
+];
+
+/// XHTML content documents often use HTML entities, which XML parsers only know from the XHTML DTD
+const HTML_ENTITIES: [(&str, &str); 9] = [
+    ("&nbsp;", "&#160;"),
+    ("&mdash;", "&#8212;"),
+    ("&ndash;", "&#8211;"),
+    ("&hellip;", "&#8230;"),
+    ("&lsquo;", "&#8216;"),
+    ("&rsquo;", "&#8217;"),
+    ("&ldquo;", "&#8220;"),
+    ("&rdquo;", "&#8221;"),
+    ("&copy;", "&#169;"),
+];
+
+/// Indexes EPUB and FictionBook ebooks
+/// Each chapter is a section, so results can point to the chapter which matched
+pub struct EbookIndexer;
+
+impl Indexer for EbookIndexer {
+    fn supports_extension(&self, extension: &OsStr) -> bool {
+        extension == OsStr::new("epub") || extension == OsStr::new("fb2")
+    }
+
+    fn supported_extensions(&self) -> Vec<OsString> {
+        vec![OsString::from("epub"), OsString::from("fb2")]
+    }
+
+    fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
+        let path = file_to_process.path.to_str().unwrap();
+        span!(Level::INFO, "ebook_indexer: indexing ebook file", path).in_scope(|| {
+            let book = if file_to_process.path.extension() == Some(OsStr::new("fb2")) {
+                span!(Level::INFO, "ebook_indexer: Process FictionBook")
+                    .in_scope(|| read_fiction_book(&file_to_process.contents))
+            } else {
+                span!(Level::INFO, "ebook_indexer: Process EPUB")
+                    .in_scope(|| read_epub(&file_to_process.contents))
+            }
+            .with_context(|| {
+                log_and_return_error_string(format!(
+                    "ebook_indexer: Failed to read ebook at path: {:?}",
+                    file_to_process.path
+                ))
+            })?;
+
+            let name = book.title.unwrap_or_else(|| {
+                file_to_process
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            });
+
+            let body = book
+                .chapters
+                .iter()
+                .map(|chapter| chapter.text.as_str())
+                .collect::<Vec<&str>>()
+                .join(" ");
+
+            let metadata = book
+                .authors
+                .into_iter()
+                .map(|author| (String::from("author"), MetadataValue::Text(author)))
+                .collect();
+
+            Ok(DocumentSchema {
+                name,
+                body,
+                metadata,
+                sections: book.chapters,
+            })
+        })
+    }
+}
+
+/// What is indexed from an ebook, whatever its format
+#[derive(Debug, Default)]
+struct Book {
+    title: Option<String>,
+    authors: Vec<String>,
+    chapters: Vec<Section>,
+}
+
+/// The OPF package document says where the title, the authors and the chapters are
+/// Chapters are read in the order of the spine, which is the reading order
+fn read_epub(contents: &[u8]) -> Result<Book> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(contents))?;
+
+    let container = read_entry(&mut archive, "META-INF/container.xml")?;
+    let container = Document::parse(&container)?;
+    let package_path = container
+        .descendants()
+        .find(|node| node.has_tag_name((CONTAINER_NS, "rootfile")))
+        .and_then(|rootfile| rootfile.attribute("full-path"))
+        .ok_or_else(|| anyhow!("The container doesn't point to a package document"))?
+        .to_string();
+
+    let package = read_entry(&mut archive, &package_path)?;
+    let package = Document::parse(&package)?;
+
+    let title = package
+        .descendants()
+        .find(|node| node.has_tag_name((DC_NS, "title")))
+        .map(text)
+        .filter(|title| !title.is_empty());
+    let authors = package
+        .descendants()
+        .filter(|node| node.has_tag_name((DC_NS, "creator")))
+        .map(text)
+        .filter(|author| !author.is_empty())
+        .collect();
+
+    let manifest = package
+        .descendants()
+        .filter(|node| node.has_tag_name((OPF_NS, "item")))
+        .filter_map(|item| Some((item.attribute("id")?, item.attribute("href")?)))
+        .collect::<HashMap<&str, &str>>();
+
+    let mut chapters = Vec::new();
+    for itemref in package
+        .descendants()
+        .filter(|node| node.has_tag_name((OPF_NS, "itemref")))
+    {
+        let href = match itemref.attribute("idref").and_then(|id| manifest.get(id)) {
+            Some(href) => href,
+            None => continue,
+        };
+        // A broken chapter shouldn't hide the rest of the book
+        let chapter = read_entry(&mut archive, &resolve_href(&package_path, href))
+            .and_then(|content| chapter(&content, chapters.len() + 1));
+        if let Ok(Some(chapter)) = chapter {
+            chapters.push(chapter);
+        }
+    }
+
+    Ok(Book {
+        title,
+        authors,
+        chapters,
+    })
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<String> {
+    let mut entry = archive.by_name(name)?;
+    let mut contents = String::new();
+    entry.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Hrefs in the package document are relative to it, eg: `Text/ch1.xhtml` in `OEBPS/content.opf` is `OEBPS/Text/ch1.xhtml`
+fn resolve_href(package_path: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut segments = package_path.split('/').collect::<Vec<&str>>();
+    segments.pop();
+    for segment in href.split('/') {
+        match segment {
+            "." | "" => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+/// The text of an XHTML content document, labeled by its first heading
+/// Documents without text, eg: a cover image, aren't chapters
+fn chapter(content: &str, number: usize) -> Result<Option<Section>> {
+    let content = if content.contains('&') {
+        HTML_ENTITIES
+            .iter()
+            .fold(content.to_string(), |content, (entity, reference)| {
+                content.replace(entity, reference)
+            })
+    } else {
+        content.to_string()
+    };
+    let document = Document::parse(&content)?;
+
+    let body = match document
+        .descendants()
+        .find(|node| node.has_tag_name((XHTML_NS, "body")))
+    {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    let chapter_text = text(body);
+    if chapter_text.is_empty() {
+        return Ok(None);
+    }
+
+    let heading = body
+        .descendants()
+        .find(|node| {
+            node.is_element()
+                && ["h1", "h2", "h3"].contains(&node.tag_name().name())
+                && node.tag_name().namespace() == Some(XHTML_NS)
+        })
+        .or_else(|| {
+            document
+                .descendants()
+                .find(|node| node.has_tag_name((XHTML_NS, "title")))
+        })
+        .map(text)
+        .filter(|heading| !heading.is_empty());
+
+    Ok(Some(Section {
+        label: heading.unwrap_or_else(|| format!("Chapter {}", number)),
+        text: chapter_text,
+    }))
+}
+
+/// FictionBook is a single XML document, the top level sections of the main body are its chapters
+fn read_fiction_book(contents: &[u8]) -> Result<Book> {
+    let contents = std::str::from_utf8(contents)?;
+    let document = Document::parse(contents)?;
+
+    let title_info = document
+        .descendants()
+        .find(|node| node.has_tag_name((FB2_NS, "title-info")))
+        .ok_or_else(|| anyhow!("The book has no description"))?;
+
+    let title = child(title_info, "book-title")
+        .map(text)
+        .filter(|title| !title.is_empty());
+    let authors = title_info
+        .children()
+        .filter(|node| node.has_tag_name((FB2_NS, "author")))
+        .map(|author| {
+            ["first-name", "middle-name", "last-name"]
+                .iter()
+                .filter_map(|name| child(author, name).map(text))
+                .filter(|name| !name.is_empty())
+                .collect::<Vec<String>>()
+                .join(" ")
+        })
+        .filter(|author| !author.is_empty())
+        .collect();
+
+    // Other bodies hold the notes and comments
+    let body = document
+        .root_element()
+        .children()
+        .find(|node| node.has_tag_name((FB2_NS, "body")) && node.attribute("name").is_none());
+    let chapters = body
+        .iter()
+        .flat_map(|body| body.children())
+        .filter(|node| node.has_tag_name((FB2_NS, "section")))
+        .map(|section| (child(section, "title").map(text), text(section)))
+        .filter(|(_, text)| !text.is_empty())
+        .enumerate()
+        .map(|(index, (title, text))| Section {
+            label: title
+                .filter(|title| !title.is_empty())
+                .unwrap_or_else(|| format!("Chapter {}", index + 1)),
+            text,
+        })
+        .collect();
+
+    Ok(Book {
+        title,
+        authors,
+        chapters,
+    })
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.has_tag_name((FB2_NS, name)))
+}
+
+/// The text under a node with block elements separated and whitespace collapsed
+fn text(node: Node) -> String {
+    let mut text = String::new();
+    collect_text(node, &mut text);
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn collect_text(node: Node, text: &mut String) {
+    for child in node.children() {
+        if child.is_text() {
+            text.push_str(child.text().unwrap_or_default());
+        } else if child.is_element() {
+            let name = child.tag_name().name();
+            if name == "script" || name == "style" {
+                continue;
+            }
+            let is_block = BLOCK_ELEMENTS.contains(&name);
+            if is_block {
+                text.push(' ');
+            }
+            collect_text(child, text);
+            if is_block {
+                text.push(' ');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tokio;
+    use contracts::file_to_process::new_file_to_process;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn test_indexing_epub_file() {
+        let test_file_path = Path::new("../../../test_files/Cats.epub");
+        let indexed_document = EbookIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.name, "The Book of Cats");
+        assert_eq!(
+            indexed_document.metadata,
+            vec![
+                (
+                    String::from("author"),
+                    MetadataValue::Text(String::from("Jane Whiskers"))
+                ),
+                (
+                    String::from("author"),
+                    MetadataValue::Text(String::from("John Paws"))
+                ),
+            ]
+        );
+        assert_eq!(
+            indexed_document.sections,
+            vec![
+                Section {
+                    label: String::from("Kittens"),
+                    text: String::from("Kittens Kittens sleep a lot. They love to play."),
+                },
+                Section {
+                    label: String::from("Old cats"),
+                    text: String::from("Old cats Old cats have soft paws."),
+                },
+            ]
+        );
+        assert_eq!(
+            indexed_document.body,
+            "Kittens Kittens sleep a lot. They love to play. Old cats Old cats have soft paws."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_indexing_fb2_file() {
+        let test_file_path = Path::new("../../../test_files/Cats.fb2");
+        let indexed_document = EbookIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.name, "Tales of Cats");
+        assert_eq!(
+            indexed_document.metadata,
+            vec![(
+                String::from("author"),
+                MetadataValue::Text(String::from("Jane Whiskers"))
+            )]
+        );
+        assert_eq!(indexed_document.sections.len(), 2);
+        assert_eq!(indexed_document.sections[1].label, "Old cats");
+        assert_eq!(
+            indexed_document.sections[1].text,
+            "Old cats Old cats have soft paws."
+        );
+    }
+
+    #[test]
+    fn test_resolving_hrefs() {
+        assert_eq!(
+            resolve_href("OEBPS/content.opf", "Text/ch1.xhtml#start"),
+            "OEBPS/Text/ch1.xhtml"
+        );
+        assert_eq!(
+            resolve_href("OEBPS/package/content.opf", "../ch1.xhtml"),
+            "OEBPS/ch1.xhtml"
+        );
+        assert_eq!(resolve_href("content.opf", "ch1.xhtml"), "ch1.xhtml");
+    }
+
+    #[test]
+    fn test_supports_ebook_extension() {
+        assert_eq!(true, EbookIndexer.supports_extension(OsStr::new("epub")));
+        assert_eq!(true, EbookIndexer.supports_extension(OsStr::new("fb2")));
+        assert_eq!(false, EbookIndexer.supports_extension(OsStr::new("mobi")));
+    }
+}
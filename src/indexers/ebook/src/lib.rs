@@ -0,0 +1 @@
+pub mod ebook_indexer;
@@ -0,0 +1,341 @@
+use common::anyhow::{Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::{span, warn, Level};
+use contracts::file_to_process::{calculate_hash, FileToProcess};
+use contracts::indexer::{DocumentSchema, EntrySchema, Indexer, MetadataValue};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+
+use mailparse::{parse_mail, DispositionType, MailHeaderMap, ParsedMail};
+
+pub struct EmailIndexer;
+
+impl Indexer for EmailIndexer {
+    fn supports_extension(&self, extension: &OsStr) -> bool {
+        extension == OsStr::new("eml") || extension == OsStr::new("mbox")
+    }
+
+    fn supported_extensions(&self) -> Vec<OsString> {
+        vec![OsString::from("eml"), OsString::from("mbox")]
+    }
+
+    /// Big emails and mboxes are read from disk
+    fn indexes_truncated_files(&self) -> bool {
+        true
+    }
+
+    fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
+        let path = file_to_process.path.to_str().unwrap();
+        span!(Level::INFO, "email_indexer: indexing email file", path).in_scope(|| {
+            if is_mbox(file_to_process) {
+                // Every message of an mbox is its own document, see index_entries
+                // The mbox itself is only found by its name
+                return Ok(DocumentSchema {
+                    name: file_to_process
+                        .path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    ..Default::default()
+                });
+            }
+
+            let contents = if file_to_process.is_truncated() {
+                fs::read(&file_to_process.path)?
+            } else {
+                file_to_process.contents.clone()
+            };
+            index_message(&contents).with_context(|| {
+                log_and_return_error_string(format!(
+                    "email_indexer: Failed to parse email at path: {:?}",
+                    file_to_process.path
+                ))
+            })
+        })
+    }
+
+    fn index_entries(&self, file_to_process: &FileToProcess) -> Result<Vec<EntrySchema>> {
+        let path = file_to_process.path.to_str().unwrap();
+        span!(Level::INFO, "email_indexer: indexing mbox messages", path).in_scope(|| {
+            if !is_mbox(file_to_process) {
+                return Ok(Vec::new());
+            }
+
+            // A message which can't be parsed is skipped, the others are still indexed
+            // Entries keep their position in the mbox, so they don't change when a message before them is broken
+            let mut entries = Vec::new();
+            let mut add_entry = |index: usize, message: Vec<u8>| match index_message(&message) {
+                Ok(document) => entries.push(EntrySchema {
+                    entry: (index + 1).to_string(),
+                    hash: calculate_hash(&message),
+                    size: message.len() as u64,
+                    document,
+                }),
+                Err(e) => warn!(
+                    "email_indexer: Skipping message {} of mbox at path: {:?}: {:?}",
+                    index + 1,
+                    file_to_process.path,
+                    e
+                ),
+            };
+
+            // Only the start of a big mbox is in memory, the rest is read from disk a message at a time
+            if file_to_process.is_truncated() {
+                let file = BufReader::new(File::open(&file_to_process.path)?);
+                split_mbox(file, &mut add_entry)
+            } else {
+                split_mbox(&file_to_process.contents[..], &mut add_entry)
+            }
+            .with_context(|| {
+                log_and_return_error_string(format!(
+                    "email_indexer: Failed to read mbox at path: {:?}",
+                    file_to_process.path
+                ))
+            })?;
+
+            Ok(entries)
+        })
+    }
+}
+
+fn is_mbox(file_to_process: &FileToProcess) -> bool {
+    file_to_process.path.extension() == Some(OsStr::new("mbox"))
+}
+
+/// Parses a single RFC 822 message
+/// The subject is used as the name and the decoded text parts make up the body
+fn index_message(raw_message: &[u8]) -> Result<DocumentSchema> {
+    let mail = span!(Level::INFO, "email_indexer: Parsing message")
+        .in_scope(|| parse_mail(raw_message))?;
+
+    let mut metadata = Vec::new();
+    for header in &["from", "to", "cc"] {
+        if let Some(value) = mail.headers.get_first_value(header) {
+            metadata.push((header.to_string(), MetadataValue::Text(value)));
+        }
+    }
+    if let Some(date) = mail
+        .headers
+        .get_first_value("date")
+        .and_then(|date| mailparse::dateparse(&date).ok())
+    {
+        metadata.push((String::from("date"), MetadataValue::I64(date)));
+    }
+
+    let body = span!(Level::INFO, "email_indexer: Decoding message body").in_scope(
+        || -> Result<String> {
+            let mut plain = Vec::new();
+            let mut html = Vec::new();
+            collect_text_parts(&mail, &mut plain, &mut html)?;
+            // Most mail clients send an html alternative of the plain text, only fall back to it when needed
+            if plain.is_empty() {
+                Ok(html
+                    .iter()
+                    .map(|part| strip_html(part))
+                    .collect::<Vec<String>>()
+                    .join(" "))
+            } else {
+                Ok(plain.join(" "))
+            }
+        },
+    )?;
+
+    Ok(DocumentSchema {
+        name: mail.headers.get_first_value("subject").unwrap_or_default(),
+        body,
+        metadata,
+        ..Default::default()
+    })
+}
+
+/// Walks the (possibly multipart) message and decodes every inline text part
+fn collect_text_parts(
+    mail: &ParsedMail,
+    plain: &mut Vec<String>,
+    html: &mut Vec<String>,
+) -> Result<()> {
+    if !mail.subparts.is_empty() {
+        for subpart in &mail.subparts {
+            collect_text_parts(subpart, plain, html)?;
+        }
+        return Ok(());
+    }
+
+    if mail.get_content_disposition().disposition == DispositionType::Attachment {
+        return Ok(());
+    }
+
+    match mail.ctype.mimetype.as_str() {
+        "text/plain" => plain.push(mail.get_body()?),
+        "text/html" => html.push(mail.get_body()?),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Drops html tags, leaving only the text in between them
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Splits an mbox archive into its messages, which are passed on in order with their index
+/// Messages are delimited by lines starting with "From ", escaped ">From " lines in bodies are restored
+fn split_mbox<R: BufRead>(
+    mut reader: R,
+    on_message: &mut impl FnMut(usize, Vec<u8>),
+) -> std::io::Result<()> {
+    let mut messages = 0;
+    let mut current: Option<Vec<u8>> = None;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+
+        if line.starts_with(b"From ") {
+            if let Some(message) = current.take() {
+                on_message(messages, message);
+                messages += 1;
+            }
+            current = Some(Vec::new());
+            continue;
+        }
+
+        if let Some(message) = current.as_mut() {
+            if is_escaped_from_line(&line) {
+                message.extend_from_slice(&line[1..]);
+            } else {
+                message.extend_from_slice(&line);
+            }
+        }
+    }
+
+    if let Some(message) = current {
+        on_message(messages, message);
+    }
+
+    Ok(())
+}
+
+fn is_escaped_from_line(line: &[u8]) -> bool {
+    let unquoted = line.iter().position(|&b| b != b'>').unwrap_or(0);
+    unquoted > 0 && line[unquoted..].starts_with(b"From ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tokio;
+    use contracts::file_to_process::new_file_to_process;
+    use std::path::{Path, PathBuf};
+
+    #[tokio::test]
+    async fn test_indexing_eml_file() {
+        let test_file_path = Path::new("../../../test_files/Cats.eml");
+        let indexed_document = EmailIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.name, "Cats are great");
+        assert!(indexed_document.body.contains("Cats have paws."));
+        assert!(indexed_document.body.contains("They're pretty cool"));
+        assert!(indexed_document.metadata.contains(&(
+            String::from("from"),
+            MetadataValue::Text(String::from("Teodor Voinea <teodor@example.com>"))
+        )));
+        assert!(indexed_document
+            .metadata
+            .contains(&(String::from("date"), MetadataValue::I64(1577880000))));
+    }
+
+    #[tokio::test]
+    async fn test_indexing_mbox_file() {
+        let test_file_path = Path::new("../../../test_files/Animals.mbox");
+        let file_to_process = new_file_to_process(test_file_path).await;
+
+        let indexed_document = EmailIndexer.index_file(&file_to_process).unwrap();
+        assert_eq!(indexed_document.name, "Animals.mbox");
+        assert!(indexed_document.body.is_empty());
+
+        let entries = EmailIndexer.index_entries(&file_to_process).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].entry, "1");
+        assert_eq!(entries[0].document.name, "Horses");
+        assert!(entries[0].document.body.contains("From the stables"));
+        assert_eq!(entries[1].entry, "2");
+        assert_eq!(entries[1].document.name, "Dogs");
+        assert!(entries[1].document.body.contains("Dogs also have paws"));
+    }
+
+    #[tokio::test]
+    async fn test_indexing_big_mbox_file() {
+        let test_file_path = Path::new("../../../test_files/Animals.mbox");
+        let mut file_to_process = new_file_to_process(test_file_path).await;
+        // Only the start of the mbox is in memory, as if it was bigger than MAX_CONTENTS_SIZE
+        file_to_process.contents.truncate(10);
+        assert!(file_to_process.is_truncated());
+        assert!(EmailIndexer.indexes_truncated_files());
+
+        let entries = EmailIndexer.index_entries(&file_to_process).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].document.name, "Horses");
+        assert_eq!(entries[1].document.name, "Dogs");
+    }
+
+    #[test]
+    fn test_broken_messages_of_an_mbox_are_skipped() {
+        let contents = b"From cats@example.com Wed Jan  1 12:00:00 2020\n\
+Subject: Cats\n\
+\n\
+Cats have paws.\n\
+From broken@example.com Wed Jan  1 12:00:00 2020\n\
+Subject: Broken\n\
+Content-Type: text/plain\n\
+Content-Transfer-Encoding: base64\n\
+\n\
+!!! not base64 !!!\n\
+From dogs@example.com Wed Jan  1 12:00:00 2020\n\
+Subject: Dogs\n\
+\n\
+Dogs have paws too.\n"
+            .to_vec();
+        let file_to_process = FileToProcess {
+            path: PathBuf::from("broken.mbox"),
+            hash: calculate_hash(&contents),
+            size: contents.len() as u64,
+            contents,
+        };
+
+        let entries = EmailIndexer.index_entries(&file_to_process).unwrap();
+        let names = entries
+            .iter()
+            .map(|entry| (entry.entry.as_str(), entry.document.name.as_str()))
+            .collect::<Vec<(&str, &str)>>();
+        assert_eq!(names, vec![("1", "Cats"), ("3", "Dogs")]);
+    }
+
+    #[test]
+    fn test_supports_email_extension() {
+        assert_eq!(true, EmailIndexer.supports_extension(OsStr::new("eml")));
+        assert_eq!(true, EmailIndexer.supports_extension(OsStr::new("mbox")));
+        assert_eq!(false, EmailIndexer.supports_extension(OsStr::new("msg")));
+    }
+}
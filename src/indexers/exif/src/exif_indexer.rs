@@ -1,13 +1,13 @@
 use common::error_adapter::log_and_return_error_string;
 use contracts::file_to_process::FileToProcess;
-use contracts::indexer::{DocumentSchema, Indexer};
+use contracts::indexer::{DocumentSchema, Indexer, MetadataValue};
 use std::ffi::{OsStr, OsString};
-use std::fs::File;
-use std::io::BufReader;
+use std::io::Cursor;
 
+use chrono::NaiveDate;
 use common::anyhow::{Context, Error, Result};
 use common::tracing::{span, Level};
-use exif::{Rational, Tag, Value};
+use exif::{DateTime, Exif, In, Rational, Tag, Value};
 use reverse_geocoder::{Locations, Record, ReverseGeocoder};
 
 lazy_static! {
@@ -28,7 +28,7 @@ impl Indexer for ExifIndexer {
     fn supported_extensions(&self) -> Vec<OsString> {
         vec![
             OsString::from("tif"),
-            OsString::from("tifd"),
+            OsString::from("tiff"),
             OsString::from("jpg"),
             OsString::from("jpeg"),
         ]
@@ -36,80 +36,219 @@ impl Indexer for ExifIndexer {
 
     fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
         let path = file_to_process.path.to_str().unwrap();
-        let file = File::open(path)?;
         span!(Level::INFO, "exif_indexer: indexing image file", path).in_scope(|| {
-            let reader = span!(Level::INFO, "exif_indexer: Loading exif data from image from memory").in_scope(|| {
-                exif::Reader::new().read_from_container(&mut BufReader::new(&file)).with_context(|| {
-                    log_and_return_error_string(format!(
-                        "exif_indexer: Failed to initialize exif reader for file at path: {:?}",
-                        file_to_process.path
-                    ))
-                })
+            let reader = span!(
+                Level::INFO,
+                "exif_indexer: Loading exif data from image from memory"
+            )
+            .in_scope(|| {
+                exif::Reader::new()
+                    .read_from_container(&mut Cursor::new(&file_to_process.contents))
+                    .with_context(|| {
+                        log_and_return_error_string(format!(
+                            "exif_indexer: Failed to initialize exif reader for file at path: {:?}",
+                            file_to_process.path
+                        ))
+                    })
             })?;
 
-            let mut lat = 0.0;
-            let mut lon = 0.0;
-
-            span!(Level::INFO, "exif_indexer: Processing exif fields").in_scope(|| {
-                let mut lat_direction = 0_u8 as char;
-                let mut lon_direction = 0_u8 as char;
-                for f in reader.fields() {
-                    match f.tag {
-                        Tag::GPSLatitudeRef => {
-                            if let Value::Ascii(val) = &f.value {
-                                lat_direction = val[0][0] as char;
-                            }
-                        }
-                        Tag::GPSLatitude => {
-                            if let Value::Rational(val) = &f.value {
-                                lat = value_to_deg(&val);
-                            }
-                        }
-                        Tag::GPSLongitudeRef => {
-                            if let Value::Ascii(val) = &f.value {
-                                lon_direction = val[0][0] as char;
-                            }
-                        }
-                        Tag::GPSLongitude => {
-                            if let Value::Rational(val) = &f.value {
-                                lon = value_to_deg(&val);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
+            let (metadata, coordinates) =
+                span!(Level::INFO, "exif_indexer: Processing exif fields")
+                    .in_scope(|| (camera_metadata(&reader), coordinates(&reader)));
 
-                if lat_direction != 'N' {
-                    lat *= -1.0;
-                }
+            let mut metadata = metadata;
+            let mut body = String::new();
 
-                if lon_direction != 'E' {
-                    lon *= -1.0;
+            // Photos without GPS tags have no location, looking up (0, 0) would give a bogus place
+            if let Some((lat, lon)) = coordinates {
+                metadata.push((String::from("latitude"), MetadataValue::F64(lat)));
+                metadata.push((String::from("longitude"), MetadataValue::F64(lon)));
+
+                // The place is looked up on a best effort basis, a photo whose place isn't found is still indexed
+                // The failure was logged by `place`
+                if let Ok((place_body, place_metadata)) = place(lat, lon) {
+                    body = place_body;
+                    metadata.extend(place_metadata);
                 }
-            });
-
-            let res = span!(Level::INFO, "exif_indexer: Look up the coordinates").in_scope(|| -> Result<&Record, Error>{
-                Ok(
-                    GEOCODER.search((lat, lon))
-                        .with_context(|| log_and_return_error_string(format!("exif_indexer: Failed to search for location in geocoder: lat = {:?} lon = {:?}", lat, lon)))?
-                        .record
-                )
-            })?;
+            }
 
             Ok(DocumentSchema {
-                name: file_to_process.path.file_name().unwrap().to_string_lossy().to_string(),
-                body: format!("{} {} {} {}", res.name, res.admin1, res.admin2, res.admin3),
+                name: file_to_process
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string(),
+                body,
+                metadata,
+                ..Default::default()
             })
         })
     }
 }
 
+/// The names of the place nearest to the coordinates, as a body and as facets from the city to the country
+/// Coordinates which aren't finite, eg: from a GPS tag with a denominator of 0, can't be looked up
+fn place(lat: f64, lon: f64) -> Result<(String, Vec<(String, MetadataValue)>)> {
+    let res = span!(Level::INFO, "exif_indexer: Look up the coordinates").in_scope(|| -> Result<&Record, Error>{
+        Ok(
+            GEOCODER.search((lat, lon))
+                .with_context(|| log_and_return_error_string(format!("exif_indexer: Failed to search for location in geocoder: lat = {:?} lon = {:?}", lat, lon)))?
+                .record
+        )
+    })?;
+    let body = format!("{} {} {} {}", res.name, res.admin1, res.admin2, res.admin3);
+
+    let mut metadata = Vec::new();
+    for (field_name, place) in [
+        ("city", &res.name),
+        ("admin2", &res.admin2),
+        ("admin1", &res.admin1),
+        ("country", &res.admin3),
+    ] {
+        if !place.is_empty() {
+            metadata.push((
+                String::from(field_name),
+                MetadataValue::Facet(vec![place.clone()]),
+            ));
+        }
+    }
+    Ok((body, metadata))
+}
+
+/// The camera, lens, capture time, orientation and dimensions of the photo
+/// Only the tags which are present in the file are returned
+fn camera_metadata(reader: &Exif) -> Vec<(String, MetadataValue)> {
+    let mut metadata = Vec::new();
+
+    for (field_name, tag) in [
+        ("camera_make", Tag::Make),
+        ("camera_model", Tag::Model),
+        ("lens", Tag::LensModel),
+    ] {
+        if let Some(text) = ascii_value(reader, tag) {
+            metadata.push((String::from(field_name), MetadataValue::Text(text)));
+        }
+    }
+
+    if let Some(taken) = capture_timestamp(reader) {
+        metadata.push((String::from("date"), MetadataValue::I64(taken)));
+    }
+
+    if let Some(orientation) = uint_value(reader, &[Tag::Orientation]) {
+        metadata.push((
+            String::from("orientation"),
+            MetadataValue::I64(orientation as i64),
+        ));
+    }
+
+    // The Exif dimensions describe the actual image, the TIFF ones are the fallback
+    if let Some(width) = uint_value(reader, &[Tag::PixelXDimension, Tag::ImageWidth]) {
+        metadata.push((String::from("width"), MetadataValue::I64(width as i64)));
+    }
+    if let Some(height) = uint_value(reader, &[Tag::PixelYDimension, Tag::ImageLength]) {
+        metadata.push((String::from("height"), MetadataValue::I64(height as i64)));
+    }
+
+    metadata
+}
+
+fn ascii_value(reader: &Exif, tag: Tag) -> Option<String> {
+    match &reader.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => {
+            let text = String::from_utf8_lossy(values.first()?)
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string();
+            if text.is_empty() {
+                None
+            } else {
+                Some(text)
+            }
+        }
+        _ => None,
+    }
+}
+
+fn uint_value(reader: &Exif, tags: &[Tag]) -> Option<u32> {
+    tags.iter()
+        .filter_map(|tag| reader.get_field(*tag, In::PRIMARY))
+        .find_map(|field| field.value.get_uint(0))
+}
+
+/// When the photo was taken as a unix timestamp
+/// Photos without a time zone offset are assumed to be in UTC
+fn capture_timestamp(reader: &Exif) -> Option<i64> {
+    let field = [Tag::DateTimeOriginal, Tag::DateTime]
+        .iter()
+        .find_map(|tag| reader.get_field(*tag, In::PRIMARY))?;
+    let mut date_time = match &field.value {
+        Value::Ascii(values) => DateTime::from_ascii(values.first()?).ok()?,
+        _ => return None,
+    };
+
+    let offset_tag = if field.tag == Tag::DateTimeOriginal {
+        Tag::OffsetTimeOriginal
+    } else {
+        Tag::OffsetTime
+    };
+    if let Some(Value::Ascii(values)) = reader
+        .get_field(offset_tag, In::PRIMARY)
+        .map(|offset| &offset.value)
+    {
+        if let Some(offset) = values.first() {
+            let _ = date_time.parse_offset(offset);
+        }
+    }
+
+    let timestamp = NaiveDate::from_ymd_opt(
+        date_time.year as i32,
+        date_time.month as u32,
+        date_time.day as u32,
+    )?
+    .and_hms_opt(
+        date_time.hour as u32,
+        date_time.minute as u32,
+        date_time.second as u32,
+    )?
+    .timestamp();
+
+    Some(timestamp - date_time.offset.unwrap_or(0) as i64 * 60)
+}
+
+/// The latitude and longitude in decimal degrees, if the photo has GPS tags
+fn coordinates(reader: &Exif) -> Option<(f64, f64)> {
+    let lat = gps_degrees(reader, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?;
+    let lon = gps_degrees(reader, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?;
+    Some((lat, lon))
+}
+
+/// Coordinates are positive unless their reference is the negative hemisphere
+fn gps_degrees(reader: &Exif, tag: Tag, reference_tag: Tag, negative_reference: u8) -> Option<f64> {
+    let degrees = match &reader.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(val) if val.len() >= 3 => value_to_deg(val),
+        _ => return None,
+    };
+
+    let reference = reader
+        .get_field(reference_tag, In::PRIMARY)
+        .and_then(|field| match &field.value {
+            Value::Ascii(val) => val.first().and_then(|val| val.first()).copied(),
+            _ => None,
+        });
+
+    if reference == Some(negative_reference) {
+        Some(-degrees)
+    } else {
+        Some(degrees)
+    }
+}
+
 fn value_to_deg(val: &[Rational]) -> f64 {
     def_to_dec_dec(val[0].to_f64(), val[1].to_f64(), val[2].to_f64())
 }
 
 fn def_to_dec_dec(deg: f64, min: f64, sec: f64) -> f64 {
-    deg + min / 30.0 + sec / 3600.0
+    deg + min / 60.0 + sec / 3600.0
 }
 
 #[cfg(test)]
@@ -127,7 +266,77 @@ mod tests {
             .unwrap();
 
         assert_eq!(indexed_document.name, "IMG_2551.jpeg");
-        assert_eq!(indexed_document.body, "Pacureti Prahova Comuna Pacureti RO");
+        assert_eq!(indexed_document.body, "Otopeni Ilfov Comuna Otopeni RO");
+        assert!(indexed_document.metadata.contains(&(
+            String::from("camera_model"),
+            MetadataValue::Text(String::from("iPhone 6s"))
+        )));
+        assert!(indexed_document.metadata.contains(&(
+            String::from("latitude"),
+            MetadataValue::F64(44.57101111111111)
+        )));
+        assert!(indexed_document.metadata.contains(&(
+            String::from("longitude"),
+            MetadataValue::F64(26.077102777777778)
+        )));
+        assert!(indexed_document.metadata.contains(&(
+            String::from("city"),
+            MetadataValue::Facet(vec![String::from("Otopeni")])
+        )));
+        assert!(indexed_document.metadata.contains(&(
+            String::from("admin1"),
+            MetadataValue::Facet(vec![String::from("Ilfov")])
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_indexing_exif_file_without_gps() {
+        let test_file_path = Path::new("../../../test_files/NoGps.jpg");
+        let indexed_document = ExifIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.body, "");
+        assert_eq!(
+            indexed_document.metadata,
+            vec![
+                (
+                    String::from("camera_make"),
+                    MetadataValue::Text(String::from("Podium"))
+                ),
+                (
+                    String::from("camera_model"),
+                    MetadataValue::Text(String::from("Test Camera"))
+                ),
+                (
+                    String::from("lens"),
+                    MetadataValue::Text(String::from("Test Lens 50mm"))
+                ),
+                // 2021-05-01 12:30:00 +02:00
+                (String::from("date"), MetadataValue::I64(1619865000)),
+                (String::from("orientation"), MetadataValue::I64(6)),
+                (String::from("width"), MetadataValue::I64(640)),
+                (String::from("height"), MetadataValue::I64(480)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_degrees_minutes_seconds_conversion() {
+        assert_eq!(def_to_dec_dec(45.0, 30.0, 0.0), 45.5);
+        assert_eq!(def_to_dec_dec(10.0, 0.0, 36.0), 10.01);
+    }
+
+    #[test]
+    fn test_place_of_coordinates() {
+        let (body, metadata) = place(48.8566, 2.3522).unwrap();
+        assert!(body.contains("Paris"));
+        assert!(metadata.contains(&(
+            String::from("country"),
+            MetadataValue::Facet(vec![String::from("FR")])
+        )));
+
+        assert!(place(f64::NAN, 2.3522).is_err());
     }
 
     #[test]
@@ -0,0 +1,365 @@
+use common::anyhow::{anyhow, Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::{span, Level};
+use contracts::file_to_process::FileToProcess;
+use contracts::indexer::{DocumentSchema, Indexer, MetadataValue};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+
+use lofty::config::ParseOptions;
+use lofty::file::FileType;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use matroska::{Matroska, Settings, TagValue, Tracktype};
+use mp4::{Mp4Reader, TrackType};
+
+const EXTENSIONS: [&str; 6] = ["mp3", "flac", "ogg", "m4a", "mp4", "mkv"];
+
+/// Indexes the tags and stream properties of audio and video files
+/// The streams themselves are never decoded, only the headers and tags are read
+pub struct MediaIndexer;
+
+impl Indexer for MediaIndexer {
+    fn supports_extension(&self, extension: &OsStr) -> bool {
+        EXTENSIONS
+            .iter()
+            .any(|supported| extension == OsStr::new(supported))
+    }
+
+    fn supported_extensions(&self) -> Vec<OsString> {
+        EXTENSIONS.iter().map(OsString::from).collect()
+    }
+
+    /// Tags of big files are read from disk
+    fn indexes_truncated_files(&self) -> bool {
+        true
+    }
+
+    fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
+        let path = file_to_process.path.to_str().unwrap();
+        span!(Level::INFO, "media_indexer: indexing media file", path).in_scope(|| {
+            let extension = file_to_process
+                .path
+                .extension()
+                .and_then(OsStr::to_str)
+                .unwrap_or_default()
+                .to_lowercase();
+
+            let tags = span!(Level::INFO, "media_indexer: Reading tags")
+                .in_scope(|| {
+                    // Big files only have their start in memory, the readers seek to the parts they need on disk
+                    if file_to_process.is_truncated() {
+                        let file = BufReader::new(File::open(&file_to_process.path)?);
+                        read_tags(&extension, file, file_to_process.size)
+                    } else {
+                        let contents = Cursor::new(&file_to_process.contents);
+                        read_tags(&extension, contents, file_to_process.size)
+                    }
+                })
+                .with_context(|| {
+                    log_and_return_error_string(format!(
+                        "media_indexer: Failed to read tags from file at path: {:?}",
+                        file_to_process.path
+                    ))
+                })?;
+
+            Ok(DocumentSchema {
+                name: file_to_process
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string(),
+                body: tags.text(),
+                metadata: tags.metadata(),
+                ..Default::default()
+            })
+        })
+    }
+}
+
+/// What the tags and stream headers of a media file say about it
+#[derive(Debug, Default)]
+struct MediaTags {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    year: Option<u32>,
+    duration: Option<Duration>,
+    /// The codec of every audio and video stream, eg: ["h264", "aac"]
+    codecs: Vec<String>,
+    /// Width and height of the first video stream
+    resolution: Option<(u64, u64)>,
+}
+
+impl MediaTags {
+    /// The title, artist and album, so they can be found without naming the field
+    fn text(&self) -> String {
+        [&self.title, &self.artist, &self.album]
+            .iter()
+            .filter_map(|tag| tag.as_deref())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    fn metadata(&self) -> Vec<(String, MetadataValue)> {
+        let mut metadata = Vec::new();
+
+        for (field_name, tag) in [
+            ("media_title", &self.title),
+            ("artist", &self.artist),
+            ("album", &self.album),
+        ] {
+            if let Some(text) = tag {
+                metadata.push((String::from(field_name), MetadataValue::Text(text.clone())));
+            }
+        }
+
+        if let Some(year) = self.year {
+            metadata.push((String::from("year"), MetadataValue::I64(year as i64)));
+        }
+        if let Some(duration) = self.duration {
+            metadata.push((
+                String::from("duration"),
+                MetadataValue::I64(duration.as_secs() as i64),
+            ));
+        }
+        for codec in &self.codecs {
+            metadata.push((String::from("codec"), MetadataValue::Text(codec.clone())));
+        }
+        if let Some((width, height)) = self.resolution {
+            metadata.push((String::from("width"), MetadataValue::I64(width as i64)));
+            metadata.push((String::from("height"), MetadataValue::I64(height as i64)));
+        }
+
+        metadata
+    }
+}
+
+fn read_tags<R: Read + Seek>(extension: &str, reader: R, size: u64) -> Result<MediaTags> {
+    match extension {
+        "mkv" => matroska_tags(reader),
+        "mp4" | "m4a" => mp4_tags(reader, size),
+        _ => audio_tags(reader, true),
+    }
+}
+
+/// ID3, Vorbis comments and the other tag formats lofty knows about
+/// Cover art isn't read, so a big embedded picture doesn't end up in memory
+fn audio_tags<R: Read + Seek>(reader: R, with_properties: bool) -> Result<MediaTags> {
+    let tagged_file = Probe::new(reader)
+        .options(
+            ParseOptions::new()
+                .read_cover_art(false)
+                .read_properties(with_properties),
+        )
+        .guess_file_type()?
+        .read()?;
+
+    let mut tags = MediaTags::default();
+    if with_properties {
+        tags.duration = Some(tagged_file.properties().duration());
+        tags.codecs.extend(audio_codec(tagged_file.file_type()));
+    }
+    if let Some(tag) = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())
+    {
+        tags.title = tag.title().map(|title| title.to_string());
+        tags.artist = tag.artist().map(|artist| artist.to_string());
+        tags.album = tag.album().map(|album| album.to_string());
+        tags.year = tag.year();
+    }
+    Ok(tags)
+}
+
+fn audio_codec(file_type: FileType) -> Option<String> {
+    let codec = match file_type {
+        FileType::Mpeg => "mp3",
+        FileType::Flac => "flac",
+        FileType::Vorbis => "vorbis",
+        FileType::Opus => "opus",
+        FileType::Speex => "speex",
+        _ => return None,
+    };
+    Some(String::from(codec))
+}
+
+/// The tags are read by lofty, the streams by the mp4 crate since lofty only knows about audio streams
+fn mp4_tags<R: Read + Seek>(mut reader: R, size: u64) -> Result<MediaTags> {
+    // Videos often have no tags, their streams are still worth indexing
+    let mut tags = audio_tags(&mut reader, false).unwrap_or_default();
+
+    reader.seek(SeekFrom::Start(0))?;
+    let mp4 = Mp4Reader::read_header(reader, size)?;
+    tags.duration = Some(mp4.duration());
+
+    let mut tracks = mp4.tracks().values().collect::<Vec<_>>();
+    tracks.sort_by_key(|track| track.track_id());
+    for track in tracks {
+        let codec = track
+            .media_type()
+            .map(|media_type| media_type.to_string())
+            .or_else(|_| track.box_type().map(|box_type| box_type.to_string()));
+        if let Ok(codec) = codec {
+            tags.codecs.push(codec.to_lowercase());
+        }
+        if tags.resolution.is_none() && matches!(track.track_type(), Ok(TrackType::Video)) {
+            tags.resolution = Some((track.width() as u64, track.height() as u64));
+        }
+    }
+    Ok(tags)
+}
+
+fn matroska_tags<R: Read + Seek>(reader: R) -> Result<MediaTags> {
+    // The matroska crate asserts on some malformed files instead of returning an error
+    let mkv = panic::catch_unwind(AssertUnwindSafe(|| Matroska::open(reader)))
+        .map_err(|_| anyhow!("Malformed Matroska file"))??;
+
+    let simple_tags = mkv
+        .tags
+        .iter()
+        .flat_map(|tag| tag.simple.iter())
+        .filter_map(|simple| match &simple.value {
+            Some(TagValue::String(value)) => Some((simple.name.to_uppercase(), value.clone())),
+            _ => None,
+        })
+        .collect::<Vec<(String, String)>>();
+    let tag = |name: &str| {
+        simple_tags
+            .iter()
+            .find(|(tag_name, _)| tag_name == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    let mut tags = MediaTags {
+        title: mkv.info.title.clone().or_else(|| tag("TITLE")),
+        artist: tag("ARTIST"),
+        album: tag("ALBUM"),
+        year: tag("DATE_RELEASED")
+            .or_else(|| tag("DATE_RECORDED"))
+            .and_then(|date| date.get(..4)?.parse().ok()),
+        duration: mkv.info.duration,
+        ..Default::default()
+    };
+
+    for track in &mkv.tracks {
+        if !matches!(track.tracktype, Tracktype::Video | Tracktype::Audio) {
+            continue;
+        }
+        tags.codecs.push(matroska_codec(&track.codec_id));
+        if let Settings::Video(video) = &track.settings {
+            if tags.resolution.is_none() {
+                tags.resolution = Some((video.pixel_width, video.pixel_height));
+            }
+        }
+    }
+    Ok(tags)
+}
+
+/// Matroska codec ids are named like "V_MPEG4/ISO/AVC", the common ones are renamed like the mp4 ones
+fn matroska_codec(codec_id: &str) -> String {
+    match codec_id {
+        "V_MPEG4/ISO/AVC" => String::from("h264"),
+        "V_MPEGH/ISO/HEVC" => String::from("h265"),
+        "A_MPEG/L3" => String::from("mp3"),
+        _ => codec_id
+            .trim_start_matches("V_")
+            .trim_start_matches("A_")
+            .to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tokio;
+    use contracts::file_to_process::new_file_to_process;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn test_indexing_mp3_file() {
+        let test_file_path = Path::new("../../../test_files/Purring.mp3");
+        let indexed_document = MediaIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.name, "Purring.mp3");
+        assert_eq!(indexed_document.body, "Purring The Cats Whiskers");
+        assert_eq!(
+            indexed_document.metadata,
+            vec![
+                (
+                    String::from("media_title"),
+                    MetadataValue::Text(String::from("Purring"))
+                ),
+                (
+                    String::from("artist"),
+                    MetadataValue::Text(String::from("The Cats"))
+                ),
+                (
+                    String::from("album"),
+                    MetadataValue::Text(String::from("Whiskers"))
+                ),
+                (String::from("year"), MetadataValue::I64(2019)),
+                (String::from("duration"), MetadataValue::I64(2)),
+                (
+                    String::from("codec"),
+                    MetadataValue::Text(String::from("mp3"))
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_indexing_mkv_file() {
+        let test_file_path = Path::new("../../../test_files/CatVideo.mkv");
+        let indexed_document = MediaIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.body, "Cat Video The Cats");
+        assert_eq!(
+            indexed_document.metadata,
+            vec![
+                (
+                    String::from("media_title"),
+                    MetadataValue::Text(String::from("Cat Video"))
+                ),
+                (
+                    String::from("artist"),
+                    MetadataValue::Text(String::from("The Cats"))
+                ),
+                (String::from("year"), MetadataValue::I64(2020)),
+                (String::from("duration"), MetadataValue::I64(95)),
+                (
+                    String::from("codec"),
+                    MetadataValue::Text(String::from("vp9"))
+                ),
+                (
+                    String::from("codec"),
+                    MetadataValue::Text(String::from("opus"))
+                ),
+                (String::from("width"), MetadataValue::I64(1280)),
+                (String::from("height"), MetadataValue::I64(720)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_matroska_codec_names() {
+        assert_eq!(matroska_codec("V_MPEG4/ISO/AVC"), "h264");
+        assert_eq!(matroska_codec("A_OPUS"), "opus");
+    }
+
+    #[test]
+    fn test_supports_media_extension() {
+        assert_eq!(true, MediaIndexer.supports_extension(OsStr::new("mp3")));
+        assert_eq!(true, MediaIndexer.supports_extension(OsStr::new("flac")));
+        assert_eq!(true, MediaIndexer.supports_extension(OsStr::new("mkv")));
+        assert_eq!(false, MediaIndexer.supports_extension(OsStr::new("wav")));
+    }
+}
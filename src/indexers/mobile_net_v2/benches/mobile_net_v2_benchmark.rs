@@ -7,13 +7,19 @@ use criterion::*;
 use contracts::file_to_process::new_file_to_process;
 use contracts::indexer::Indexer;
 use mobile_net_v2_indexer::mobile_net_v2_indexer::MobileNetV2Indexer;
-use std::path::Path;
+use mobile_net_v2_indexer::model_registry::{configure, ClassifierConfig};
+use std::path::{Path, PathBuf};
 use tokio::runtime::Runtime;
 
 use common::tokio;
 
 fn bench_indexing_mobile_net_v2_file(c: &mut Criterion) {
     let rt = Runtime::new().unwrap();
+    configure(ClassifierConfig {
+        models_directory: PathBuf::from("../../../models"),
+        ..Default::default()
+    })
+    .unwrap();
     let test_file_path = Path::new("../../../test_files/IMG_2551.jpeg");
     let ftp = rt.block_on(new_file_to_process(test_file_path));
 
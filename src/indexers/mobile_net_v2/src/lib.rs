@@ -1 +1,3 @@
 pub mod mobile_net_v2_indexer;
+pub mod model_registry;
+pub mod perceptual_hash;
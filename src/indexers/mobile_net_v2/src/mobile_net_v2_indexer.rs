@@ -1,59 +1,17 @@
 use contracts::file_to_process::FileToProcess;
-use contracts::indexer::{DocumentSchema, Indexer};
+use contracts::indexer::{DocumentSchema, Indexer, MetadataValue};
 use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
 use std::io::Cursor;
 
 use common::anyhow;
-use common::anyhow::{Error, Result};
+use common::anyhow::Result;
 use common::tracing::{span, Level};
 use image::ImageFormat;
 use once_cell::sync::Lazy;
-use tract_core::ndarray;
-use tract_tensorflow::prelude::*;
-
-static MODEL: Lazy<TypedModel> = Lazy::new(|| {
-    span!(Level::INFO, "mobile_net_v2_indexer: Preparing typed model").in_scope(|| {
-        let mut model = span!(Level::INFO, "mobile_net_v2_indexer: Loading model").in_scope(|| {
-            // load the model
-            let model_bytes = include_bytes!("../../../../models/mobilenet_v2_1.4_224_frozen.pb");
-            let mut model_bytes = Cursor::new(&model_bytes[..]);
-            tract_tensorflow::tensorflow()
-                .model_for_read(&mut model_bytes)
-                .unwrap()
-            // .expect(&log_and_return_error_string(
-            //     "mobile_net_v2_indexer: Failed to read model from bytes".to_string(),
-            // ));
-        });
-
-        // specify input type and shape
-        model
-            .set_input_fact(
-                0,
-                InferenceFact::dt_shape(f32::datum_type(), tvec!(1, 224, 224, 3)),
-            )
-            .unwrap();
-
-        // .expect(&log_and_return_error_string(
-        //     "mobile_net_v2_indexer: Failed to specify input type and shape for model"
-        //         .to_string(),
-        // ));
 
-        // optimize the model and get an execution plan
-        let model = span!(Level::INFO, "mobile_net_v2_indexer: Optimize model")
-            .in_scope(|| model.into_optimized().unwrap());
-
-        // .expect("mobile_net_v2_indexer: Failed to optimize model");
-        model
-    })
-});
-
-static LABELS: Lazy<Vec<&'static str>> = Lazy::new(|| {
-    let labels: Vec<&str> = include_str!("../../../../models/imagenet_slim_labels.txt")
-        .lines()
-        .collect();
-    labels
-});
+use crate::model_registry::{config, Classification, REGISTRY};
+use crate::perceptual_hash::difference_hash;
 
 static IMAGE_FORMATS: Lazy<HashMap<OsString, image::ImageFormat>> = Lazy::new(|| {
     let mut map = HashMap::new();
@@ -96,21 +54,12 @@ impl Indexer for MobileNetV2Indexer {
         ]
     }
 
-    // https://github.com/snipsco/tract/tree/master/examples/tensorflow-mobilenet-v2
+    /// Classifies the image with every model in the registry
+    /// Only the most confident labels are kept, see `ClassifierConfig`
+    /// The perceptual hash and the embedding of the image are stored to find similar images
     fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
         let path = file_to_process.path.to_str().unwrap();
         span!(Level::INFO, "mobile_net_v2_indexer: indexing image file", path).in_scope(|| {
-            let t_model: &TypedModel = &*MODEL;
-            let plan = span!(Level::INFO, "mobile_net_v2_indexer: Creating plan").in_scope(|| {
-                match TypedSimplePlan::new(t_model) {
-                    Ok(plan) => Ok(plan),
-                    Err(e) => Err(anyhow::anyhow!(format!(
-                        "mobile_net_v2_indexer: Failed to create plan for model with additional error info {:?}",
-                        e
-                    )))
-                }
-            })?;
-
             let image = span!(Level::INFO, "mobile_net_v2_indexer: Load image").in_scope(|| {
                 let image_format = match IMAGE_FORMATS.get(&file_to_process.path.extension().unwrap().to_os_string()) {
                     Some(image_format) => Ok(image_format),
@@ -119,7 +68,6 @@ impl Indexer for MobileNetV2Indexer {
                     )))
                 }?.clone();
 
-                // open image, resize it and make a Tensor out of it
                 match image::io::Reader::with_format(Cursor::new(&file_to_process.contents), image_format).decode() {
                     Ok(image) => Ok(image),
                     Err(e) => Err(anyhow::anyhow!(format!(
@@ -130,61 +78,53 @@ impl Indexer for MobileNetV2Indexer {
                 // image crate seems to be more tolerant to malformed image filies using the open function
             })?;
 
-            let image: Tensor = span!(Level::INFO, "mobile_net_v2_indexer: Pre-process image").in_scope(|| {
-                let resized =
-                    image::imageops::resize(&image, 224, 224, image::imageops::FilterType::Triangle);
+            let perceptual_hash = span!(Level::INFO, "mobile_net_v2_indexer: Hash image")
+                .in_scope(|| difference_hash(&image));
 
-                ndarray::Array4::from_shape_fn((1, 224, 224, 3), |(_, y, x, c)| {
-                    f32::from(resized[(x as _, y as _)][c]) / 255.0
-                })
-                .into()
-            });
+            // Without any models there's nothing else to learn about the image
+            let Classification { labels, embedding } = if REGISTRY.is_empty() {
+                Classification::default()
+            } else {
+                span!(Level::INFO, "mobile_net_v2_indexer: Classify image")
+                    .in_scope(|| REGISTRY.classify(&image, config().top_k, config().threshold))?
+            };
 
-            // run the plan on the input
-            let result = span!(Level::INFO, "mobile_net_v2_indexer: Run image through model").in_scope(||{
-                match plan.run(tvec!(image)) {
-                    Ok(result) => Ok(result),
-                    Err(e) => Err(anyhow::anyhow!(format!(
-                        "mobile_net_v2_indexer: Failed to run the image through the model with additional error info {:?}",
-                        e
-                    )))
-                }
-            })?;
-
-            let body_res = span!(Level::INFO, "mobile_net_v2_indexer: Map model output").in_scope(|| -> Result<&&str, Error> {
-                // find and display the max value with its index
-                let best = match result[0].to_array_view::<f32>() {
-                    Ok(arr) => Ok(arr),
-                    Err(e) => Err(anyhow::anyhow!(format!(
-                        "mobile_net_v2_indexer: Failed to convert to array view with additional error info {:?}",
-                        e
-                    )))
-                }?
+            let body = labels
                 .iter()
-                .cloned()
-                .zip(1..)
-                .max_by(|a, b| {
-                    a.0.partial_cmp(&b.0).unwrap()
-                    // .expect(&log_and_return_error_string(
-                    //     "mobile_net_v2_indexer: Failed to partial compare while sorting".to_string(),
-                    // ))
-                });
-
-                if let Some((_score, index)) = best {
-                    return Ok(LABELS.get(index as usize - 1).unwrap());
-                    // .with_context(|| {
-                    //     log_and_return_error_string(format!(
-                    //         "mobile_net_v2_indexer: Failed to get label associated with model result"
-                    //     ))
-                    // })?;
-                }
-
-                Ok(&"")
-            })?;
+                .map(|(label, _)| label.as_str())
+                .collect::<Vec<&str>>()
+                .join(" ");
+
+            // Each label is stored at the same position as its confidence
+            let mut metadata: Vec<(String, MetadataValue)> = labels
+                .into_iter()
+                .flat_map(|(label, confidence)| {
+                    vec![
+                        (String::from("labels"), MetadataValue::Text(label)),
+                        (
+                            String::from("label_scores"),
+                            MetadataValue::F64(confidence as f64),
+                        ),
+                    ]
+                })
+                .collect();
+            metadata.push((
+                String::from("perceptual_hash"),
+                MetadataValue::I64(perceptual_hash as i64),
+            ));
+            if let Some(embedding) = embedding {
+                let bytes = embedding
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes().to_vec())
+                    .collect();
+                metadata.push((String::from("image_embedding"), MetadataValue::Bytes(bytes)));
+            }
 
             Ok(DocumentSchema {
                 name: file_to_process.path(),
-                body: body_res.to_string(),
+                body,
+                metadata,
+                ..Default::default()
             })
         })
     }
@@ -193,19 +133,46 @@ impl Indexer for MobileNetV2Indexer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model_registry::{configure, ClassifierConfig};
     use common::tokio;
     use contracts::file_to_process::new_file_to_process;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
+    // Configures the classifier for the whole test binary, so it's only run on its own: `cargo test -- --ignored`
     #[tokio::test]
+    #[ignore = "needs the MobileNetV2 model from `models/mobilenet_v2.json` to be downloaded"]
     async fn test_indexing_mobile_net_v2_file() {
+        let _ = configure(ClassifierConfig {
+            models_directory: PathBuf::from("../../../models"),
+            ..Default::default()
+        });
         let test_file_path = Path::new("../../../test_files/IMG_2551.jpeg");
         let indexed_document = MobileNetV2Indexer
             .index_file(&new_file_to_process(test_file_path).await)
             .unwrap();
 
         assert_eq!(indexed_document.name, "../../../test_files/IMG_2551.jpeg");
-        assert_eq!(indexed_document.body, "eggnog");
+        assert!(indexed_document.body.starts_with("eggnog"));
+        assert_eq!(
+            indexed_document.metadata[0],
+            (
+                String::from("labels"),
+                MetadataValue::Text(String::from("eggnog"))
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_perceptual_hash_without_labels() {
+        let test_file_path = Path::new("../../../test_files/IMG_2551.jpeg");
+        let file_to_process = new_file_to_process(test_file_path).await;
+        let image = image::load_from_memory(&file_to_process.contents).unwrap();
+        let indexed_document = MobileNetV2Indexer.index_file(&file_to_process).unwrap();
+
+        assert!(indexed_document.metadata.contains(&(
+            String::from("perceptual_hash"),
+            MetadataValue::I64(difference_hash(&image) as i64)
+        )));
     }
 
     #[test]
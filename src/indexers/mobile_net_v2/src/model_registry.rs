@@ -0,0 +1,461 @@
+use common::anyhow;
+use common::anyhow::{Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::{error, info, span, Level};
+use image::DynamicImage;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Deserialize;
+use tract_core::ndarray;
+use tract_onnx::prelude::*;
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+static CONFIG: OnceCell<ClassifierConfig> = OnceCell::new();
+
+/// Every model in the configured models directory, loaded the first time an image is classified
+pub static REGISTRY: Lazy<ModelRegistry> = Lazy::new(|| {
+    span!(Level::INFO, "model_registry: Loading models")
+        .in_scope(|| ModelRegistry::load(&config().models_directory))
+});
+
+/// How images are classified
+#[derive(Debug, Clone)]
+pub struct ClassifierConfig {
+    /// Where the models and their manifests are
+    pub models_directory: PathBuf,
+    /// How many labels each model can give an image
+    pub top_k: usize,
+    /// Labels with a lower confidence than this are dropped
+    pub threshold: f32,
+}
+
+impl Default for ClassifierConfig {
+    fn default() -> Self {
+        ClassifierConfig {
+            models_directory: PathBuf::from("models"),
+            top_k: 5,
+            threshold: 0.1,
+        }
+    }
+}
+
+/// Sets up the classifier, this has to happen before the first image is indexed
+pub fn configure(classifier_config: ClassifierConfig) -> Result<()> {
+    CONFIG
+        .set(classifier_config)
+        .map_err(|_| anyhow::anyhow!("model_registry: The classifier was already configured"))
+}
+
+/// The configuration of the classifier, the default one if it was never configured
+pub fn config() -> &'static ClassifierConfig {
+    CONFIG.get_or_init(ClassifierConfig::default)
+}
+
+/// The file formats models can be loaded from
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelFormat {
+    Onnx,
+    Tensorflow,
+}
+
+/// How the pixels of the input image are laid out
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Batch, height, width, channels, eg: TensorFlow models
+    Nhwc,
+    /// Batch, channels, height, width, eg: most ONNX models
+    Nchw,
+}
+
+/// Describes a model in the models directory, read from a `<name>.json` file
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelManifest {
+    /// The model file, relative to the models directory
+    pub model: PathBuf,
+    /// A file with one label per line, in the order of the model outputs
+    pub labels: PathBuf,
+    /// Guessed from the extension of the model file when it isn't set
+    #[serde(default)]
+    pub format: Option<ModelFormat>,
+    /// Width and height of the square image the model expects
+    #[serde(default = "default_input_size")]
+    pub input_size: usize,
+    #[serde(default = "default_layout")]
+    pub layout: Layout,
+    /// Each channel is scaled to [0, 1], then normalized as (value - mean) / std
+    #[serde(default = "default_mean")]
+    pub mean: [f32; 3],
+    #[serde(default = "default_std")]
+    pub std: [f32; 3],
+    /// Models which output logits instead of probabilities need a softmax
+    #[serde(default)]
+    pub softmax: bool,
+    /// The node whose output is used as the embedding of the image, eg: the layer before the logits
+    #[serde(default)]
+    pub features: Option<String>,
+}
+
+fn default_input_size() -> usize {
+    224
+}
+
+fn default_layout() -> Layout {
+    Layout::Nhwc
+}
+
+fn default_mean() -> [f32; 3] {
+    [0.0, 0.0, 0.0]
+}
+
+fn default_std() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+impl ModelManifest {
+    fn format(&self) -> Result<ModelFormat> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        match self.model.extension().and_then(OsStr::to_str) {
+            Some("onnx") => Ok(ModelFormat::Onnx),
+            Some("pb") => Ok(ModelFormat::Tensorflow),
+            _ => Err(anyhow::anyhow!(
+                "model_registry: Unknown format for model: {:?}",
+                self.model
+            )),
+        }
+    }
+
+    fn input_shape(&self) -> TVec<usize> {
+        match self.layout {
+            Layout::Nhwc => tvec!(1, self.input_size, self.input_size, 3),
+            Layout::Nchw => tvec!(1, 3, self.input_size, self.input_size),
+        }
+    }
+}
+
+/// A model which is ready to classify images
+pub struct LoadedModel {
+    pub name: String,
+    pub manifest: ModelManifest,
+    model: TypedModel,
+    labels: Vec<String>,
+}
+
+impl LoadedModel {
+    /// Loads the model described by a manifest
+    pub fn load(name: String, manifest: ModelManifest, models_directory: &Path) -> Result<Self> {
+        let model_path = models_directory.join(&manifest.model);
+        let labels_path = models_directory.join(&manifest.labels);
+
+        let labels = fs::read_to_string(&labels_path)
+            .with_context(|| {
+                log_and_return_error_string(format!(
+                    "model_registry: Failed to read labels at path: {:?}",
+                    labels_path
+                ))
+            })?
+            .lines()
+            .map(String::from)
+            .collect();
+
+        let model = match manifest.format()? {
+            ModelFormat::Onnx => onnx().model_for_path(&model_path),
+            ModelFormat::Tensorflow => tract_tensorflow::tensorflow().model_for_path(&model_path),
+        }
+        .and_then(|model| {
+            model.with_input_fact(
+                0,
+                InferenceFact::dt_shape(f32::datum_type(), manifest.input_shape()),
+            )
+        })
+        .and_then(|model| match &manifest.features {
+            // The features are computed on the way to the labels, so they are an extra output
+            Some(features) => {
+                let mut outputs = model.output_outlets()?.to_vec();
+                outputs.push(OutletId::new(model.node_by_name(features)?.id, 0));
+                model.with_output_outlets(&outputs)
+            }
+            None => Ok(model),
+        })
+        .and_then(|model| model.into_optimized())
+        .map_err(|e| {
+            anyhow::anyhow!(log_and_return_error_string(format!(
+                "model_registry: Failed to load model at path: {:?} with additional error info {:?}",
+                model_path, e
+            )))
+        })?;
+
+        Ok(LoadedModel {
+            name,
+            manifest,
+            model,
+            labels,
+        })
+    }
+
+    /// The confidence of every label for the image, along with its features if the model has them
+    fn scores(&self, image: &DynamicImage) -> Result<(Vec<f32>, Option<Vec<f32>>)> {
+        let size = self.manifest.input_size;
+        let resized = image::imageops::resize(
+            &image.to_rgb8(),
+            size as u32,
+            size as u32,
+            image::imageops::FilterType::Triangle,
+        );
+
+        let mean = self.manifest.mean;
+        let std = self.manifest.std;
+        let pixel = |x: usize, y: usize, c: usize| {
+            (f32::from(resized[(x as _, y as _)][c]) / 255.0 - mean[c]) / std[c]
+        };
+        let input: Tensor = match self.manifest.layout {
+            Layout::Nhwc => {
+                ndarray::Array4::from_shape_fn((1, size, size, 3), |(_, y, x, c)| pixel(x, y, c))
+                    .into()
+            }
+            Layout::Nchw => {
+                ndarray::Array4::from_shape_fn((1, 3, size, size), |(_, c, y, x)| pixel(x, y, c))
+                    .into()
+            }
+        };
+
+        let result = SimplePlan::new(&self.model)
+            .and_then(|plan| plan.run(tvec!(input)))
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "model_registry: Failed to run the image through model {} with additional error info {:?}",
+                    self.name,
+                    e
+                )
+            })?;
+
+        let output = |index: usize| -> Result<Vec<f32>> {
+            Ok(result[index]
+                .to_array_view::<f32>()
+                .map_err(|e| {
+                    anyhow::anyhow!(
+                        "model_registry: Failed to convert the output of model {} to an array view with additional error info {:?}",
+                        self.name,
+                        e
+                    )
+                })?
+                .iter()
+                .cloned()
+                .collect())
+        };
+
+        let scores = output(0)?;
+        let scores = if self.manifest.softmax {
+            softmax(scores)
+        } else {
+            scores
+        };
+        let features = match self.manifest.features {
+            Some(_) => Some(output(1)?),
+            None => None,
+        };
+        Ok((scores, features))
+    }
+}
+
+/// All of the models which images are classified with
+pub struct ModelRegistry {
+    pub models: Vec<LoadedModel>,
+}
+
+impl ModelRegistry {
+    /// Loads every model which has a manifest in the directory
+    /// Models which fail to load are logged and skipped so one broken model doesn't disable the others
+    pub fn load(models_directory: &Path) -> Self {
+        let manifests = match fs::read_dir(models_directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension() == Some(OsStr::new("json")))
+                .collect::<Vec<PathBuf>>(),
+            Err(_) => {
+                info!(
+                    "No models directory at {:?}, images won't be classified",
+                    models_directory
+                );
+                Vec::new()
+            }
+        };
+
+        let models = manifests
+            .into_iter()
+            .filter_map(|manifest_path| {
+                let name = manifest_path.file_stem()?.to_string_lossy().to_string();
+                let loaded = fs::read_to_string(&manifest_path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|manifest| Ok(serde_json::from_str::<ModelManifest>(&manifest)?))
+                    .and_then(|manifest| LoadedModel::load(name, manifest, models_directory));
+                match loaded {
+                    Ok(model) => {
+                        info!("Loaded image classification model {}", model.name);
+                        Some(model)
+                    }
+                    Err(e) => {
+                        error!("Skipping model {:?}: {:?}", manifest_path, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        ModelRegistry { models }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+
+    /// The best labels of every model for the image, most confident first
+    /// A label given by more than one model keeps its highest confidence
+    /// The embedding comes from the first model which has features
+    pub fn classify(
+        &self,
+        image: &DynamicImage,
+        top_k: usize,
+        threshold: f32,
+    ) -> Result<Classification> {
+        let mut labels: HashMap<String, f32> = HashMap::new();
+        let mut embedding = None;
+        for model in &self.models {
+            let (scores, features) = span!(Level::INFO, "model_registry: Run image through model")
+                .in_scope(|| model.scores(image))?;
+            for (label, confidence) in top_labels(&scores, &model.labels, top_k, threshold) {
+                let best = labels.entry(label).or_insert(confidence);
+                *best = best.max(confidence);
+            }
+            if embedding.is_none() {
+                embedding = features.map(normalize);
+            }
+        }
+
+        let mut labels = labels.into_iter().collect::<Vec<(String, f32)>>();
+        labels.sort_by(|(a_label, a), (b_label, b)| {
+            b.partial_cmp(a).unwrap().then_with(|| a_label.cmp(b_label))
+        });
+        Ok(Classification { labels, embedding })
+    }
+}
+
+/// What the models saw in an image
+#[derive(Debug, Default)]
+pub struct Classification {
+    /// Labels and their confidence, most confident first
+    pub labels: Vec<(String, f32)>,
+    /// Features of the image scaled to a length of 1, similar images have close embeddings
+    pub embedding: Option<Vec<f32>>,
+}
+
+/// The `top_k` most confident labels which are at least as confident as the threshold
+fn top_labels(
+    scores: &[f32],
+    labels: &[String],
+    top_k: usize,
+    threshold: f32,
+) -> Vec<(String, f32)> {
+    let mut ranked = scores
+        .iter()
+        .cloned()
+        .enumerate()
+        .filter(|(_, confidence)| !confidence.is_nan())
+        .collect::<Vec<(usize, f32)>>();
+    ranked.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+
+    ranked
+        .into_iter()
+        .take(top_k)
+        .filter(|(_, confidence)| *confidence >= threshold)
+        .filter_map(|(index, confidence)| Some((labels.get(index)?.clone(), confidence)))
+        .collect()
+}
+
+fn normalize(features: Vec<f32>) -> Vec<f32> {
+    let norm = features
+        .iter()
+        .map(|value| value * value)
+        .sum::<f32>()
+        .sqrt();
+    if norm == 0.0 {
+        return features;
+    }
+    features.into_iter().map(|value| value / norm).collect()
+}
+
+fn softmax(scores: Vec<f32>) -> Vec<f32> {
+    let max = scores.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let exponents = scores
+        .iter()
+        .map(|score| (score - max).exp())
+        .collect::<Vec<f32>>();
+    let sum: f32 = exponents.iter().sum();
+    exponents
+        .into_iter()
+        .map(|exponent| exponent / sum)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_top_labels_respects_k_and_threshold() {
+        let labels = labels(&["dummy", "cat", "dog", "eggnog"]);
+        let scores = [0.01, 0.6, 0.3, 0.09];
+
+        assert_eq!(
+            top_labels(&scores, &labels, 3, 0.1),
+            vec![(String::from("cat"), 0.6), (String::from("dog"), 0.3)]
+        );
+        assert_eq!(
+            top_labels(&scores, &labels, 1, 0.0),
+            vec![(String::from("cat"), 0.6)]
+        );
+    }
+
+    #[test]
+    fn test_softmax_sums_to_one() {
+        let probabilities = softmax(vec![1.0, 2.0, 3.0]);
+        assert!((probabilities.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!(probabilities[2] > probabilities[1] && probabilities[1] > probabilities[0]);
+    }
+
+    #[test]
+    fn test_parsing_manifest_with_defaults() {
+        let manifest: ModelManifest = serde_json::from_str(
+            r#"{ "model": "resnet.onnx", "labels": "imagenet.txt", "layout": "nchw", "softmax": true }"#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.format().unwrap(), ModelFormat::Onnx);
+        assert_eq!(manifest.input_shape(), tvec!(1, 3, 224, 224));
+        assert_eq!(manifest.mean, [0.0, 0.0, 0.0]);
+        assert!(manifest.softmax);
+        assert_eq!(manifest.features, None);
+    }
+
+    #[test]
+    fn test_normalizing_features() {
+        assert_eq!(normalize(vec![3.0, 4.0]), vec![0.6, 0.8]);
+        assert_eq!(normalize(vec![0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_missing_models_directory_is_empty_registry() {
+        assert!(ModelRegistry::load(Path::new("does/not/exist")).is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// A 64 bit difference hash of the image, which stays the same when it's resized or recompressed
+/// Each bit says if a pixel is brighter than the one to its right in a 9x8 grayscale thumbnail
+pub fn difference_hash(image: &DynamicImage) -> u64 {
+    let thumbnail = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = thumbnail.get_pixel(x, y)[0];
+            let right = thumbnail.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(left > right);
+        }
+    }
+    hash
+}
+
+/// How many bits two hashes differ by, images within a few bits of each other look the same
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, _| {
+            Luma([255 - (x * 255 / width) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_resized_images_have_the_same_hash() {
+        let hash = difference_hash(&gradient(90, 80));
+        assert_eq!(hash, u64::MAX);
+        assert_eq!(
+            hamming_distance(hash, difference_hash(&gradient(450, 400))),
+            0
+        );
+    }
+
+    #[test]
+    fn test_different_images_have_distant_hashes() {
+        let flipped = gradient(90, 80).fliph();
+        assert_eq!(
+            hamming_distance(
+                difference_hash(&gradient(90, 80)),
+                difference_hash(&flipped)
+            ),
+            64
+        );
+    }
+}
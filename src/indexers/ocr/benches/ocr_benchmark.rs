@@ -0,0 +1,29 @@
+#[macro_use]
+extern crate criterion;
+use criterion::Criterion;
+use criterion::*;
+
+use contracts::file_to_process::new_file_to_process;
+use contracts::indexer::Indexer;
+use ocr_indexer::ocr_indexer::{configure, OcrIndexer};
+use std::path::{Path, PathBuf};
+use tokio::runtime::Runtime;
+
+use common::tokio;
+
+fn bench_indexing_ocr_file(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    configure(PathBuf::from("../../../models")).unwrap();
+    let test_file_path = Path::new("../../../test_files/Scanned.pdf");
+    let ftp = rt.block_on(new_file_to_process(test_file_path));
+
+    c.bench_function("indexing_ocr_file", |b| {
+        b.iter(|| {
+            let _indexed_document = OcrIndexer.index_file(&ftp).unwrap();
+        });
+    });
+}
+
+criterion_group!(benches, bench_indexing_ocr_file,);
+
+criterion_main!(benches);
@@ -0,0 +1,309 @@
+use common::anyhow;
+use common::anyhow::{Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::{error, info, span, Level};
+use contracts::file_to_process::FileToProcess;
+use contracts::indexer::{DocumentSchema, Indexer, Section};
+use std::ffi::{OsStr, OsString};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+use image::{DynamicImage, GrayImage, RgbImage};
+use lopdf::{Object, ObjectId, Stream};
+use ocrs::{ImageSource, OcrEngine, OcrEngineParams};
+use once_cell::sync::{Lazy, OnceCell};
+use rten::Model;
+
+const OCR_EXTENSIONS: [&str; 8] = ["png", "jpg", "jpeg", "bmp", "gif", "tif", "tiff", "pdf"];
+
+/// File names of the ocrs models inside of the models directory
+const DETECTION_MODEL: &str = "text-detection.rten";
+const RECOGNITION_MODEL: &str = "text-recognition.rten";
+
+static MODELS_DIRECTORY: OnceCell<PathBuf> = OnceCell::new();
+
+/// The engine is only available when both of its models are in the models directory
+static ENGINE: Lazy<Option<OcrEngine>> = Lazy::new(|| {
+    let models_directory = MODELS_DIRECTORY.get_or_init(|| PathBuf::from("models"));
+    span!(Level::INFO, "ocr_indexer: Loading models").in_scope(|| {
+        match load_engine(models_directory) {
+            Ok(engine) => Some(engine),
+            Err(e) => {
+                error!("Text in images won't be recognized: {:?}", e);
+                None
+            }
+        }
+    })
+});
+
+/// Sets the directory the text detection and recognition models are loaded from
+/// This has to happen before the first file is indexed
+pub fn configure(models_directory: PathBuf) -> Result<()> {
+    MODELS_DIRECTORY
+        .set(models_directory)
+        .map_err(|_| anyhow::anyhow!("ocr_indexer: The models directory was already configured"))
+}
+
+fn load_engine(models_directory: &Path) -> Result<OcrEngine> {
+    let load_model = |file_name: &str| {
+        let model_path = models_directory.join(file_name);
+        Model::load_file(&model_path).with_context(|| {
+            log_and_return_error_string(format!(
+                "ocr_indexer: Failed to load model at path: {:?}",
+                model_path
+            ))
+        })
+    };
+
+    let engine = OcrEngine::new(OcrEngineParams {
+        detection_model: Some(load_model(DETECTION_MODEL)?),
+        recognition_model: Some(load_model(RECOGNITION_MODEL)?),
+        ..Default::default()
+    })?;
+    info!("Loaded OCR models from {:?}", models_directory);
+    Ok(engine)
+}
+
+/// Recognizes the text in images, eg: screenshots, and in the pages of scanned PDFs
+pub struct OcrIndexer;
+
+impl Indexer for OcrIndexer {
+    fn supports_extension(&self, extension: &OsStr) -> bool {
+        OCR_EXTENSIONS
+            .iter()
+            .any(|ocr_extension| extension == OsStr::new(ocr_extension))
+    }
+
+    fn supported_extensions(&self) -> Vec<OsString> {
+        OCR_EXTENSIONS.iter().map(OsString::from).collect()
+    }
+
+    fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
+        let path = file_to_process.path.to_str().unwrap();
+        span!(Level::INFO, "ocr_indexer: indexing file", path).in_scope(|| {
+            let engine = match &*ENGINE {
+                Some(engine) => engine,
+                // Why is logged once, when the models failed to load
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "ocr_indexer: The OCR models aren't available"
+                    ))
+                }
+            };
+
+            if file_to_process.path.extension() == Some(OsStr::new("pdf")) {
+                return index_scanned_pdf(engine, file_to_process);
+            }
+
+            let image = span!(Level::INFO, "ocr_indexer: Load image").in_scope(|| {
+                image::load_from_memory(&file_to_process.contents).with_context(|| {
+                    log_and_return_error_string(format!(
+                        "ocr_indexer: Failed to load image at path: {:?}",
+                        file_to_process.path
+                    ))
+                })
+            })?;
+
+            let body = span!(Level::INFO, "ocr_indexer: Recognize text")
+                .in_scope(|| recognize_text(engine, &image))?;
+
+            Ok(DocumentSchema {
+                body,
+                ..Default::default()
+            })
+        })
+    }
+}
+
+/// Recognizes the text of the pages which only have images on them
+/// Pages with text are left to the PdfIndexer
+fn index_scanned_pdf(
+    engine: &OcrEngine,
+    file_to_process: &FileToProcess,
+) -> Result<DocumentSchema> {
+    let pages = span!(Level::INFO, "ocr_indexer: Find image only pages")
+        .in_scope(|| image_only_pages(&file_to_process.contents))
+        .with_context(|| {
+            log_and_return_error_string(format!(
+                "ocr_indexer: Failed to read pdf at path: {:?}",
+                file_to_process.path
+            ))
+        })?;
+
+    let mut sections = Vec::new();
+    for (page_number, images) in pages {
+        let text =
+            span!(Level::INFO, "ocr_indexer: Recognize text", page_number).in_scope(|| {
+                images
+                    .iter()
+                    .map(|image| recognize_text(engine, image))
+                    .collect::<Result<Vec<String>>>()
+            })?;
+        let text = text.join(" ");
+        if !text.is_empty() {
+            sections.push(Section {
+                label: format!("Page {}", page_number),
+                text,
+            });
+        }
+    }
+
+    let body = sections
+        .iter()
+        .map(|section| section.text.as_str())
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    Ok(DocumentSchema {
+        body,
+        sections,
+        ..Default::default()
+    })
+}
+
+fn recognize_text(engine: &OcrEngine, image: &DynamicImage) -> Result<String> {
+    let image = image.to_rgb8();
+    let image_source = ImageSource::from_bytes(image.as_raw(), image.dimensions())?;
+    let input = engine.prepare_input(image_source)?;
+    let text = engine.get_text(&input)?;
+    Ok(text.split_whitespace().collect::<Vec<&str>>().join(" "))
+}
+
+/// The images on each page which has no text, by page number
+fn image_only_pages(contents: &[u8]) -> Result<Vec<(u32, Vec<DynamicImage>)>> {
+    let document = lopdf::Document::load_mem(contents)?;
+
+    Ok(document
+        .get_pages()
+        .into_iter()
+        .filter(|(page_number, _)| {
+            document
+                .extract_text(&[*page_number])
+                .map(|text| text.trim().is_empty())
+                .unwrap_or(true)
+        })
+        .map(|(page_number, page_id)| (page_number, page_images(&document, page_id)))
+        .filter(|(_, images)| !images.is_empty())
+        .collect())
+}
+
+/// Decodes the image XObjects a page uses
+/// Only JPEG images and 8 bit grayscale or RGB images are supported, which covers most scanners
+fn page_images(document: &lopdf::Document, page_id: ObjectId) -> Vec<DynamicImage> {
+    let (resources, resource_ids) = document.get_page_resources(page_id);
+    let resources = resources.into_iter().chain(
+        resource_ids
+            .into_iter()
+            .filter_map(|resource_id| document.get_dictionary(resource_id).ok()),
+    );
+
+    resources
+        .filter_map(|resources| resources.get_deref(b"XObject", document).ok())
+        .filter_map(|x_objects| x_objects.as_dict().ok())
+        .flat_map(|x_objects| x_objects.iter())
+        .filter_map(|(_, x_object)| match x_object {
+            Object::Reference(id) => document.get_object(*id).ok()?.as_stream().ok(),
+            Object::Stream(stream) => Some(stream),
+            _ => None,
+        })
+        .filter(|stream| {
+            matches!(
+                stream.dict.get(b"Subtype").and_then(Object::as_name_str),
+                Ok("Image")
+            )
+        })
+        .filter_map(|stream| decode_image(document, stream))
+        .collect()
+}
+
+fn decode_image(document: &lopdf::Document, stream: &Stream) -> Option<DynamicImage> {
+    let filters = stream.filters().unwrap_or_default();
+    if filters.iter().any(|filter| filter == "DCTDecode") {
+        return image::load_from_memory_with_format(&stream.content, image::ImageFormat::Jpeg).ok();
+    }
+
+    let width = stream.dict.get(b"Width").and_then(Object::as_i64).ok()? as u32;
+    let height = stream.dict.get(b"Height").and_then(Object::as_i64).ok()? as u32;
+    let bits_per_component = stream
+        .dict
+        .get(b"BitsPerComponent")
+        .and_then(Object::as_i64)
+        .ok()?;
+    if bits_per_component != 8 {
+        return None;
+    }
+
+    // lopdf doesn't decompress image streams, FlateDecode without a predictor is the common case
+    let pixels = match filters.as_slice() {
+        [] => stream.content.clone(),
+        [filter] if filter == "FlateDecode" => {
+            let mut pixels = Vec::new();
+            ZlibDecoder::new(stream.content.as_slice())
+                .read_to_end(&mut pixels)
+                .ok()?;
+            pixels
+        }
+        _ => return None,
+    };
+
+    match stream
+        .dict
+        .get_deref(b"ColorSpace", document)
+        .and_then(Object::as_name_str)
+        .ok()?
+    {
+        "DeviceGray" => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        "DeviceRGB" => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tokio;
+    use contracts::file_to_process::new_file_to_process;
+
+    // Needs the ocrs models, `text-detection.rten` and `text-recognition.rten`, to be downloaded into `models/`
+    #[tokio::test]
+    async fn test_indexing_scanned_pdf() {
+        let _ = configure(PathBuf::from("../../../models"));
+        let test_file_path = Path::new("../../../test_files/Scanned.pdf");
+        let indexed_document = OcrIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.body, "CATS");
+        assert_eq!(indexed_document.sections[0].label, "Page 1");
+    }
+
+    #[tokio::test]
+    async fn test_finding_image_only_pages() {
+        let test_file_path = Path::new("../../../test_files/Scanned.pdf");
+        let file_to_process = new_file_to_process(test_file_path).await;
+
+        let pages = image_only_pages(&file_to_process.contents).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].0, 1);
+        assert_eq!(pages[0].1.len(), 1);
+        assert_eq!(pages[0].1[0].to_luma8().dimensions(), (216, 88));
+    }
+
+    #[tokio::test]
+    async fn test_pages_with_text_are_skipped() {
+        let test_file_path = Path::new("../../../test_files/Cats.pdf");
+        let file_to_process = new_file_to_process(test_file_path).await;
+
+        assert!(image_only_pages(&file_to_process.contents)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_supports_ocr_extension() {
+        assert_eq!(true, OcrIndexer.supports_extension(OsStr::new("png")));
+        assert_eq!(true, OcrIndexer.supports_extension(OsStr::new("pdf")));
+        assert_eq!(false, OcrIndexer.supports_extension(OsStr::new("txt")));
+    }
+}
@@ -0,0 +1 @@
+pub mod opendocument_indexer;
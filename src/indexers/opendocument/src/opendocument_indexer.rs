@@ -0,0 +1,217 @@
+use common::anyhow::{Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::{span, Level};
+use contracts::file_to_process::FileToProcess;
+use contracts::indexer::{DocumentSchema, Indexer, Section};
+use std::ffi::{OsStr, OsString};
+use std::io::{Cursor, Read};
+
+use roxmltree::{Document, Node};
+
+const TEXT_NS: &str = "urn:oasis:names:tc:opendocument:xmlns:text:1.0";
+const DRAW_NS: &str = "urn:oasis:names:tc:opendocument:xmlns:drawing:1.0";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+
+/// Indexes OpenDocument text and presentation files
+/// OpenDocument spreadsheets are read by the SpreadsheetIndexer
+pub struct OpenDocumentIndexer;
+
+impl Indexer for OpenDocumentIndexer {
+    fn supports_extension(&self, extension: &OsStr) -> bool {
+        extension == OsStr::new("odt") || extension == OsStr::new("odp")
+    }
+
+    fn supported_extensions(&self) -> Vec<OsString> {
+        vec![OsString::from("odt"), OsString::from("odp")]
+    }
+
+    fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
+        let path = file_to_process.path.to_str().unwrap();
+        span!(
+            Level::INFO,
+            "opendocument_indexer: indexing opendocument file",
+            path
+        )
+        .in_scope(|| {
+            let mut archive = span!(Level::INFO, "opendocument_indexer: Load from memory")
+                .in_scope(|| zip::ZipArchive::new(Cursor::new(&file_to_process.contents)))
+                .with_context(|| {
+                    log_and_return_error_string(format!(
+                        "opendocument_indexer: Failed to open archive at path: {:?}",
+                        file_to_process.path
+                    ))
+                })?;
+
+            let content = read_entry(&mut archive, "content.xml").with_context(|| {
+                log_and_return_error_string(format!(
+                    "opendocument_indexer: Failed to read content.xml from file at path: {:?}",
+                    file_to_process.path
+                ))
+            })?;
+            // meta.xml is optional, documents without it are named after the file
+            let meta = read_entry(&mut archive, "meta.xml").ok();
+
+            span!(Level::INFO, "opendocument_indexer: Process file").in_scope(|| {
+                let content = Document::parse(&content).with_context(|| {
+                    log_and_return_error_string(format!(
+                        "opendocument_indexer: Failed to parse content.xml from file at path: {:?}",
+                        file_to_process.path
+                    ))
+                })?;
+
+                // Every page of a presentation is a slide
+                let sections = content
+                    .descendants()
+                    .filter(|node| node.has_tag_name((DRAW_NS, "page")))
+                    .enumerate()
+                    .map(|(index, page)| Section {
+                        label: format!("Slide {}", index + 1),
+                        text: paragraphs(page),
+                    })
+                    .collect::<Vec<Section>>();
+
+                let body = if sections.is_empty() {
+                    paragraphs(content.root())
+                } else {
+                    sections
+                        .iter()
+                        .map(|section| section.text.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(" ")
+                };
+
+                let name = meta.as_deref().and_then(title).unwrap_or_else(|| {
+                    file_to_process
+                        .path
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                });
+
+                Ok(DocumentSchema {
+                    name,
+                    body,
+                    sections,
+                    ..Default::default()
+                })
+            })
+        })
+    }
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<Cursor<&Vec<u8>>>, name: &str) -> Result<String> {
+    let mut entry = archive.by_name(name)?;
+    let mut contents = String::new();
+    entry.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// The title from the document properties, if it was set
+fn title(meta: &str) -> Option<String> {
+    let meta = Document::parse(meta).ok()?;
+    let title = meta
+        .descendants()
+        .find(|node| node.has_tag_name((DC_NS, "title")))?
+        .text()?
+        .trim()
+        .to_string();
+    if title.is_empty() {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+/// Joins the text of all the paragraphs and headings under a node
+fn paragraphs(node: Node) -> String {
+    node.descendants()
+        .filter(|node| is_paragraph(node))
+        .map(|paragraph| {
+            let mut text = String::new();
+            paragraph_text(paragraph, &mut text);
+            text
+        })
+        .filter(|text| !text.trim().is_empty())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn is_paragraph(node: &Node) -> bool {
+    node.has_tag_name((TEXT_NS, "p")) || node.has_tag_name((TEXT_NS, "h"))
+}
+
+/// Collects the text of a paragraph
+/// Paragraphs nested inside of it (eg: in a text box) are collected on their own
+fn paragraph_text(node: Node, text: &mut String) {
+    for child in node.children() {
+        if child.is_text() {
+            text.push_str(child.text().unwrap_or_default());
+        } else if child.has_tag_name((TEXT_NS, "s"))
+            || child.has_tag_name((TEXT_NS, "tab"))
+            || child.has_tag_name((TEXT_NS, "line-break"))
+        {
+            text.push(' ');
+        } else if child.is_element() && !is_paragraph(&child) {
+            paragraph_text(child, text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tokio;
+    use contracts::file_to_process::new_file_to_process;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn test_indexing_odt_file() {
+        let test_file_path = Path::new("../../../test_files/Cats.odt");
+        let indexed_document = OpenDocumentIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.name, "All about cats");
+        assert_eq!(
+            indexed_document.body,
+            "Cats This is an example document about cats. Cats have paws. They're pretty cool."
+        );
+        assert!(indexed_document.sections.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_indexing_odp_file() {
+        let test_file_path = Path::new("../../../test_files/Cats.odp");
+        let indexed_document = OpenDocumentIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.name, "Cats deck");
+        assert_eq!(indexed_document.sections.len(), 2);
+        assert_eq!(indexed_document.sections[0].label, "Slide 1");
+        assert_eq!(
+            indexed_document.sections[0].text,
+            "Cats A quick story about cats"
+        );
+        assert_eq!(indexed_document.sections[1].label, "Slide 2");
+        assert!(indexed_document.sections[1].text.contains("Paws are cool"));
+        assert!(indexed_document.body.contains("whiskers"));
+    }
+
+    #[test]
+    fn test_supports_opendocument_extension() {
+        assert_eq!(
+            true,
+            OpenDocumentIndexer.supports_extension(OsStr::new("odt"))
+        );
+        assert_eq!(
+            true,
+            OpenDocumentIndexer.supports_extension(OsStr::new("odp"))
+        );
+        assert_eq!(
+            false,
+            OpenDocumentIndexer.supports_extension(OsStr::new("ods"))
+        );
+    }
+}
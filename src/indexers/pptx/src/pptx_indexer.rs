@@ -1,13 +1,24 @@
-use common::anyhow;
-use common::anyhow::Result;
-use common::tracing::{span, Level};
+use common::anyhow::{Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::{info, span, Level};
 use contracts::file_to_process::FileToProcess;
-use contracts::indexer::{DocumentSchema, Indexer};
+use contracts::indexer::{DocumentSchema, Indexer, MetadataValue, Section};
+use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
+use std::io::{Cursor, Read};
 
-use msoffice_pptx::document::PPTXDocument;
-use msoffice_pptx::pml::ShapeGroup;
-use msoffice_shared::drawingml::TextRun;
+use roxmltree::{Document, Node};
+
+const PML_NS: &str = "http://schemas.openxmlformats.org/presentationml/2006/main";
+const DML_NS: &str = "http://schemas.openxmlformats.org/drawingml/2006/main";
+const CHART_NS: &str = "http://schemas.openxmlformats.org/drawingml/2006/chart";
+const RELATIONSHIPS_NS: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const PACKAGE_RELATIONSHIPS_NS: &str =
+    "http://schemas.openxmlformats.org/package/2006/relationships";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+
+type PptxArchive<'a> = zip::ZipArchive<Cursor<&'a Vec<u8>>>;
 
 pub struct PptxIndexer;
 
@@ -23,75 +34,279 @@ impl Indexer for PptxIndexer {
     fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
         let path = file_to_process.path.to_str().unwrap();
         span!(Level::INFO, "pptx_indexer: indexing powerpoint file", path).in_scope(|| {
-            let mut total_text = String::new();
-            let document = span!(Level::INFO, "pptx_indexer: Load from disk").in_scope(|| {
-                match PPTXDocument::from_file(file_to_process.path.as_path()) {
-                    Ok(doc) => Ok(doc),
-                    Err(e) => Err(anyhow::anyhow!(format!(
-                        "pptx_indexer: Failed to open PPTX Document from file at path: {:?} with additional error info {:?}",
-                        file_to_process.path,
-                        e
-                    )))
-                }
-            })?;
-
-            span!(Level::INFO, "pptx_indexer: Process file").in_scope(|| {
-                for slide in document.slide_map.values() {
-                    let shape_group = &(*(*slide.common_slide_data).shape_tree).shape_array;
-                    for s_g in shape_group {
-                        if let Some(res_text) = extract_text(s_g) {
-                            total_text.push_str(&res_text);
-                        }
-                    }
-                }
+            let mut archive = span!(Level::INFO, "pptx_indexer: Load from memory")
+                .in_scope(|| zip::ZipArchive::new(Cursor::new(&file_to_process.contents)))
+                .with_context(|| {
+                    log_and_return_error_string(format!(
+                        "pptx_indexer: Failed to open PPTX Document from file at path: {:?}",
+                        file_to_process.path
+                    ))
+                })?;
+
+            let slides = span!(Level::INFO, "pptx_indexer: Process file")
+                .in_scope(|| read_slides(&mut archive))
+                .with_context(|| {
+                    log_and_return_error_string(format!(
+                        "pptx_indexer: Failed to read slides from file at path: {:?}",
+                        file_to_process.path
+                    ))
+                })?;
+
+            let body = slides
+                .iter()
+                .map(|slide| slide.text())
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            let metadata = slides
+                .iter()
+                .filter_map(|slide| slide.title.clone())
+                .map(|title| (String::from("headings"), MetadataValue::Text(title)))
+                .collect();
+
+            let sections = slides
+                .iter()
+                .enumerate()
+                .map(|(index, slide)| Section {
+                    label: format!("Slide {}", index + 1),
+                    text: slide.text(),
+                })
+                .collect();
+
+            // Decks without a title in their core properties are named after the file
+            let name = deck_title(&mut archive).unwrap_or_else(|| {
+                file_to_process
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
             });
 
             Ok(DocumentSchema {
-                name: String::new(),
-                body: total_text,
+                name,
+                body,
+                metadata,
+                sections,
             })
         })
     }
 }
 
-fn extract_text(shape_group: &ShapeGroup) -> Option<String> {
-    let mut total_text = String::new();
-    match shape_group {
-        ShapeGroup::Shape(shape) => {
-            if let Some(text_body) = &shape.text_body {
-                for paragraph in &text_body.paragraph_array {
-                    for text_run in &paragraph.text_run_list {
-                        if let TextRun::RegularTextRun(regular_text_run) = text_run {
-                            total_text.push_str(&regular_text_run.text);
-                            total_text.push_str(" ");
-                        }
-                    }
-                }
-            }
+/// Everything that was extracted from a single slide
+#[derive(Debug, Default)]
+struct Slide {
+    title: Option<String>,
+    /// Text of every shape, group and table on the slide, including the title
+    text: String,
+    chart_titles: Vec<String>,
+    notes: String,
+}
+
+impl Slide {
+    fn text(&self) -> String {
+        let mut text = self.text.clone();
+        for chart_title in &self.chart_titles {
+            text.push(' ');
+            text.push_str(chart_title);
         }
-        ShapeGroup::GroupShape(group_shape) => {
-            let res_text = group_shape
-                .shape_array
-                .iter()
-                .map(|s_g| extract_text(s_g))
-                .filter_map(|r_t| r_t)
-                .fold(String::new(), |mut acc, x| {
-                    acc.push_str(&x);
-                    acc.push_str(" ");
-                    acc
-                });
-
-            total_text.push_str(&res_text);
+        if !self.notes.is_empty() {
+            text.push(' ');
+            text.push_str(&self.notes);
         }
-        _ => {}
+        text
     }
-    if total_text != String::new() {
-        Some(total_text)
-    } else {
+}
+
+/// Reads the slides in the order they are presented
+fn read_slides(archive: &mut PptxArchive) -> Result<Vec<Slide>> {
+    let presentation = read_entry(archive, "ppt/presentation.xml")?;
+    let presentation = Document::parse(&presentation)?;
+    let presentation_relationships = read_relationships(archive, "ppt/presentation.xml")?;
+
+    presentation
+        .descendants()
+        .filter(|node| node.has_tag_name((PML_NS, "sldId")))
+        .filter_map(|slide_id| slide_id.attribute((RELATIONSHIPS_NS, "id")))
+        .filter_map(|relationship_id| presentation_relationships.get(relationship_id))
+        .map(|(_, slide_path)| read_slide(archive, slide_path))
+        .collect()
+}
+
+fn read_slide(archive: &mut PptxArchive, slide_path: &str) -> Result<Slide> {
+    let slide_xml = read_entry(archive, slide_path)?;
+    let slide_xml = Document::parse(&slide_xml)?;
+
+    let mut slide = Slide {
+        title: slide_xml
+            .descendants()
+            .filter(|node| node.has_tag_name((PML_NS, "sp")))
+            .find(|shape| matches!(placeholder_type(shape), Some("title") | Some("ctrTitle")))
+            .map(paragraphs),
+        text: paragraphs(slide_xml.root()),
+        ..Default::default()
+    };
+
+    // Sorted by target so charts keep a stable order, eg: chart1 before chart2
+    let mut relationships = read_relationships(archive, slide_path)?
+        .into_values()
+        .collect::<Vec<(String, String)>>();
+    relationships.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    // Broken notes or charts shouldn't hide the rest of the deck
+    for (relationship_type, target) in &relationships {
+        if relationship_type.ends_with("/notesSlide") {
+            match read_notes(archive, target) {
+                Ok(notes) => slide.notes = notes,
+                Err(e) => info!("Skipping the notes at {:?}: {:?}", target, e),
+            }
+        } else if relationship_type.ends_with("/chart") {
+            match read_chart_title(archive, target) {
+                Ok(Some(chart_title)) => slide.chart_titles.push(chart_title),
+                Ok(None) => {}
+                Err(e) => info!("Skipping the chart at {:?}: {:?}", target, e),
+            }
+        }
+    }
+
+    Ok(slide)
+}
+
+/// The text of the notes, without the slide image and slide number placeholders
+fn read_notes(archive: &mut PptxArchive, notes_path: &str) -> Result<String> {
+    let notes_xml = read_entry(archive, notes_path)?;
+    let notes_xml = Document::parse(&notes_xml)?;
+
+    Ok(notes_xml
+        .descendants()
+        .filter(|node| node.has_tag_name((PML_NS, "sp")))
+        .filter(|shape| {
+            !matches!(
+                placeholder_type(shape),
+                Some("sldImg") | Some("sldNum") | Some("hdr") | Some("ftr") | Some("dt")
+            )
+        })
+        .map(paragraphs)
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<String>>()
+        .join(" "))
+}
+
+fn read_chart_title(archive: &mut PptxArchive, chart_path: &str) -> Result<Option<String>> {
+    let chart_xml = read_entry(archive, chart_path)?;
+    let chart_xml = Document::parse(&chart_xml)?;
+
+    Ok(chart_xml
+        .descendants()
+        .find(|node| node.has_tag_name((CHART_NS, "title")))
+        .map(paragraphs)
+        .filter(|title| !title.is_empty()))
+}
+
+/// The title from the core properties, if it was set
+fn deck_title(archive: &mut PptxArchive) -> Option<String> {
+    let core = read_entry(archive, "docProps/core.xml").ok()?;
+    let core = Document::parse(&core).ok()?;
+    let title = core
+        .descendants()
+        .find(|node| node.has_tag_name((DC_NS, "title")))?
+        .text()?
+        .trim()
+        .to_string();
+    if title.is_empty() {
         None
+    } else {
+        Some(title)
     }
 }
 
+fn placeholder_type<'a>(shape: &Node<'a, '_>) -> Option<&'a str> {
+    shape
+        .descendants()
+        .find(|node| node.has_tag_name((PML_NS, "ph")))?
+        .attribute("type")
+}
+
+/// Joins the text runs of every paragraph under a node
+fn paragraphs(node: Node) -> String {
+    node.descendants()
+        .filter(|node| node.has_tag_name((DML_NS, "p")))
+        .map(|paragraph| {
+            paragraph
+                .descendants()
+                .filter(|node| node.has_tag_name((DML_NS, "t")))
+                .filter_map(|text| text.text())
+                .collect::<String>()
+        })
+        .filter(|text| !text.trim().is_empty())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn read_entry(archive: &mut PptxArchive, name: &str) -> Result<String> {
+    let mut entry = archive.by_name(name)?;
+    let mut contents = String::new();
+    entry.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Reads the relationships of a part, keyed by their id
+/// Each relationship is its type and the path of its target inside of the archive
+fn read_relationships(
+    archive: &mut PptxArchive,
+    part_path: &str,
+) -> Result<HashMap<String, (String, String)>> {
+    let (directory, file_name) = match part_path.rfind('/') {
+        Some(index) => (&part_path[..index], &part_path[index + 1..]),
+        None => ("", part_path),
+    };
+    let relationships_path = format!("{}/_rels/{}.rels", directory, file_name);
+
+    // Parts without any relationships don't have a relationships file
+    let relationships = match read_entry(archive, &relationships_path) {
+        Ok(relationships) => relationships,
+        Err(_) => return Ok(HashMap::new()),
+    };
+    let relationships = Document::parse(&relationships)?;
+
+    Ok(relationships
+        .descendants()
+        .filter(|node| node.has_tag_name((PACKAGE_RELATIONSHIPS_NS, "Relationship")))
+        .filter(|relationship| relationship.attribute("TargetMode") != Some("External"))
+        .filter_map(|relationship| {
+            Some((
+                relationship.attribute("Id")?.to_string(),
+                (
+                    relationship.attribute("Type")?.to_string(),
+                    resolve_target(directory, relationship.attribute("Target")?),
+                ),
+            ))
+        })
+        .collect())
+}
+
+/// Resolves a relationship target, relative to the directory of its part, to a path inside of the archive
+fn resolve_target(directory: &str, target: &str) -> String {
+    if let Some(absolute_target) = target.strip_prefix('/') {
+        return absolute_target.to_string();
+    }
+
+    let mut segments = directory
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<&str>>();
+    for segment in target.split('/') {
+        match segment {
+            ".." => {
+                segments.pop();
+            }
+            "." | "" => {}
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,12 +322,85 @@ mod tests {
             .index_file(&new_file_to_process(test_file_path).await)
             .unwrap();
 
-        assert_eq!(indexed_document.name, "");
+        assert_eq!(indexed_document.name, "Cats");
         assert!(indexed_document.body.contains("Cats"));
         assert!(indexed_document.body.contains("quick"));
         assert!(indexed_document.body.contains("story"));
         assert!(indexed_document.body.contains("Paws"));
         assert!(indexed_document.body.contains("cool"));
+        assert_eq!(
+            indexed_document.metadata,
+            vec![
+                (
+                    String::from("headings"),
+                    MetadataValue::Text(String::from("Cats"))
+                ),
+                (
+                    String::from("headings"),
+                    MetadataValue::Text(String::from("Paws"))
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_indexing_pptx_notes_tables_and_charts() {
+        let test_file_path = Path::new("../../../test_files/Paws.pptx");
+        let indexed_document = PptxIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.sections.len(), 2);
+        assert_eq!(indexed_document.sections[0].label, "Slide 1");
+        assert_eq!(indexed_document.sections[0].text, "Cats A quick story");
+
+        let second_slide = &indexed_document.sections[1];
+        assert_eq!(second_slide.label, "Slide 2");
+        assert!(second_slide.text.contains("Front paws"));
+        assert!(second_slide.text.contains("Back paws"));
+        assert!(second_slide.text.contains("Paw sizes"));
+        assert!(second_slide.text.contains("Remember to mention whiskers"));
+    }
+
+    #[tokio::test]
+    async fn test_indexing_pptx_with_missing_notes() {
+        let test_file_path = Path::new("../../../test_files/Paws.pptx");
+        let mut file_to_process = new_file_to_process(test_file_path).await;
+
+        // Copies the deck without its notes, which the slides still point to
+        let mut archive = zip::ZipArchive::new(Cursor::new(&file_to_process.contents)).unwrap();
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index).unwrap();
+            if !entry.name().starts_with("ppt/notesSlides/") {
+                writer.raw_copy_file(entry).unwrap();
+            }
+        }
+        file_to_process.contents = writer.finish().unwrap().into_inner();
+
+        let indexed_document = PptxIndexer.index_file(&file_to_process).unwrap();
+
+        assert_eq!(indexed_document.sections.len(), 2);
+        assert!(indexed_document.sections[1].text.contains("Paw sizes"));
+        assert!(!indexed_document.sections[1]
+            .text
+            .contains("Remember to mention whiskers"));
+    }
+
+    #[test]
+    fn test_resolve_relationship_target() {
+        assert_eq!(
+            resolve_target("ppt/slides", "../notesSlides/notesSlide1.xml"),
+            "ppt/notesSlides/notesSlide1.xml"
+        );
+        assert_eq!(
+            resolve_target("ppt", "slides/slide1.xml"),
+            "ppt/slides/slide1.xml"
+        );
+        assert_eq!(
+            resolve_target("ppt/slides", "/ppt/charts/chart1.xml"),
+            "ppt/charts/chart1.xml"
+        );
     }
 
     #[test]
@@ -2,68 +2,222 @@ use common::anyhow;
 use common::anyhow::Result;
 use common::tracing::{span, Level};
 use contracts::file_to_process::FileToProcess;
-use contracts::indexer::{DocumentSchema, Indexer};
+use contracts::indexer::{DocumentSchema, Indexer, MetadataValue, Section};
 use std::ffi::{OsStr, OsString};
+use std::io::{Cursor, Read};
 
-use calamine::{open_workbook, Reader, Xlsx};
+use calamine::{DataType, Ods, Range, Reader, Xls, Xlsb, Xlsx};
+use chrono::Timelike;
+
+/// Every format calamine can read
+const SPREADSHEET_EXTENSIONS: [&str; 5] = ["xlsx", "xlsm", "xlsb", "xls", "ods"];
+
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
 
 pub struct SpreadsheetIndexer;
 
 impl Indexer for SpreadsheetIndexer {
     fn supports_extension(&self, extension: &OsStr) -> bool {
-        // Only xslx for now
-        extension == OsStr::new("xlsx")
+        SPREADSHEET_EXTENSIONS
+            .iter()
+            .any(|spreadsheet_extension| extension == OsStr::new(spreadsheet_extension))
     }
 
     fn supported_extensions(&self) -> Vec<OsString> {
-        vec![OsString::from("xlsx")]
+        SPREADSHEET_EXTENSIONS.iter().map(OsString::from).collect()
     }
 
     fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
         let path = file_to_process.path.to_str().unwrap();
-        span!(Level::INFO, "spreadsheet_indexer: indexing spreadsheet file", path).in_scope(|| {
-            let mut workbook: Xlsx<_> = span!(Level::INFO, "spreadsheet_indexer: Load from disk").in_scope(|| {
-                match open_workbook(&file_to_process.path) {
-                    Ok(workbook) => Ok(workbook),
-                    Err(e) => Err(anyhow::anyhow!(format!(
-                        "spreadsheet_indexer: Failed to open workbook at path: {:?} with additional error info {:?}",
-                        file_to_process.path,
-                        e
-                    )))
+        span!(
+            Level::INFO,
+            "spreadsheet_indexer: indexing spreadsheet file",
+            path
+        )
+        .in_scope(|| {
+            let workbook = span!(
+                Level::INFO,
+                "spreadsheet_indexer: Load from memory and process"
+            )
+            .in_scope(|| {
+                match file_to_process.path.extension().and_then(OsStr::to_str) {
+                    Some("xls") => read_workbook::<Xls<_>>(file_to_process),
+                    Some("xlsb") => read_workbook::<Xlsb<_>>(file_to_process),
+                    Some("ods") => read_workbook::<Ods<_>>(file_to_process),
+                    _ => read_workbook::<Xlsx<_>>(file_to_process),
                 }
             })?;
 
-            let strings = span!(Level::INFO, "spreadsheet_indexer: Process file").in_scope(|| {
-                workbook
-                    .sheet_names()
-                    .to_vec()
-                    .iter()
-                    .filter_map(|sheet_name| workbook.worksheet_range(sheet_name))
-                    .filter_map(Result::ok)
-                    .map(|range| {
-                        range
-                            .used_cells()
-                            .filter(|(_, _, cell)| cell.is_string())
-                            .filter_map(|(_, _, cell)| cell.get_string())
-                            .map(std::string::ToString::to_string)
-                            .collect::<Vec<String>>()
-                    })
-                    .flatten()
-                    .fold(String::new(), |mut acc, x| {
-                        acc.push_str(&x);
-                        acc.push_str(" ");
-                        acc
-                    })
-            });
+            let strings = workbook
+                .sheets
+                .iter()
+                .map(|sheet| sheet.text.as_str())
+                .collect();
+
+            let metadata = workbook
+                .headers
+                .into_iter()
+                .map(|header| (String::from("columns"), MetadataValue::Text(header)))
+                .collect();
+
+            // Workbooks without a title in their document properties are named after the file
+            let name = span!(Level::INFO, "spreadsheet_indexer: Read document properties")
+                .in_scope(|| workbook_title(file_to_process))
+                .unwrap_or_else(|| {
+                    file_to_process
+                        .path
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                });
 
             Ok(DocumentSchema {
-                name: file_to_process.path.file_name().unwrap().to_string_lossy().to_string(),
+                name,
                 body: strings,
+                metadata,
+                sections: workbook.sheets,
             })
         })
     }
 }
 
+/// What was read out of every sheet of a workbook
+struct Workbook {
+    /// The text of each sheet, labelled by the sheet name
+    sheets: Vec<Section>,
+    /// The header row of each sheet which has one
+    headers: Vec<String>,
+}
+
+/// Opens the workbook and extracts the values and formulas of each sheet
+/// The sheet name is part of the text so sheets can be found by name
+fn read_workbook<'a, R>(file_to_process: &'a FileToProcess) -> Result<Workbook>
+where
+    R: Reader<RS = Cursor<&'a Vec<u8>>>,
+{
+    let mut workbook = match R::new(Cursor::new(&file_to_process.contents)) {
+        Ok(workbook) => Ok(workbook),
+        Err(e) => Err(anyhow::anyhow!(format!(
+            "spreadsheet_indexer: Failed to open workbook at path: {:?} with additional error info {:?}",
+            file_to_process.path,
+            e
+        ))),
+    }?;
+
+    let mut sheets = Vec::new();
+    let mut headers = Vec::new();
+    for sheet_name in workbook.sheet_names().to_vec() {
+        let range = match workbook.worksheet_range(&sheet_name) {
+            Some(Ok(range)) => range,
+            _ => continue,
+        };
+
+        if let Some(header) = header_row(&range) {
+            headers.push(header);
+        }
+
+        let mut text = format!("{} ", sheet_name);
+        for (_, _, cell) in range.used_cells() {
+            if let Some(cell_text) = cell_text(cell) {
+                text.push_str(&cell_text);
+                text.push(' ');
+            }
+        }
+
+        // Not every format supports formulas, the values are enough for those
+        if let Some(Ok(formulas)) = workbook.worksheet_formula(&sheet_name) {
+            for (_, _, formula) in formulas.used_cells() {
+                text.push('=');
+                text.push_str(formula);
+                text.push(' ');
+            }
+        }
+
+        sheets.push(Section {
+            label: sheet_name,
+            text,
+        });
+    }
+
+    Ok(Workbook { sheets, headers })
+}
+
+/// Renders a cell the way it would be displayed, dates are rendered as ISO 8601
+fn cell_text(cell: &DataType) -> Option<String> {
+    match cell {
+        DataType::String(string) => Some(string.clone()),
+        DataType::Int(int) => Some(int.to_string()),
+        DataType::Float(float) => Some(float.to_string()),
+        DataType::Bool(boolean) => Some(boolean.to_string()),
+        DataType::DateTime(_) => cell.as_datetime().map(|date_time| {
+            if date_time.num_seconds_from_midnight() == 0 {
+                date_time.format("%Y-%m-%d").to_string()
+            } else {
+                date_time.format("%Y-%m-%d %H:%M:%S").to_string()
+            }
+        }),
+        DataType::Error(_) | DataType::Empty => None,
+    }
+}
+
+/// The first row of a sheet is a header row when it's only text
+/// and the rows below it hold other kinds of values, eg: numbers or dates
+fn header_row(range: &Range<DataType>) -> Option<String> {
+    let mut rows = range.rows();
+    let first_row = rows
+        .next()?
+        .iter()
+        .filter(|cell| !cell.is_empty())
+        .collect::<Vec<&DataType>>();
+
+    let only_text = !first_row.is_empty() && first_row.iter().all(|cell| cell.is_string());
+    let has_values = rows.any(|row| row.iter().any(|cell| !cell.is_empty() && !cell.is_string()));
+
+    if only_text && has_values {
+        Some(
+            first_row
+                .iter()
+                .filter_map(|cell| cell.get_string())
+                .collect::<Vec<&str>>()
+                .join(" "),
+        )
+    } else {
+        None
+    }
+}
+
+/// The title from the document properties of an xlsx or ods file
+fn workbook_title(file_to_process: &FileToProcess) -> Option<String> {
+    let properties = match file_to_process.path.extension().and_then(OsStr::to_str) {
+        Some("ods") => "meta.xml",
+        Some("xlsx") | Some("xlsm") => "docProps/core.xml",
+        _ => return None,
+    };
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(&file_to_process.contents)).ok()?;
+    let mut properties_xml = String::new();
+    archive
+        .by_name(properties)
+        .ok()?
+        .read_to_string(&mut properties_xml)
+        .ok()?;
+
+    let properties = roxmltree::Document::parse(&properties_xml).ok()?;
+    let title = properties
+        .descendants()
+        .find(|node| node.has_tag_name((DC_NS, "title")))?
+        .text()?
+        .trim()
+        .to_string();
+
+    if title.is_empty() {
+        None
+    } else {
+        Some(title)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -80,7 +234,44 @@ mod tests {
             .unwrap();
 
         assert_eq!(indexed_document.name, "Cats.xlsx");
-        assert_eq!(indexed_document.body, "this sheet is about cats cats have paws they\'re pretty cool Horses are also an animal Horses don\'t have paws Weird isn\'t it? ");
+        assert_eq!(indexed_document.body, "Cats this sheet is about cats cats have paws they\'re pretty cool Horses Horses are also an animal Horses don\'t have paws Weird isn\'t it? ");
+        assert_eq!(indexed_document.sections.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_indexing_open_document_spreadsheet_file() {
+        let test_file_path = Path::new("../../../test_files/Cats.ods");
+        let indexed_document = SpreadsheetIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.name, "Animal counts");
+        assert_eq!(
+            indexed_document.sections,
+            vec![
+                Section {
+                    label: String::from("Cats"),
+                    text: String::from("Cats Name Paws Tom 4 Felix 4 Total 8 =of:=SUM([.B2:.B3]) "),
+                },
+                Section {
+                    label: String::from("Horses"),
+                    text: String::from("Horses Name Hooves Spirit 4 "),
+                },
+            ]
+        );
+        assert_eq!(
+            indexed_document.metadata,
+            vec![
+                (
+                    String::from("columns"),
+                    MetadataValue::Text(String::from("Name Paws"))
+                ),
+                (
+                    String::from("columns"),
+                    MetadataValue::Text(String::from("Name Hooves"))
+                ),
+            ]
+        );
     }
 
     #[test]
@@ -90,8 +281,20 @@ mod tests {
             SpreadsheetIndexer.supports_extension(OsStr::new("xlsx"))
         );
         assert_eq!(
-            false,
+            true,
             SpreadsheetIndexer.supports_extension(OsStr::new("xls"))
-        ); // not yet..
+        );
+        assert_eq!(
+            true,
+            SpreadsheetIndexer.supports_extension(OsStr::new("xlsb"))
+        );
+        assert_eq!(
+            true,
+            SpreadsheetIndexer.supports_extension(OsStr::new("ods"))
+        );
+        assert_eq!(
+            false,
+            SpreadsheetIndexer.supports_extension(OsStr::new("csv"))
+        );
     }
 }
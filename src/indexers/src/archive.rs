@@ -0,0 +1,237 @@
+use common::anyhow::{Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::{info, span, Level};
+use contracts::file_to_process::{calculate_hash, FileToProcess};
+
+use flate2::read::GzDecoder;
+
+use std::ffi::{OsStr, OsString};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+/// Entries bigger than this are skipped so a single archive can't exhaust memory
+const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Archives are only expanded up to this many bytes in total, so a zip bomb can't exhaust memory
+const MAX_EXPANDED_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Archives are only expanded up to this many entries
+const MAX_ENTRIES: usize = 10_000;
+
+/// Tracks how much of an archive was expanded, the remaining entries are skipped once it runs out
+struct ExpansionBudget {
+    size: u64,
+    entries: usize,
+}
+
+impl ExpansionBudget {
+    fn new() -> Self {
+        ExpansionBudget {
+            size: MAX_EXPANDED_SIZE,
+            entries: MAX_ENTRIES,
+        }
+    }
+
+    /// How many bytes can be read from an entry of this size, None when nothing more should be expanded
+    /// The size is the one declared by the archive, reads are still limited in case it lies
+    fn next_entry(&mut self, size: u64) -> Option<u64> {
+        if self.entries == 0 || size > self.size {
+            info!("Skipping the rest of an archive which expands to too much");
+            return None;
+        }
+        self.entries -= 1;
+        Some(self.size.min(MAX_ENTRY_SIZE))
+    }
+
+    fn spend(&mut self, size: usize) {
+        self.size = self.size.saturating_sub(size as u64);
+    }
+}
+
+/// Extensions of the archives we know how to look inside of
+pub fn supported_extensions() -> Vec<OsString> {
+    vec![
+        OsString::from("zip"),
+        OsString::from("tar"),
+        OsString::from("tgz"),
+        OsString::from("gz"),
+    ]
+}
+
+/// If the file is an archive we can descend into
+/// `.gz` is only an archive when it's a compressed tarball
+pub fn is_archive(path: &Path) -> bool {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("zip") | Some("tar") | Some("tgz") => true,
+        Some("gz") => is_tarball(path),
+        _ => false,
+    }
+}
+
+fn is_tarball(path: &Path) -> bool {
+    path.file_stem()
+        .map(|stem| Path::new(stem).extension() == Some(OsStr::new("tar")))
+        .unwrap_or(false)
+}
+
+/// Reads every file inside of an archive into a synthetic FileToProcess
+/// The path of each entry is virtual, eg: `/path/archive.zip!/inner/doc.csv`
+/// Returns the name of the entry inside of the archive alongside it
+pub fn expand_archive(file_to_process: &FileToProcess) -> Result<Vec<(String, FileToProcess)>> {
+    let path = file_to_process.path.to_str().unwrap();
+    span!(Level::INFO, "archive: expanding archive", path).in_scope(|| {
+        let contents = Cursor::new(&file_to_process.contents);
+        let entries = match file_to_process.path.extension().and_then(OsStr::to_str) {
+            Some("zip") => read_zip(contents),
+            Some("tar") => read_tar(contents),
+            _ => read_tar(GzDecoder::new(contents)),
+        }
+        .with_context(|| {
+            log_and_return_error_string(format!(
+                "archive: Failed to read archive at path: {:?}",
+                file_to_process.path
+            ))
+        })?;
+
+        Ok(entries
+            .into_iter()
+            .map(|(name, contents)| {
+                let path = PathBuf::from(format!("{}!/{}", path, name));
+                let hash = calculate_hash(&contents);
+                let size = contents.len() as u64;
+                (
+                    name,
+                    FileToProcess {
+                        path,
+                        hash,
+                        contents,
+                        size,
+                    },
+                )
+            })
+            .collect())
+    })
+}
+
+fn read_zip<R: Read + std::io::Seek>(reader: R) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut entries = Vec::new();
+    let mut budget = ExpansionBudget::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if entry.is_dir() {
+            continue;
+        }
+        if entry.size() > MAX_ENTRY_SIZE {
+            info!(
+                "Skipping archive entry which is too big: {:?}",
+                entry.name()
+            );
+            continue;
+        }
+        let max_size = match budget.next_entry(entry.size()) {
+            Some(max_size) => max_size,
+            None => break,
+        };
+        let mut contents = Vec::with_capacity(entry.size().min(max_size) as usize);
+        (&mut entry).take(max_size).read_to_end(&mut contents)?;
+        budget.spend(contents.len());
+        entries.push((entry.name().trim_start_matches('/').to_string(), contents));
+    }
+    Ok(entries)
+}
+
+fn read_tar<R: Read>(reader: R) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+    let mut budget = ExpansionBudget::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()?
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .trim_start_matches('/')
+            .to_string();
+        if entry.size() > MAX_ENTRY_SIZE {
+            info!("Skipping archive entry which is too big: {:?}", name);
+            continue;
+        }
+        let max_size = match budget.next_entry(entry.size()) {
+            Some(max_size) => max_size,
+            None => break,
+        };
+        let mut contents = Vec::with_capacity(entry.size().min(max_size) as usize);
+        (&mut entry).take(max_size).read_to_end(&mut contents)?;
+        budget.spend(contents.len());
+        entries.push((name, contents));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tokio;
+    use contracts::file_to_process::new_file_to_process;
+
+    #[tokio::test]
+    async fn test_expanding_zip_archive() {
+        let test_file_path = Path::new("../../test_files/Animals.zip");
+        let entries = expand_archive(&new_file_to_process(test_file_path).await).unwrap();
+
+        let names = entries
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, vec!["animals/data.csv", "animals/file.txt"]);
+        assert_eq!(
+            entries[0].1.path,
+            PathBuf::from("../../test_files/Animals.zip!/animals/data.csv")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expanding_tarball() {
+        let test_file_path = Path::new("../../test_files/Animals.tar.gz");
+        let entries = expand_archive(&new_file_to_process(test_file_path).await).unwrap();
+
+        let names = entries
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, vec!["animals/data.csv", "animals/file.txt"]);
+        assert_eq!(
+            entries[1].1.contents,
+            b"this is a file with some contents in it".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_expansion_budget() {
+        let mut budget = ExpansionBudget::new();
+        assert_eq!(budget.next_entry(10), Some(MAX_ENTRY_SIZE));
+        budget.spend(MAX_EXPANDED_SIZE as usize - 10);
+        assert_eq!(budget.next_entry(10), Some(10));
+        assert_eq!(budget.next_entry(11), None);
+
+        let mut budget = ExpansionBudget::new();
+        for _ in 0..MAX_ENTRIES {
+            assert!(budget.next_entry(0).is_some());
+        }
+        assert_eq!(budget.next_entry(0), None);
+    }
+
+    #[test]
+    fn test_is_archive() {
+        assert!(is_archive(Path::new("/a/b.zip")));
+        assert!(is_archive(Path::new("/a/b.tar")));
+        assert!(is_archive(Path::new("/a/b.tar.gz")));
+        assert!(is_archive(Path::new("/a/b.tgz")));
+        assert!(!is_archive(Path::new("/a/b.gz")));
+        assert!(!is_archive(Path::new("/a/b.pptx")));
+    }
+}
@@ -6,27 +6,41 @@
 // mod csv_indexer;
 // mod pptx_indexer;
 // mod spreadsheet_indexer;
+mod archive;
 
 use exif_indexer::exif_indexer::ExifIndexer;
+use media_indexer::media_indexer::MediaIndexer;
 use mobile_net_v2_indexer::mobile_net_v2_indexer::MobileNetV2Indexer;
 //use pdf_indexer::PdfIndexer;
 use text_indexer::text_indexer::TextIndexer;
 // pub use self::docx_indexer::DocxIndexer;
 use csv_indexer::csv_indexer::CsvIndexer;
+use ebook_indexer::ebook_indexer::EbookIndexer;
+use email_indexer::email_indexer::EmailIndexer;
+use opendocument_indexer::opendocument_indexer::OpenDocumentIndexer;
 use pptx_indexer::pptx_indexer::PptxIndexer;
 use spreadsheet_indexer::spreadsheet_indexer::SpreadsheetIndexer;
 
+pub use mobile_net_v2_indexer::model_registry::{
+    configure as configure_image_classifier, ClassifierConfig,
+};
+#[cfg(feature = "ocr")]
+pub use ocr_indexer::ocr_indexer::configure as configure_ocr;
+#[cfg(feature = "ocr")]
+use ocr_indexer::ocr_indexer::OcrIndexer;
+pub use text_indexer::text_indexer::configure as configure_text_indexer;
+
 use std::collections::HashSet;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::iter::FromIterator;
 
 use common::tokio;
-use common::tracing::instrument;
+use common::tracing::{info, instrument};
 
 use once_cell::sync::Lazy;
 
 use contracts::file_to_process::FileToProcess;
-use contracts::indexer::{DocumentSchema, Indexer};
+use contracts::indexer::{DocumentSchema, EntrySchema, Indexer};
 
 /// Container for all Indexers
 pub struct Analyzer {
@@ -36,7 +50,7 @@ pub struct Analyzer {
 impl Default for Analyzer {
     #[cfg(not(target_os = "windows"))]
     fn default() -> Analyzer {
-        let indexers: Vec<Box<dyn Indexer>> = vec![
+        let mut indexers: Vec<Box<dyn Indexer>> = vec![
             Box::new(TextIndexer),
             Box::new(ExifIndexer),
             // Box::new(PdfIndexer),
@@ -44,13 +58,19 @@ impl Default for Analyzer {
             Box::new(PptxIndexer),
             Box::new(CsvIndexer),
             Box::new(SpreadsheetIndexer),
+            Box::new(EmailIndexer),
+            Box::new(OpenDocumentIndexer),
+            Box::new(MediaIndexer),
+            Box::new(EbookIndexer),
         ];
+        indexers.extend(optional_indexers());
 
         let supported_extensions = HashSet::from_iter(
             indexers
                 .iter()
                 .map(|indexer| indexer.supported_extensions())
-                .flatten(),
+                .flatten()
+                .chain(archive::supported_extensions()),
         );
 
         Analyzer {
@@ -60,20 +80,26 @@ impl Default for Analyzer {
 
     #[cfg(target_os = "windows")]
     fn default() -> Analyzer {
-        let indexers: Vec<Box<dyn Indexer>> = vec![
+        let mut indexers: Vec<Box<dyn Indexer>> = vec![
             Box::new(TextIndexer),
             Box::new(ExifIndexer),
             // Box::new(PdfIndexer),
             Box::new(PptxIndexer),
             Box::new(CsvIndexer),
             Box::new(SpreadsheetIndexer),
+            Box::new(EmailIndexer),
+            Box::new(OpenDocumentIndexer),
+            Box::new(MediaIndexer),
+            Box::new(EbookIndexer),
         ];
+        indexers.extend(optional_indexers());
 
         let supported_extensions = HashSet::from_iter(
             indexers
                 .iter()
                 .map(|indexer| indexer.supported_extensions())
-                .flatten(),
+                .flatten()
+                .chain(archive::supported_extensions()),
         );
 
         Analyzer {
@@ -82,8 +108,19 @@ impl Default for Analyzer {
     }
 }
 
+/// Indexers which are only built when their cargo feature is enabled
+#[cfg(feature = "ocr")]
+fn optional_indexers() -> Vec<Box<dyn Indexer>> {
+    vec![Box::new(OcrIndexer)]
+}
+
+#[cfg(not(feature = "ocr"))]
+fn optional_indexers() -> Vec<Box<dyn Indexer>> {
+    Vec::new()
+}
+
 static INDEXERS: Lazy<Vec<Box<dyn Indexer>>> = Lazy::new(|| {
-    let indexers: Vec<Box<dyn Indexer>> = vec![
+    let mut indexers: Vec<Box<dyn Indexer>> = vec![
         Box::new(TextIndexer),
         Box::new(ExifIndexer),
         // Box::new(PdfIndexer),
@@ -91,19 +128,141 @@ static INDEXERS: Lazy<Vec<Box<dyn Indexer>>> = Lazy::new(|| {
         Box::new(PptxIndexer),
         Box::new(CsvIndexer),
         Box::new(SpreadsheetIndexer),
+        Box::new(EmailIndexer),
+        Box::new(OpenDocumentIndexer),
+        Box::new(MediaIndexer),
+        Box::new(EbookIndexer),
     ];
+    indexers.extend(optional_indexers());
     indexers
 });
 
+/// Runs every Indexer which supports the extension over the file
+/// Returns the documents extracted from the file itself and the documents nested inside of it
+/// Archives are descended into and each file inside of them becomes a nested document
 #[instrument(skip(file_to_process))]
-pub async fn analyze(extension: OsString, file_to_process: FileToProcess) -> Vec<DocumentSchema> {
+pub async fn analyze(
+    extension: OsString,
+    file_to_process: FileToProcess,
+) -> (Vec<DocumentSchema>, Vec<EntrySchema>) {
     let processing_task = tokio::task::spawn_blocking(move || {
-        INDEXERS
-            .iter()
-            .filter(|indexer| indexer.supports_extension(extension.as_os_str()))
-            .filter_map(|indexer| indexer.index_file(&file_to_process).ok())
-            .collect()
+        if archive::is_archive(&file_to_process.path) {
+            // Only the start of a big archive is read, which can't be expanded
+            if file_to_process.is_truncated() {
+                info!(
+                    "Skipping the entries of {:?}, it's too big to read",
+                    file_to_process.path
+                );
+                return (Vec::new(), Vec::new());
+            }
+            return (Vec::new(), analyze_archive(&file_to_process));
+        }
+
+        index(extension.as_os_str(), &file_to_process)
     });
 
     processing_task.await.unwrap()
 }
+
+fn index(
+    extension: &OsStr,
+    file_to_process: &FileToProcess,
+) -> (Vec<DocumentSchema>, Vec<EntrySchema>) {
+    let indexers = INDEXERS
+        .iter()
+        .filter(|indexer| indexer.supports_extension(extension))
+        .filter(|indexer| {
+            let skipped = file_to_process.is_truncated() && !indexer.indexes_truncated_files();
+            if skipped {
+                info!(
+                    "Skipping an indexer which needs all of {:?}, it's too big to read",
+                    file_to_process.path
+                );
+            }
+            !skipped
+        })
+        .collect::<Vec<&Box<dyn Indexer>>>();
+
+    let documents = indexers
+        .iter()
+        .filter_map(|indexer| indexer.index_file(file_to_process).ok())
+        .collect();
+
+    let entries = indexers
+        .iter()
+        .filter_map(|indexer| indexer.index_entries(file_to_process).ok())
+        .flatten()
+        .collect();
+
+    (documents, entries)
+}
+
+/// Runs the Indexers over every file inside of an archive
+/// Entries of files inside the archive (eg: an mbox in a zip) and nested archives are not descended into
+fn analyze_archive(file_to_process: &FileToProcess) -> Vec<EntrySchema> {
+    let archive_entries = match archive::expand_archive(file_to_process) {
+        Ok(archive_entries) => archive_entries,
+        Err(_) => return Vec::new(),
+    };
+
+    archive_entries
+        .into_iter()
+        .filter_map(|(name, entry_to_process)| {
+            let extension = entry_to_process.path.extension()?.to_os_string();
+            let (documents, _entries) = index(&extension, &entry_to_process);
+            merge_documents(documents).map(|document| EntrySchema {
+                entry: name,
+                hash: entry_to_process.hash,
+                size: entry_to_process.size,
+                document,
+            })
+        })
+        .collect()
+}
+
+/// Combines what each Indexer extracted from the same file into a single document
+/// The name of the first document is kept, bodies, metadata and sections are concatenated
+pub fn merge_documents(documents: Vec<DocumentSchema>) -> Option<DocumentSchema> {
+    let mut documents = documents.into_iter();
+    let mut merged = documents.next()?;
+    for document in documents {
+        merged.body.push(' ');
+        merged.body.push_str(&document.body);
+        merged.metadata.extend(document.metadata);
+        merged.sections.extend(document.sections);
+    }
+    Some(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use contracts::file_to_process::calculate_hash;
+    use std::path::PathBuf;
+
+    fn truncated_file(name: &str, contents: &[u8]) -> FileToProcess {
+        FileToProcess {
+            path: PathBuf::from(name),
+            hash: calculate_hash(contents),
+            contents: contents.to_vec(),
+            size: contents.len() as u64 + 1,
+        }
+    }
+
+    #[test]
+    fn test_truncated_files_skip_indexers_which_need_all_of_them() {
+        let (documents, entries) = index(
+            OsStr::new("csv"),
+            &truncated_file("/data/animals.csv", b"name,legs\ncat,4\n"),
+        );
+        assert!(documents.is_empty());
+        assert!(entries.is_empty());
+
+        let (documents, _) = index(
+            OsStr::new("txt"),
+            &truncated_file("/data/animals.txt", b"cats have four legs"),
+        );
+        assert_eq!(documents.len(), 1);
+        assert!(documents[0].body.starts_with("cats have four legs"));
+    }
+}
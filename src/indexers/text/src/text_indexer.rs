@@ -1,20 +1,47 @@
-use common::anyhow::{Context, Result};
-use common::error_adapter::log_and_return_error_string;
-use common::tracing::{span, Level};
+use common::anyhow::{anyhow, Result};
+use common::tracing::{info, span, Level};
 use contracts::file_to_process::FileToProcess;
-use contracts::indexer::{DocumentSchema, Indexer};
+use contracts::indexer::{DocumentSchema, Indexer, MetadataValue};
 use std::ffi::{OsStr, OsString};
 use std::str;
 
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use once_cell::sync::OnceCell;
+
+const TEXT_EXTENSIONS: [&str; 16] = [
+    "txt", "text", "log", "md", "markdown", "rst", "json", "yaml", "yml", "toml", "ini", "cfg",
+    "conf", "tex", "srt", "vtt",
+];
+
+/// How many bytes of a text file are indexed unless configured otherwise
+pub const DEFAULT_MAX_SIZE: usize = 10 * 1024 * 1024;
+
+static MAX_SIZE: OnceCell<usize> = OnceCell::new();
+
+/// Sets how many bytes of a text file are indexed, the rest of the file is skipped
+pub fn configure(max_size: usize) -> Result<()> {
+    MAX_SIZE
+        .set(max_size)
+        .map_err(|_| anyhow!("text_indexer: The maximum size was already configured"))
+}
+
 pub struct TextIndexer;
 
 impl Indexer for TextIndexer {
     fn supports_extension(&self, extension: &OsStr) -> bool {
-        extension == OsStr::new("txt")
+        TEXT_EXTENSIONS
+            .iter()
+            .any(|supported| extension == OsStr::new(supported))
     }
 
     fn supported_extensions(&self) -> Vec<OsString> {
-        vec![OsString::from("txt")]
+        TEXT_EXTENSIONS.iter().map(OsString::from).collect()
+    }
+
+    /// The start of a big text file is indexed
+    fn indexes_truncated_files(&self) -> bool {
+        true
     }
 
     fn index_file(&self, file_to_process: &FileToProcess) -> Result<DocumentSchema> {
@@ -26,21 +53,81 @@ impl Indexer for TextIndexer {
                 .to_string_lossy()
                 .to_string();
 
-            let body = str::from_utf8(&file_to_process.contents).with_context(|| {
-                log_and_return_error_string(format!(
-                    "text_indexer: Failed to read file to string at path: {:?}",
-                    file_to_process.path
-                ))
-            })?;
+            let max_size = *MAX_SIZE.get_or_init(|| DEFAULT_MAX_SIZE);
+            let (body, encoding, truncated) = span!(Level::INFO, "text_indexer: Decoding text")
+                .in_scope(|| {
+                    text_body(
+                        &file_to_process.contents,
+                        max_size,
+                        file_to_process.is_truncated(),
+                    )
+                });
+            info!("Decoded {:?} as {}", file_to_process.path, encoding.name());
+
+            // Files which were too big to be indexed whole are marked so the ui can say only their start is searched
+            let mut metadata = Vec::new();
+            if truncated {
+                metadata.push((String::from("truncated"), MetadataValue::I64(1)));
+            }
 
             Ok(DocumentSchema {
-                name: name,
-                body: body.to_string(),
+                name,
+                body,
+                metadata,
+                ..Default::default()
             })
         })
     }
 }
 
+/// The text of at most the first `max_size` bytes, and if the rest of the file is skipped
+fn text_body(
+    contents: &[u8],
+    max_size: usize,
+    is_truncated: bool,
+) -> (String, &'static Encoding, bool) {
+    let truncated = is_truncated || contents.len() > max_size;
+    let (body, encoding) = decode(&contents[..contents.len().min(max_size)], !truncated);
+    (body, encoding, truncated)
+}
+
+/// Decodes the text with the encoding from its byte order mark, as UTF-8 if it's valid or else with a guessed encoding
+/// Bytes which aren't valid in the encoding become replacement characters instead of failing the whole file
+/// When the text is only the start of a file, a character cut off at its end is dropped
+fn decode(contents: &[u8], is_complete: bool) -> (String, &'static Encoding) {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(contents) {
+        return (
+            decode_with(encoding, &contents[bom_length..], is_complete),
+            encoding,
+        );
+    }
+
+    match str::from_utf8(contents) {
+        Ok(text) => return (text.to_string(), UTF_8),
+        // Only the last character is incomplete, the text was cut in the middle of it
+        Err(e) if e.error_len().is_none() => {
+            return (decode_with(UTF_8, contents, is_complete), UTF_8)
+        }
+        Err(_) => {}
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(contents, is_complete);
+    let encoding = detector.guess(None, true);
+    (decode_with(encoding, contents, is_complete), encoding)
+}
+
+/// An incomplete character at the end is only replaced when the text is complete, otherwise it's left out
+fn decode_with(encoding: &'static Encoding, contents: &[u8], is_complete: bool) -> String {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let capacity = decoder
+        .max_utf8_buffer_length(contents.len())
+        .unwrap_or(contents.len());
+    let mut text = String::with_capacity(capacity);
+    let _ = decoder.decode_to_string(contents, &mut text, is_complete);
+    text
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,9 +150,90 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_indexing_utf16_file() {
+        let test_file_path = Path::new("../../../test_files/utf16.txt");
+        let indexed_document = TextIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.body, "cats purr when they’re happy");
+    }
+
+    #[tokio::test]
+    async fn test_indexing_windows_1252_file() {
+        let test_file_path = Path::new("../../../test_files/windows1252.txt");
+        let indexed_document = TextIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(indexed_document.body, "The café’s cat is named Zoë");
+    }
+
+    #[tokio::test]
+    async fn test_indexing_shift_jis_file() {
+        let test_file_path = Path::new("../../../test_files/shift_jis.txt");
+        let indexed_document = TextIndexer
+            .index_file(&new_file_to_process(test_file_path).await)
+            .unwrap();
+
+        assert_eq!(
+            indexed_document.body,
+            "吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。何でも薄暗いじめじめした所でニャーニャー泣いていた事だけは記憶している。"
+        );
+    }
+
+    #[test]
+    fn test_big_files_are_truncated() {
+        let (body, _, truncated) = text_body("cats purr, dogs bark".as_bytes(), 9, false);
+        assert_eq!(body, "cats purr");
+        assert!(truncated);
+
+        // "é" is two bytes in UTF-8, the cut falls between them
+        let (body, _, _) = text_body("café au lait".as_bytes(), 4, false);
+        assert_eq!(body, "caf");
+
+        // Replacement characters which are part of the text are kept
+        let (body, _, _) = text_body("cats \u{FFFD}\u{FFFD} purr".as_bytes(), 11, false);
+        assert_eq!(body, "cats \u{FFFD}\u{FFFD}");
+
+        let (body, _, truncated) = text_body(b"cats", 9, true);
+        assert_eq!(body, "cats");
+        assert!(truncated);
+
+        let (_, _, truncated) = text_body(b"cats", 9, false);
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn test_truncated_files_are_marked() {
+        let file_to_process = FileToProcess {
+            path: std::path::PathBuf::from("/logs/big.log"),
+            hash: contracts::file_to_process::calculate_hash(b"cats"),
+            contents: b"cats".to_vec(),
+            size: 5,
+        };
+        let indexed_document = TextIndexer.index_file(&file_to_process).unwrap();
+
+        assert_eq!(indexed_document.body, "cats");
+        assert_eq!(
+            indexed_document.metadata,
+            vec![(String::from("truncated"), MetadataValue::I64(1))]
+        );
+    }
+
+    #[test]
+    fn test_invalid_bytes_are_replaced() {
+        let (text, encoding) = decode(b"\xEF\xBB\xBFcats \xFF paws", true);
+        assert_eq!(encoding, UTF_8);
+        assert_eq!(text, "cats \u{FFFD} paws");
+    }
+
     #[test]
     fn test_supports_text_extension() {
         assert_eq!(true, TextIndexer.supports_extension(OsStr::new("txt")));
+        assert_eq!(true, TextIndexer.supports_extension(OsStr::new("log")));
+        assert_eq!(true, TextIndexer.supports_extension(OsStr::new("yaml")));
         assert_eq!(false, TextIndexer.supports_extension(OsStr::new("png")));
     }
 }
@@ -2,13 +2,18 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
+pub mod annotations;
 pub mod config;
+pub mod open_history;
+pub mod relevance;
 pub mod routes;
 pub mod searcher;
 pub mod tantivy_process;
 
 pub extern crate contracts;
 pub extern crate custom_tantivy;
+#[cfg(feature = "semantic")]
+pub extern crate embeddings;
 pub extern crate indexers;
 
 mod file_watcher;
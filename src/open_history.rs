@@ -0,0 +1,71 @@
+use common::anyhow::{Context, Result};
+use common::error_adapter::log_and_return_error_string;
+use common::tracing::info;
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+
+/// How many times each document was opened from podium, by the hash of the document
+/// Every open is appended to a file as a line with the hash, so the counts survive restarts and reindexing
+pub struct OpenHistory {
+    counts: RwLock<HashMap<String, u32>>,
+    file: Mutex<File>,
+}
+
+impl OpenHistory {
+    /// Loads the opens stored at this path, the file is created if it doesn't exist
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| {
+                log_and_return_error_string(format!(
+                    "open_history: Failed to open the history at path: {:?}",
+                    path
+                ))
+            })?;
+
+        let mut counts = HashMap::new();
+        for hash in BufReader::new(&file).lines().map_while(Result::ok) {
+            if !hash.is_empty() {
+                *counts.entry(hash).or_insert(0) += 1;
+            }
+        }
+        info!(
+            "Loaded the opens of {} documents from {:?}",
+            counts.len(),
+            path
+        );
+
+        Ok(OpenHistory {
+            counts: RwLock::new(counts),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Counts an open of the document and writes it to disk
+    pub fn record(&self, hash: &str) -> Result<()> {
+        writeln!(self.file.lock().unwrap(), "{}", hash).with_context(|| {
+            log_and_return_error_string(format!(
+                "open_history: Failed to record the open of document: {:?}",
+                hash
+            ))
+        })?;
+        *self
+            .counts
+            .write()
+            .unwrap()
+            .entry(hash.to_string())
+            .or_insert(0) += 1;
+        Ok(())
+    }
+
+    pub fn count(&self, hash: &str) -> u32 {
+        self.counts.read().unwrap().get(hash).cloned().unwrap_or(0)
+    }
+}
@@ -0,0 +1,261 @@
+use common::anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+
+use std::path::PathBuf;
+
+/// How many seconds there are in a day, the recency half life is set in days
+const SECONDS_PER_DAY: f32 = 24.0 * 60.0 * 60.0;
+
+/// How search results are ranked, set in the `[relevance]` table of the config file
+/// Every setting can be overridden by a search request
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Relevance {
+    /// How much a match in the title counts, relative to the other fields
+    pub title_boost: f32,
+    /// How much a match in a heading counts, eg: a chapter or a section title
+    pub headings_boost: f32,
+    pub body_boost: f32,
+    /// How much the score of a file modified just now is raised, eg: 0.5 for 50%, 0 turns recency off
+    pub recency_boost: f32,
+    /// After how many days the recency boost of a file is halved
+    pub recency_half_life_days: f32,
+    /// Scores of files in matching locations are multiplied by the boost, eg: to demote `Downloads/`
+    pub path_boosts: Vec<PathBoost>,
+}
+
+impl Default for Relevance {
+    fn default() -> Self {
+        Relevance {
+            title_boost: 3.0,
+            headings_boost: 2.0,
+            body_boost: 1.0,
+            recency_boost: 0.5,
+            recency_half_life_days: 30.0,
+            path_boosts: Vec::new(),
+        }
+    }
+}
+
+/// A location matches when its path contains the pattern, eg: `Downloads/` or a scan directory
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct PathBoost {
+    pub pattern: String,
+    /// Below 1 demotes the files, above 1 promotes them
+    pub boost: f32,
+}
+
+/// Relevance settings of a single search, the ones which aren't set keep their configured value
+#[derive(Debug, Default, Deserialize)]
+pub struct RelevanceOverrides {
+    pub title_boost: Option<f32>,
+    pub headings_boost: Option<f32>,
+    pub body_boost: Option<f32>,
+    pub recency_boost: Option<f32>,
+    pub recency_half_life_days: Option<f32>,
+    /// Replaces the configured path boosts, as `pattern:boost` pairs separated by commas, eg: `Downloads/:0.5,Projects/:2`
+    pub path_boosts: Option<String>,
+}
+
+impl Relevance {
+    /// These settings with the ones set by a request replacing them
+    pub fn with_overrides(&self, overrides: &RelevanceOverrides) -> Result<Relevance> {
+        let relevance = Relevance {
+            title_boost: overrides.title_boost.unwrap_or(self.title_boost),
+            headings_boost: overrides.headings_boost.unwrap_or(self.headings_boost),
+            body_boost: overrides.body_boost.unwrap_or(self.body_boost),
+            recency_boost: overrides.recency_boost.unwrap_or(self.recency_boost),
+            recency_half_life_days: overrides
+                .recency_half_life_days
+                .unwrap_or(self.recency_half_life_days),
+            path_boosts: match &overrides.path_boosts {
+                Some(path_boosts) => parse_path_boosts(path_boosts)?,
+                None => self.path_boosts.clone(),
+            },
+        };
+        relevance.validate()?;
+        Ok(relevance)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let boosts = [
+            self.title_boost,
+            self.headings_boost,
+            self.body_boost,
+            self.recency_boost,
+        ];
+        if boosts
+            .iter()
+            .chain(self.path_boosts.iter().map(|path_boost| &path_boost.boost))
+            .any(|boost| !boost.is_finite() || *boost < 0.0)
+        {
+            bail!("Boosts have to be numbers >= 0");
+        }
+        if !self.recency_half_life_days.is_finite() || self.recency_half_life_days <= 0.0 {
+            bail!("The recency half life has to be a number of days > 0");
+        }
+        Ok(())
+    }
+
+    /// Multiplies the score of a file by up to `1 + recency_boost`, halving the boost every half life since it was modified
+    /// Files without a modification time, eg: indexed by an older version, aren't boosted
+    pub fn recency_factor(&self, modified: Option<i64>, now: i64) -> f32 {
+        match modified {
+            Some(modified) => {
+                let age_days = (now - modified).max(0) as f32 / SECONDS_PER_DAY;
+                1.0 + self.recency_boost * 0.5f32.powf(age_days / self.recency_half_life_days)
+            }
+            None => 1.0,
+        }
+    }
+
+    /// The boosts of every pattern a location matches multiplied together
+    /// A file found in more than one location gets the factor of its best location
+    pub fn path_factor(&self, locations: &[PathBuf]) -> f32 {
+        if self.path_boosts.is_empty() {
+            return 1.0;
+        }
+        locations
+            .iter()
+            .map(|location| {
+                let location = location.to_string_lossy();
+                self.path_boosts
+                    .iter()
+                    .filter(|path_boost| location.contains(&path_boost.pattern))
+                    .map(|path_boost| path_boost.boost)
+                    .product::<f32>()
+            })
+            .reduce(f32::max)
+            .unwrap_or(1.0)
+    }
+}
+
+/// Parses `pattern:boost` pairs separated by commas, the boost is after the last colon so patterns can contain colons
+fn parse_path_boosts(path_boosts: &str) -> Result<Vec<PathBoost>> {
+    path_boosts
+        .split(',')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (pattern, boost) = pair.rsplit_once(':').ok_or_else(|| {
+                anyhow!(
+                    "Path boosts have to be `pattern:boost` pairs, got {:?}",
+                    pair
+                )
+            })?;
+            let boost = boost
+                .parse::<f32>()
+                .map_err(|_| anyhow!("The boost of {:?} isn't a number", pattern))?;
+            Ok(PathBoost {
+                pattern: pattern.to_string(),
+                boost,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path_boost(pattern: &str, boost: f32) -> PathBoost {
+        PathBoost {
+            pattern: pattern.to_string(),
+            boost,
+        }
+    }
+
+    #[test]
+    fn test_recency_boost_halves_every_half_life() {
+        let relevance = Relevance::default();
+        let now = 100 * SECONDS_PER_DAY as i64;
+        let half_life = 30 * SECONDS_PER_DAY as i64;
+
+        assert_eq!(relevance.recency_factor(Some(now), now), 1.5);
+        assert_eq!(relevance.recency_factor(Some(now - half_life), now), 1.25);
+        assert_eq!(
+            relevance.recency_factor(Some(now - 2 * half_life), now),
+            1.125
+        );
+        assert_eq!(relevance.recency_factor(None, now), 1.0);
+        // Modification times in the future aren't boosted more than now
+        assert_eq!(relevance.recency_factor(Some(now + half_life), now), 1.5);
+    }
+
+    #[test]
+    fn test_path_boosts_of_a_location_are_multiplied() {
+        let relevance = Relevance {
+            path_boosts: vec![path_boost("Downloads/", 0.5), path_boost("scans", 0.5)],
+            ..Relevance::default()
+        };
+        assert_eq!(
+            relevance.path_factor(&[PathBuf::from("/home/me/Downloads/scans/invoice.pdf")]),
+            0.25
+        );
+        assert_eq!(
+            relevance.path_factor(&[PathBuf::from("/home/me/Documents/invoice.pdf")]),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_path_factor_of_the_best_location() {
+        let relevance = Relevance {
+            path_boosts: vec![path_boost("Downloads/", 0.5), path_boost("Projects/", 2.0)],
+            ..Relevance::default()
+        };
+        let locations = [
+            PathBuf::from("/home/me/Downloads/spec.pdf"),
+            PathBuf::from("/home/me/Projects/spec.pdf"),
+        ];
+        assert_eq!(relevance.path_factor(&locations), 2.0);
+        assert_eq!(relevance.path_factor(&locations[..1]), 0.5);
+    }
+
+    #[test]
+    fn test_parse_path_boosts() {
+        assert_eq!(
+            parse_path_boosts("Downloads/:0.5,C:\\Scans:with:colons:0.5,").unwrap(),
+            vec![
+                path_boost("Downloads/", 0.5),
+                path_boost("C:\\Scans:with:colons", 0.5)
+            ]
+        );
+        assert!(parse_path_boosts("Downloads/").is_err());
+        assert!(parse_path_boosts("Downloads/:lots").is_err());
+    }
+
+    #[test]
+    fn test_negative_and_nan_boosts_are_rejected() {
+        let relevance = Relevance::default();
+        let overrides = |path_boosts: &str| RelevanceOverrides {
+            path_boosts: Some(path_boosts.to_string()),
+            ..RelevanceOverrides::default()
+        };
+
+        assert!(relevance.with_overrides(&overrides("Projects/:2")).is_ok());
+        assert!(relevance
+            .with_overrides(&overrides("Downloads/:-0.5"))
+            .is_err());
+        assert!(relevance
+            .with_overrides(&overrides("Downloads/:NaN"))
+            .is_err());
+        assert!(relevance
+            .with_overrides(&RelevanceOverrides {
+                title_boost: Some(f32::NAN),
+                ..RelevanceOverrides::default()
+            })
+            .is_err());
+        assert!(relevance
+            .with_overrides(&RelevanceOverrides {
+                body_boost: Some(-1.0),
+                ..RelevanceOverrides::default()
+            })
+            .is_err());
+        assert!(relevance
+            .with_overrides(&RelevanceOverrides {
+                recency_half_life_days: Some(0.0),
+                ..RelevanceOverrides::default()
+            })
+            .is_err());
+    }
+}
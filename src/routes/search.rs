@@ -1,22 +1,450 @@
+use crate::relevance::RelevanceOverrides;
 use crate::routes::app_state::*;
+use crate::searcher::{
+    AnnotationUpdate, GeoArea, GeoQuery, IndexedFile, OpenAction, SearchMode, Sort, SortKey,
+    SortOrder, DEFAULT_FUZZY_DISTANCE, DEFAULT_MAX_HASH_DISTANCE, DEFAULT_MAX_SIMHASH_DISTANCE,
+    DEFAULT_RELATED_LIMIT, DEFAULT_SEMANTIC_WEIGHT, DEFAULT_SUGGESTION_LIMIT,
+};
 use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
 use tracing::{info, span, Level};
 
 pub fn server_config(cfg: &mut web::ServiceConfig) {
     cfg.route("/search/{query}", web::get().to(index));
+    cfg.route("/suggest", web::get().to(suggest));
+    cfg.route("/geo", web::get().to(geo));
+    cfg.route("/related", web::get().to(related));
+    cfg.route("/similar", web::get().to(similar));
+    cfg.route("/similar/clusters", web::get().to(similar_clusters));
+    cfg.route("/duplicates", web::get().to(duplicates));
+    cfg.route("/duplicates/near", web::get().to(near_duplicates));
+    cfg.route("/annotations", web::get().to(annotation));
+    cfg.route("/annotations", web::post().to(annotate));
+    cfg.route("/open", web::post().to(open));
+    cfg.route("/reveal", web::post().to(reveal));
 }
 
-async fn index(app_state: web::Data<AppState>, req: HttpRequest) -> HttpResponse {
+/// Parameters of a search, eg: `/search/cats?mode=hybrid&semantic_weight=0.7` or `/search/spredsheet?mode=fuzzy&distance=2`
+#[derive(Deserialize, Debug)]
+pub struct SearchParams {
+    #[serde(default)]
+    mode: SearchMode,
+    /// Only used by hybrid searches, between 0 for keywords only and 1 for meaning only
+    semantic_weight: Option<f32>,
+    /// Only used by fuzzy searches, how many typos a word can have, 1 or 2
+    distance: Option<u8>,
+    /// Eg: `?sort=modified` for the newest files first, hybrid searches can only be sorted by relevance
+    #[serde(default)]
+    sort: SortKey,
+    /// `asc` or `desc`, defaults to the best match, newest or biggest first and names from A to Z
+    order: Option<SortOrder>,
+}
+
+/// The relevance settings of a search can be overridden in the same query string, eg: `/search/cats?title_boost=5&recency_boost=0`
+async fn index(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    params: web::Query<SearchParams>,
+    overrides: web::Query<RelevanceOverrides>,
+) -> HttpResponse {
     println!("{:?}", req);
 
     let query: String = req.match_info().query("query").parse().unwrap();
     println!("{:?}", query);
 
-    let response = span!(Level::INFO, "search_query").in_scope(|| app_state.searcher.search(query));
+    let relevance = match app_state.searcher.relevance().with_overrides(&overrides) {
+        Ok(relevance) => relevance,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let mut sort = Sort::by(params.sort);
+    if let Some(order) = params.order {
+        sort.order = order;
+    }
+    if params.mode == SearchMode::Hybrid && sort != Sort::default() {
+        return HttpResponse::BadRequest().body("Hybrid searches can only be sorted by relevance");
+    }
+
+    let response = span!(Level::INFO, "search_query").in_scope(|| match params.mode {
+        SearchMode::Keyword => app_state.searcher.search(query.clone(), &relevance, sort),
+        SearchMode::Hybrid => app_state.searcher.hybrid_search(
+            &query,
+            params.semantic_weight.unwrap_or(DEFAULT_SEMANTIC_WEIGHT),
+            &relevance,
+        ),
+        SearchMode::Fuzzy => app_state.searcher.fuzzy_search(
+            &query,
+            params.distance.unwrap_or(DEFAULT_FUZZY_DISTANCE),
+            &relevance,
+            sort,
+        ),
+        SearchMode::Prefix => app_state.searcher.prefix_search(&query, &relevance, sort),
+    });
+    let response = response.and_then(|results| app_state.searcher.search_response(&query, results));
+
+    match response {
+        Ok(response) => {
+            let result = serde_json::to_string(&response).unwrap();
+            info!("Found results: {:?}", &result);
+            HttpResponse::Ok().body(result)
+        }
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// Parameters of a suggestion request, eg: `/suggest?q=red%20spre&limit=5`
+#[derive(Deserialize, Debug)]
+pub struct SuggestParams {
+    /// What the user typed so far
+    q: String,
+    limit: Option<usize>,
+}
+
+async fn suggest(
+    app_state: web::Data<AppState>,
+    params: web::Query<SuggestParams>,
+) -> HttpResponse {
+    info!("Suggestions for {:?}", params);
+
+    let response = span!(Level::INFO, "suggest_query").in_scope(|| {
+        app_state
+            .searcher
+            .suggest(&params.q, params.limit.unwrap_or(DEFAULT_SUGGESTION_LIMIT))
+    });
+
+    match response {
+        Ok(response) => {
+            let result = serde_json::to_string(&response).unwrap();
+            info!("Found results: {:?}", &result);
+            HttpResponse::Ok().body(result)
+        }
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// Parameters of a geo search
+/// Eg: `/geo?lat=44.57&lon=26.07&radius_km=5`, `/geo?min_lat=44&min_lon=26&max_lat=45&max_lon=27`
+/// or `/geo?place=Otopeni&q=cats`
+#[derive(Deserialize, Debug)]
+pub struct GeoParams {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    radius_km: Option<f64>,
+    min_lat: Option<f64>,
+    min_lon: Option<f64>,
+    max_lat: Option<f64>,
+    max_lon: Option<f64>,
+    place: Option<String>,
+    q: Option<String>,
+}
+
+impl GeoParams {
+    fn area(&self) -> Result<Option<GeoArea>, &'static str> {
+        match *self {
+            GeoParams {
+                lat: Some(lat),
+                lon: Some(lon),
+                radius_km: Some(radius_km),
+                min_lat: None,
+                min_lon: None,
+                max_lat: None,
+                max_lon: None,
+                ..
+            } => Ok(Some(GeoArea::Radius {
+                lat,
+                lon,
+                radius_km,
+            })),
+            GeoParams {
+                lat: None,
+                lon: None,
+                radius_km: None,
+                min_lat: Some(min_lat),
+                min_lon: Some(min_lon),
+                max_lat: Some(max_lat),
+                max_lon: Some(max_lon),
+                ..
+            } => Ok(Some(GeoArea::BoundingBox {
+                min_lat,
+                min_lon,
+                max_lat,
+                max_lon,
+            })),
+            GeoParams {
+                lat: None,
+                lon: None,
+                radius_km: None,
+                min_lat: None,
+                min_lon: None,
+                max_lat: None,
+                max_lon: None,
+                ..
+            } => Ok(None),
+            _ => Err(
+                "Expected either lat, lon and radius_km or min_lat, min_lon, max_lat and max_lon",
+            ),
+        }
+    }
+}
+
+async fn geo(app_state: web::Data<AppState>, params: web::Query<GeoParams>) -> HttpResponse {
+    info!("Geo search with {:?}", params);
+
+    let area = match params.area() {
+        Ok(area) => area,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    let geo_query = GeoQuery {
+        area,
+        place: params.place.clone(),
+        query: params.q.clone(),
+    };
+
+    let response =
+        span!(Level::INFO, "geo_query").in_scope(|| app_state.searcher.geo_search(&geo_query));
+
+    match response {
+        Ok(response) => {
+            let result = serde_json::to_string(&response).unwrap();
+            info!("Found results: {:?}", &result);
+            HttpResponse::Ok().body(result)
+        }
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// Parameters of a related documents search, eg: `/related?path=/home/docs/spec.pdf&limit=5`
+/// The file can also be given by the hash of its contents, eg: `/related?hash=8f2a...`
+#[derive(Deserialize, Debug)]
+pub struct RelatedParams {
+    path: Option<PathBuf>,
+    hash: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn related(
+    app_state: web::Data<AppState>,
+    params: web::Query<RelatedParams>,
+) -> HttpResponse {
+    info!("Related documents search with {:?}", params);
+
+    let file = match indexed_file(&params.path, &params.hash) {
+        Some(file) => file,
+        None => return HttpResponse::BadRequest().body("Either a path or a hash is needed"),
+    };
+
+    let response = span!(Level::INFO, "related_query").in_scope(|| {
+        app_state
+            .searcher
+            .related(&file, params.limit.unwrap_or(DEFAULT_RELATED_LIMIT))
+    });
+
+    match response {
+        Ok(response) => {
+            let result = serde_json::to_string(&response).unwrap();
+            info!("Found results: {:?}", &result);
+            HttpResponse::Ok().body(result)
+        }
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// A file is pointed to by one of its locations or by the hash of its contents
+fn indexed_file(path: &Option<PathBuf>, hash: &Option<String>) -> Option<IndexedFile> {
+    match (path, hash) {
+        (Some(path), _) => Some(IndexedFile::Location(path.clone())),
+        (None, Some(hash)) => Some(IndexedFile::Hash(hash.clone())),
+        (None, None) => None,
+    }
+}
+
+/// Parameters of an annotation lookup, eg: `/annotations?path=/home/docs/spec.pdf` or `/annotations?hash=8f2a...`
+#[derive(Deserialize, Debug)]
+pub struct AnnotationParams {
+    path: Option<PathBuf>,
+    hash: Option<String>,
+}
+
+async fn annotation(
+    app_state: web::Data<AppState>,
+    params: web::Query<AnnotationParams>,
+) -> HttpResponse {
+    info!("Annotation of {:?}", params);
+
+    let file = match indexed_file(&params.path, &params.hash) {
+        Some(file) => file,
+        None => return HttpResponse::BadRequest().body("Either a path or a hash is needed"),
+    };
+
+    let response =
+        span!(Level::INFO, "annotation").in_scope(|| app_state.searcher.annotation(&file));
+
+    match response {
+        Ok(response) => HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// Body of an annotate request, eg: `{"path": "/home/me/cats.txt", "tags": ["pets", "to read"], "note": "From Ana"}`
+/// Tags or a note which aren't set are kept, an empty list or note removes them
+#[derive(Deserialize, Debug)]
+pub struct AnnotateRequest {
+    path: Option<PathBuf>,
+    hash: Option<String>,
+    #[serde(flatten)]
+    update: AnnotationUpdate,
+}
+
+async fn annotate(
+    app_state: web::Data<AppState>,
+    request: web::Json<AnnotateRequest>,
+) -> HttpResponse {
+    info!("Annotating with {:?}", request);
+
+    let request = request.into_inner();
+    let file = match indexed_file(&request.path, &request.hash) {
+        Some(file) => file,
+        None => return HttpResponse::BadRequest().body("Either a path or a hash is needed"),
+    };
+
+    let response = span!(Level::INFO, "annotate")
+        .in_scope(|| app_state.searcher.annotate(&file, request.update));
+
+    match response {
+        Ok(response) => HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// Parameters of a similar images search, eg: `/similar?path=/home/photos/cat.jpg&limit=20`
+#[derive(Deserialize, Debug)]
+pub struct SimilarParams {
+    /// The location of an indexed image, as returned by a search
+    path: PathBuf,
+    limit: Option<usize>,
+}
+
+async fn similar(
+    app_state: web::Data<AppState>,
+    params: web::Query<SimilarParams>,
+) -> HttpResponse {
+    info!("Similar images search with {:?}", params);
+
+    let response = span!(Level::INFO, "similar_query").in_scope(|| {
+        app_state
+            .searcher
+            .similar_images(&params.path, params.limit.unwrap_or(10))
+    });
+
+    match response {
+        Ok(response) => {
+            let result = serde_json::to_string(&response).unwrap();
+            info!("Found results: {:?}", &result);
+            HttpResponse::Ok().body(result)
+        }
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// Parameters of a near-duplicate images search, eg: `/similar/clusters?max_distance=6`
+#[derive(Deserialize, Debug)]
+pub struct ClusterParams {
+    /// How many bits of the 64 bit perceptual hashes can differ
+    max_distance: Option<u32>,
+}
+
+async fn similar_clusters(
+    app_state: web::Data<AppState>,
+    params: web::Query<ClusterParams>,
+) -> HttpResponse {
+    info!("Near-duplicate images search with {:?}", params);
+
+    let response = span!(Level::INFO, "similar_clusters_query").in_scope(|| {
+        app_state
+            .searcher
+            .image_clusters(params.max_distance.unwrap_or(DEFAULT_MAX_HASH_DISTANCE))
+    });
+
+    match response {
+        Ok(response) => {
+            let result = serde_json::to_string(&response).unwrap();
+            info!("Found results: {:?}", &result);
+            HttpResponse::Ok().body(result)
+        }
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+async fn duplicates(app_state: web::Data<AppState>) -> HttpResponse {
+    info!("Duplicate files search");
+
+    let response =
+        span!(Level::INFO, "duplicates_query").in_scope(|| app_state.searcher.exact_duplicates());
+
+    match response {
+        Ok(response) => {
+            let result = serde_json::to_string(&response).unwrap();
+            info!("Found results: {:?}", &result);
+            HttpResponse::Ok().body(result)
+        }
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// Parameters of a near-duplicate documents search, eg: `/duplicates/near?max_distance=5`
+#[derive(Deserialize, Debug)]
+pub struct NearDuplicatesParams {
+    /// How many bits of the 64 bit SimHashes can differ
+    max_distance: Option<u32>,
+}
+
+async fn near_duplicates(
+    app_state: web::Data<AppState>,
+    params: web::Query<NearDuplicatesParams>,
+) -> HttpResponse {
+    info!("Near-duplicate documents search with {:?}", params);
+
+    let response = span!(Level::INFO, "near_duplicates_query").in_scope(|| {
+        app_state
+            .searcher
+            .near_duplicates(params.max_distance.unwrap_or(DEFAULT_MAX_SIMHASH_DISTANCE))
+    });
+
+    match response {
+        Ok(response) => {
+            let result = serde_json::to_string(&response).unwrap();
+            info!("Found results: {:?}", &result);
+            HttpResponse::Ok().body(result)
+        }
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// Body of an open or reveal request, eg: `{"path": "/home/me/cats.txt"}`
+#[derive(Deserialize, Debug)]
+pub struct OpenRequest {
+    /// A location of an indexed file, as returned by a search
+    path: PathBuf,
+}
+
+async fn open(app_state: web::Data<AppState>, request: web::Json<OpenRequest>) -> HttpResponse {
+    open_file(&app_state, &request.path, OpenAction::Open)
+}
+
+async fn reveal(app_state: web::Data<AppState>, request: web::Json<OpenRequest>) -> HttpResponse {
+    open_file(&app_state, &request.path, OpenAction::Reveal)
+}
 
-    let result = serde_json::to_string(&response).unwrap();
+fn open_file(app_state: &AppState, path: &Path, action: OpenAction) -> HttpResponse {
+    info!("{:?} file at {:?}", action, path);
 
-    info!("Found results: {:?}", &result);
+    let response =
+        span!(Level::INFO, "open_file").in_scope(|| app_state.searcher.open_file(path, action));
 
-    HttpResponse::Ok().body(result)
+    match response {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().body(format!("{:?} isn't in the index", path)),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
 }
@@ -1,17 +1,172 @@
-use common::tantivy::collector::TopDocs;
-use common::tantivy::query::QueryParser;
+use common::anyhow::{bail, Result};
+use common::tantivy::collector::{DocSetCollector, FilterCollector, TopDocs};
+use common::tantivy::fastfield::FastFieldReader;
+use common::tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, FuzzyTermQuery, MoreLikeThisQuery, Occur, Query,
+    QueryParser, RangeQuery, TermQuery,
+};
 use common::tantivy::schema::*;
-use common::tantivy::Index;
-use common::tantivy::IndexReader;
+use common::tantivy::tokenizer::TextAnalyzer;
+use common::tantivy::{DocAddress, DocId, Index, SegmentReader};
+use common::tantivy::{IndexReader, LeasedItem};
 use common::tracing::info;
 use serde::{Deserialize, Serialize};
 
-use crate::custom_tantivy::{path_facet_convert::TantivyConvert, utils::destructure_schema};
+use crate::annotations::{Annotation, Annotations};
+use crate::custom_tantivy::{
+    languages::{language_body_field, language_body_fields},
+    path_facet_convert::{containing_file, TantivyConvert},
+    spelling,
+    utils::{destructure_schema, normalize_tags, place_facet, PLACE_FIELDS},
+};
+use crate::open_history::OpenHistory;
+use crate::relevance::Relevance;
+#[cfg(feature = "semantic")]
+use embeddings::VectorStore;
 
+use std::cmp::Reverse;
+#[cfg(feature = "semantic")]
+use std::collections::BTreeSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
 use std::path::*;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Mean radius of the earth, used to measure distances between coordinates
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// How many results a search returns
+const RESULT_LIMIT: usize = 10;
+
+/// How many of the best keyword matches are ranked again with how often they were opened, how recent they are and where they are
+const RERANKING_CANDIDATES: usize = 50;
+
+/// Exact matches count this much more than misspelled or prefix matches, so they rank above them
+const EXACT_MATCH_BOOST: f32 = 2.0;
+
+/// How many edits a word of a fuzzy search can be away from an indexed word when the request doesn't say
+pub const DEFAULT_FUZZY_DISTANCE: u8 = 1;
+
+/// Longer edit distances match too many unrelated words
+const MAX_FUZZY_DISTANCE: u8 = 2;
+
+/// How many completions a suggestion returns when the request doesn't say
+pub const DEFAULT_SUGGESTION_LIMIT: usize = 10;
+
+/// How many file names a suggestion returns at most
+const FILENAME_SUGGESTIONS: usize = 5;
+
+/// A single character starts a large part of the dictionary, only this many of its words are counted per segment
+const SHORT_PREFIX_TERMS: usize = 1000;
+
+/// How much each doubling of the opens of a document adds to its score, relative to the score
+const OPEN_BOOST: f32 = 0.1;
+
+/// How many related documents are returned when the request doesn't say
+pub const DEFAULT_RELATED_LIMIT: usize = 10;
+
+/// How many related documents are returned at most, larger limits are lowered to it
+const MAX_RELATED_LIMIT: usize = 100;
+
+/// Words of a document have to be in at least one other document to say what it's related to
+const RELATED_MIN_DOC_FREQUENCY: u64 = 2;
+
+/// How many of the most distinctive words of a document are searched for to find related ones
+const RELATED_MAX_QUERY_TERMS: usize = 25;
+
+/// How many results a geo search returns at most
+const GEO_RESULT_LIMIT: usize = 100;
+
+/// How many documents are taken from both the keyword and the vector search before blending
+#[cfg(feature = "semantic")]
+const HYBRID_CANDIDATES: usize = 50;
+
+/// How many bits the perceptual hashes of two images can differ by for them to look the same
+pub const DEFAULT_MAX_HASH_DISTANCE: u32 = 10;
+
+/// How many bits the SimHashes of two documents can differ by for them to be near duplicates
+pub const DEFAULT_MAX_SIMHASH_DISTANCE: u32 = 8;
+
+/// Images without embeddings to compare are only similar when their perceptual hashes differ by at most this many bits
+const MAX_SIMILAR_HASH_DISTANCE: u32 = 16;
+
+/// How much vector similarity counts in a hybrid search when the request doesn't say
+pub const DEFAULT_SEMANTIC_WEIGHT: f32 = 0.5;
+
+/// How the documents of a search are found and ranked
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// BM25 over the title and body
+    #[default]
+    Keyword,
+    /// BM25 blended with the similarity of the document embeddings to the query embedding
+    Hybrid,
+    /// Also matches words a few typos away from the query words
+    Fuzzy,
+    /// The last query word also matches the words it starts, for searching as the user types
+    Prefix,
+}
+
+/// What the results of a search are ordered by
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// The score of the query, adjusted by the relevance settings
+    #[default]
+    Relevance,
+    /// When the file was last modified
+    #[serde(alias = "mtime")]
+    Modified,
+    Size,
+    Filename,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sort {
+    pub key: SortKey,
+    pub order: SortOrder,
+}
+
+impl Sort {
+    /// Sorts in the order which is usually wanted, eg: the best match, newest or biggest first, names from A to Z
+    pub fn by(key: SortKey) -> Self {
+        let order = match key {
+            SortKey::Filename => SortOrder::Asc,
+            _ => SortOrder::Desc,
+        };
+        Sort { key, order }
+    }
+}
+
+impl Default for Sort {
+    fn default() -> Self {
+        Sort::by(SortKey::Relevance)
+    }
+}
 
 pub type QueryResponse = Vec<Response>;
 
+/// What a text search returns to the ui
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SearchResponse {
+    pub results: QueryResponse,
+    /// The query with its misspelled words corrected, only when nothing was found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub did_you_mean: Option<String>,
+}
+
+/// A tantivy searcher over the index as of when it was leased
+type IndexSearcher = LeasedItem<common::tantivy::Searcher>;
+
 /// Each tantivy document is stored in this format to be communicated to the ui
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Response {
@@ -21,11 +176,144 @@ pub struct Response {
     pub location: Vec<PathBuf>,
     /// The content that was indexed from the file
     pub body: String,
+    /// The sections of the file where the query matched, eg: a sheet or a slide
+    pub hits: Vec<String>,
+    /// Latitude and longitude of where a photo was taken
+    pub coordinates: Option<[f64; 2]>,
+    /// When the file was last modified, in seconds since the unix epoch
+    pub modified: Option<i64>,
+    /// Size of the file in bytes
+    pub size: Option<u64>,
+    /// The language the file is written in, as its ISO 639-3 code, eg: "eng"
+    pub language: Option<String>,
+    /// If the file was too big to be indexed whole and only its start is searched
+    pub truncated: bool,
+    /// Tags added through podium and the ones stored in the extended attributes of the file
+    pub tags: Vec<String>,
+    /// A note added through podium
+    pub note: Option<String>,
+}
+
+/// An image which looks like the one a similarity search started from
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SimilarImage {
+    #[serde(flatten)]
+    pub response: Response,
+    /// Cosine similarity of the image embeddings, when both images have one
+    pub similarity: Option<f32>,
+    /// How many bits the perceptual hashes of the images differ by, out of 64
+    pub hash_distance: u32,
+}
+
+/// What is done with an indexed file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenAction {
+    /// Opened with the default application for its type
+    Open,
+    /// The folder which contains it is opened
+    Reveal,
+}
+
+/// Ways to finish what the user is typing
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Suggestions {
+    /// The query with its last word completed by indexed words, most common first
+    pub completions: Vec<String>,
+    /// Names of indexed files which match the query
+    pub filenames: Vec<String>,
+}
+
+/// The locations of a file which was found more than once
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DuplicateGroup {
+    pub title: String,
+    pub locations: Vec<PathBuf>,
+    /// Size in bytes of a single copy
+    pub size: u64,
+    /// Bytes which would be freed by keeping a single copy
+    pub wasted_bytes: u64,
+}
+
+/// The area a geo search is restricted to
+#[derive(Debug, Clone, Copy)]
+pub enum GeoArea {
+    /// Everything within `radius_km` kilometres of a point
+    Radius { lat: f64, lon: f64, radius_km: f64 },
+    /// Everything between two corners, the bounds are inclusive
+    BoundingBox {
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    },
+}
+
+impl GeoArea {
+    /// The smallest box which contains the area, as (min_lat, min_lon, max_lat, max_lon)
+    /// Areas which cross the antimeridian are cut off at it
+    fn bounding_box(&self) -> (f64, f64, f64, f64) {
+        match *self {
+            GeoArea::Radius {
+                lat,
+                lon,
+                radius_km,
+            } => {
+                let lat_delta = (radius_km / EARTH_RADIUS_KM).to_degrees();
+                let lon_delta = lat_delta / lat.to_radians().cos().max(f64::EPSILON);
+                (
+                    (lat - lat_delta).max(-90.0),
+                    (lon - lon_delta).max(-180.0),
+                    (lat + lat_delta).min(90.0),
+                    (lon + lon_delta).min(180.0),
+                )
+            }
+            GeoArea::BoundingBox {
+                min_lat,
+                min_lon,
+                max_lat,
+                max_lon,
+            } => (min_lat, min_lon, max_lat, max_lon),
+        }
+    }
+}
+
+/// How a request points to an indexed file
+#[derive(Debug, Clone)]
+pub enum IndexedFile {
+    /// Any of the locations of the file, as returned by a search
+    Location(PathBuf),
+    /// The hash of the contents of the file
+    Hash(String),
 }
+
+/// Changes to the annotation of a file, the parts which aren't set are kept
+#[derive(Debug, Default, Deserialize)]
+pub struct AnnotationUpdate {
+    /// Replaces the tags, an empty list removes them
+    pub tags: Option<Vec<String>>,
+    /// Replaces the note, an empty note removes it
+    pub note: Option<String>,
+}
+
+/// A search over the locations of photos, every part which is set has to match
+#[derive(Debug, Default)]
+pub struct GeoQuery {
+    pub area: Option<GeoArea>,
+    /// A place name as returned by the reverse geocoder, eg: a city or a region
+    pub place: Option<String>,
+    /// A regular search query to narrow the results down, eg: "cats"
+    pub query: Option<String>,
+}
+
 pub struct Searcher {
     index: Index,
     index_reader: IndexReader,
     schema: Schema,
+    #[cfg(feature = "semantic")]
+    vector_store: Option<Arc<VectorStore>>,
+    open_history: Option<Arc<OpenHistory>>,
+    annotations: Option<Arc<Annotations>>,
+    relevance: Relevance,
 }
 
 impl Searcher {
@@ -34,55 +322,1840 @@ impl Searcher {
             index,
             index_reader,
             schema,
+            #[cfg(feature = "semantic")]
+            vector_store: None,
+            open_history: None,
+            annotations: None,
+            relevance: Relevance::default(),
         }
     }
 
-    pub fn search(&self, query_string: String) -> QueryResponse {
-        let searcher = self.index_reader.searcher();
+    pub fn with_relevance(mut self, relevance: Relevance) -> Self {
+        self.relevance = relevance;
+        self
+    }
 
-        let (title, _, location, body) = destructure_schema(&self.schema);
+    /// The configured relevance settings, which requests can override
+    pub fn relevance(&self) -> &Relevance {
+        &self.relevance
+    }
+
+    pub fn with_open_history(mut self, open_history: Arc<OpenHistory>) -> Self {
+        self.open_history = Some(open_history);
+        self
+    }
+
+    pub fn with_annotations(mut self, annotations: Arc<Annotations>) -> Self {
+        self.annotations = Some(annotations);
+        self
+    }
+
+    #[cfg(feature = "semantic")]
+    pub fn with_vector_store(mut self, vector_store: Arc<VectorStore>) -> Self {
+        self.vector_store = Some(vector_store);
+        self
+    }
 
-        let query_parser = QueryParser::for_index(&self.index, vec![title, body]);
+    pub fn search(
+        &self,
+        query_string: String,
+        relevance: &Relevance,
+        sort: Sort,
+    ) -> Result<QueryResponse> {
+        let searcher = self.index_reader.searcher();
+
+        let query_parser = self.query_parser(relevance);
         info!("Searching for a file with {:?}...", query_string);
-        let query = query_parser.parse_query(&query_string).unwrap();
+        let (words, tags) = split_tag_filters(&query_string);
+        let query = parse_words(&query_parser, &words, &tags)?;
+        let query = self.filter_by_tags(&searcher, query, &tags)?;
         info!("Parsed query");
 
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(10)).unwrap();
+        let query_words = query_words(&*query, &self.highlighted_fields());
+
+        self.ranked_search(&searcher, &*query, &query_words, relevance, sort)
+    }
+
+    /// Parses queries over the title, headings and body, each boosted as the relevance settings say
+    /// The body is searched in every language field, analyzed the way documents in that language were
+    /// Each document has its body in a single one of them, so a match in the body counts once whatever its language
+    fn query_parser(&self, relevance: &Relevance) -> QueryParser {
+        let (title, _, _, _) = destructure_schema(&self.schema);
+        let headings = self.schema.get_field("headings").unwrap();
+        let language_bodies = language_body_fields(&self.schema);
+
+        let mut fields = vec![title, headings];
+        fields.extend(&language_bodies);
+        let mut query_parser = QueryParser::for_index(&self.index, fields);
+        query_parser.set_field_boost(title, relevance.title_boost);
+        query_parser.set_field_boost(headings, relevance.headings_boost);
+        for language_body in language_bodies {
+            query_parser.set_field_boost(language_body, relevance.body_boost);
+        }
+        query_parser
+    }
+
+    /// Keyword search which also matches words up to `distance` edits away from the query words
+    pub fn fuzzy_search(
+        &self,
+        query_string: &str,
+        distance: u8,
+        relevance: &Relevance,
+        sort: Sort,
+    ) -> Result<QueryResponse> {
+        if !(1..=MAX_FUZZY_DISTANCE).contains(&distance) {
+            bail!(
+                "The edit distance has to be between 1 and {}",
+                MAX_FUZZY_DISTANCE
+            );
+        }
+        info!("Fuzzy search for a file with {:?}...", query_string);
+        self.expanded_search(query_string, relevance, sort, |term, _| {
+            Box::new(FuzzyTermQuery::new(term, distance, true))
+        })
+    }
+
+    /// Keyword search where the last query word also matches the words it's the start of
+    /// The other words are complete, so they only match exactly
+    pub fn prefix_search(
+        &self,
+        query_string: &str,
+        relevance: &Relevance,
+        sort: Sort,
+    ) -> Result<QueryResponse> {
+        info!("Prefix search for a file with {:?}...", query_string);
+        self.expanded_search(query_string, relevance, sort, |term, is_last_word| {
+            if is_last_word {
+                Box::new(FuzzyTermQuery::new_prefix(term, 0, false))
+            } else {
+                Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs))
+            }
+        })
+    }
+
+    /// Matches the query as a keyword search or any of its words expanded in the title, the headings or the body
+    /// The keyword search is boosted, so documents which match it exactly rank first
+    fn expanded_search<F>(
+        &self,
+        query_string: &str,
+        relevance: &Relevance,
+        sort: Sort,
+        expand: F,
+    ) -> Result<QueryResponse>
+    where
+        F: Fn(Term, bool) -> Box<dyn Query>,
+    {
+        let searcher = self.index_reader.searcher();
+        let (title, _, _, body) = destructure_schema(&self.schema);
+        let headings = self.schema.get_field("headings").unwrap();
+
+        let query_parser = self.query_parser(relevance);
+        let (query_string, tags) = split_tag_filters(query_string);
+        let exact_query = parse_words(&query_parser, &query_string, &tags)?;
+        let query_words = query_words(&*exact_query, &self.highlighted_fields());
+
+        // Words are expanded the way they were indexed, eg: lowercased
+        let words = self.query_tokens(&query_string)?;
+
+        let mut subqueries: Vec<(Occur, Box<dyn Query>)> = vec![(
+            Occur::Should,
+            Box::new(BoostQuery::new(exact_query, EXACT_MATCH_BOOST)),
+        )];
+        for (position, word) in words.iter().enumerate() {
+            let is_last_word = position + 1 == words.len();
+            for (field, boost) in [
+                (title, relevance.title_boost),
+                (headings, relevance.headings_boost),
+                (body, relevance.body_boost),
+            ] {
+                subqueries.push((
+                    Occur::Should,
+                    Box::new(BoostQuery::new(
+                        expand(Term::from_field_text(field, word), is_last_word),
+                        boost,
+                    )),
+                ));
+            }
+        }
+
+        let query =
+            self.filter_by_tags(&searcher, Box::new(BooleanQuery::new(subqueries)), &tags)?;
+        self.ranked_search(&searcher, &*query, &query_words, relevance, sort)
+    }
+
+    /// Completes the last word of the query with words from the titles and bodies of indexed files
+    /// Words found in more documents come first, the term dictionaries are only walked from the prefix on
+    /// The files whose names have the words are suggested too
+    pub fn suggest(&self, query_string: &str, limit: usize) -> Result<Suggestions> {
+        let searcher = self.index_reader.searcher();
+        let (title, _, location, body) = destructure_schema(&self.schema);
+        let filename_field = self.schema.get_field("filename").unwrap();
+
+        let words = self.query_tokens(query_string)?;
+        let (last_word, first_words) = match words.split_last() {
+            Some(split) => split,
+            None => return Ok(Suggestions::default()),
+        };
+
+        let max_terms = if last_word.chars().count() == 1 {
+            SHORT_PREFIX_TERMS
+        } else {
+            usize::MAX
+        };
+        let mut doc_freqs: HashMap<String, u32> = HashMap::new();
+        for segment_reader in searcher.segment_readers() {
+            for field in [title, body] {
+                let inverted_index = segment_reader.inverted_index(field)?;
+                let mut terms = inverted_index
+                    .terms()
+                    .range()
+                    .ge(last_word.as_bytes())
+                    .into_stream()?;
+                let mut walked_terms = 0;
+                while terms.advance() {
+                    if !terms.key().starts_with(last_word.as_bytes()) || walked_terms == max_terms {
+                        break;
+                    }
+                    walked_terms += 1;
+                    if let Ok(word) = std::str::from_utf8(terms.key()) {
+                        *doc_freqs.entry(word.to_string()).or_insert(0) += terms.value().doc_freq;
+                    }
+                }
+            }
+        }
+        let mut completions = doc_freqs.into_iter().collect::<Vec<(String, u32)>>();
+        completions.sort_by(|(a, a_freq), (b, b_freq)| b_freq.cmp(a_freq).then(a.cmp(b)));
+        let completions = completions
+            .into_iter()
+            .take(limit)
+            .map(|(word, _)| {
+                first_words
+                    .iter()
+                    .chain(std::iter::once(&word))
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            })
+            .collect();
+
+        // Files are matched by the names of their locations, a title set by the file itself doesn't count
+        let mut subqueries: Vec<(Occur, Box<dyn Query>)> = first_words
+            .iter()
+            .map(|word| {
+                let query: Box<dyn Query> = Box::new(TermQuery::new(
+                    Term::from_field_text(filename_field, word),
+                    IndexRecordOption::Basic,
+                ));
+                (Occur::Must, query)
+            })
+            .collect();
+        subqueries.push((
+            Occur::Must,
+            Box::new(FuzzyTermQuery::new_prefix(
+                Term::from_field_text(filename_field, last_word),
+                0,
+                false,
+            )),
+        ));
+        let top_docs = searcher.search(
+            &BooleanQuery::new(subqueries),
+            &TopDocs::with_limit(FILENAME_SUGGESTIONS),
+        )?;
+        let mut filenames = Vec::new();
+        for (_, doc_address) in top_docs {
+            let doc = searcher.doc(doc_address)?;
+            for value in doc.get_all(location) {
+                if let Value::Facet(facet) = value {
+                    let filename = Path::from_facet_value(facet)
+                        .file_name()
+                        .map(|filename| filename.to_string_lossy().to_string());
+                    // Copies of a file can have other names, which don't match
+                    let filename = filename.filter(|name| {
+                        name.to_lowercase().contains(last_word.as_str())
+                            && !filenames.contains(name)
+                    });
+                    if let Some(filename) = filename {
+                        filenames.push(filename);
+                    }
+                }
+            }
+        }
+
+        Ok(Suggestions {
+            completions,
+            filenames,
+        })
+    }
+
+    /// The fields whose query words are highlighted in results
+    /// Queries search the body of documents without a language in `body_other`, which is tokenized like `body`
+    fn highlighted_fields(&self) -> [Field; 3] {
+        let (title, _, _, body) = destructure_schema(&self.schema);
+        [
+            title,
+            body,
+            language_body_field(&self.schema, None).unwrap(),
+        ]
+    }
+
+    /// The words of a query the way the body was tokenized when it was indexed, in order
+    fn query_tokens(&self, query_string: &str) -> Result<Vec<String>> {
+        let (_, _, _, body) = destructure_schema(&self.schema);
+        let tokenizer = self.index.tokenizer_for_field(body)?;
+        let mut words = Vec::new();
+        let mut token_stream = tokenizer.token_stream(query_string);
+        while token_stream.advance() {
+            words.push(token_stream.token().text.clone());
+        }
+        Ok(words)
+    }
+
+    /// Wraps the results of a search, when there are none the query is corrected with words from the index
+    pub fn search_response(
+        &self,
+        query_string: &str,
+        results: QueryResponse,
+    ) -> Result<SearchResponse> {
+        let did_you_mean = if results.is_empty() {
+            let searcher = self.index_reader.searcher();
+            let (title, _, _, body) = destructure_schema(&self.schema);
+            // Only the words are corrected, the tag filters are kept as they are
+            let (words, tags) = split_tag_filters(query_string);
+            let words = self.query_tokens(&words)?;
+            spelling::did_you_mean(&searcher, &[title, body], &words)?.map(|correction| {
+                tags.iter().fold(correction, |correction, tag| {
+                    format!("{} {}", correction, tag_filter(tag))
+                })
+            })
+        } else {
+            None
+        };
+        if let Some(correction) = &did_you_mean {
+            info!(
+                "No results for {:?}, did you mean {:?}",
+                query_string, correction
+            );
+        }
+
+        Ok(SearchResponse {
+            results,
+            did_you_mean,
+        })
+    }
+
+    /// The best matches of a query, after documents which were opened more often, modified recently or are in boosted locations are moved up
+    fn ranked_search(
+        &self,
+        searcher: &IndexSearcher,
+        query: &dyn Query,
+        query_words: &HashSet<String>,
+        relevance: &Relevance,
+        sort: Sort,
+    ) -> Result<QueryResponse> {
+        let (_, _, _, body) = destructure_schema(&self.schema);
+        let tokenizer = self.index.tokenizer_for_field(body)?;
+
+        if sort.key != SortKey::Relevance {
+            return Ok(self
+                .sorted_search(searcher, query, sort)?
+                .iter()
+                .map(|retrieved_doc| self.to_response(retrieved_doc, &tokenizer, query_words))
+                .collect());
+        }
+
+        let top_docs = searcher.search(query, &TopDocs::with_limit(RERANKING_CANDIDATES))?;
         info!("Executed search");
 
-        top_docs
+        let now = unix_time();
+        let mut ranked = top_docs
             .into_iter()
-            .map(|(_score, doc_address)| searcher.doc(doc_address).unwrap())
-            .map(|retrieved_doc| {
-                let title = retrieved_doc.get_all(title).map(|val| val.text()).fold(
-                    String::new(),
-                    |mut acc, x| {
-                        acc.push_str(x.unwrap());
-                        acc.push(' ');
-                        acc
-                    },
+            .map(|(score, doc_address)| {
+                let retrieved_doc = searcher.doc(doc_address)?;
+                let factor = self.rank_factor(&retrieved_doc, relevance, now);
+                Ok((score * factor, retrieved_doc))
+            })
+            .collect::<Result<Vec<(f32, Document)>>>()?;
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        if sort.order == SortOrder::Asc {
+            ranked.reverse();
+        }
+
+        Ok(ranked
+            .into_iter()
+            .take(RESULT_LIMIT)
+            .map(|(_, retrieved_doc)| self.to_response(&retrieved_doc, &tokenizer, query_words))
+            .collect())
+    }
+
+    /// The documents which match a query ordered by a fast field instead of their score
+    fn sorted_search(
+        &self,
+        searcher: &IndexSearcher,
+        query: &dyn Query,
+        sort: Sort,
+    ) -> Result<Vec<Document>> {
+        let field_name = match sort.key {
+            SortKey::Modified => "modified",
+            SortKey::Size => "size",
+            SortKey::Filename => "name_key",
+            SortKey::Relevance => bail!("Results sorted by relevance are ranked, not sorted"),
+        };
+        let field = self.schema.get_field(field_name).unwrap();
+        let by_name = sort.key == SortKey::Filename;
+
+        // Fast fields are read as u64s which keep the order of the values, eg: of negative i64s
+        // Documents without a name have a key of 0, they come last in either order
+        let ascending = sort.order == SortOrder::Asc;
+        let sort_value = move |value: u64| {
+            if !ascending || (by_name && value == 0) {
+                value
+            } else {
+                u64::MAX - value
+            }
+        };
+        let collector = TopDocs::with_limit(RESULT_LIMIT).custom_score(
+            move |segment_reader: &SegmentReader| {
+                let fast_field = segment_reader.fast_fields().u64_lenient(field).unwrap();
+                move |doc: DocId| sort_value(fast_field.get(doc))
+            },
+        );
+        let top_docs = searcher.search(query, &collector)?;
+        info!("Executed search sorted by {:?}", sort);
+
+        let doc_addresses = match top_docs.last() {
+            // Names which start the same have the same key, so every document tied with the last one is compared whole too
+            Some(&(cutoff, _)) if by_name && top_docs.len() == RESULT_LIMIT => {
+                let tied = FilterCollector::new(
+                    field,
+                    move |value: u64| sort_value(value) >= cutoff,
+                    DocSetCollector,
                 );
-                let location = retrieved_doc
-                    .get_all(location)
-                    .filter_map(|val| match &val {
-                        Value::Facet(loc_str) => Some(Path::from_facet_value(loc_str)),
+                searcher.search(query, &tied)?.into_iter().collect()
+            }
+            _ => top_docs
+                .into_iter()
+                .map(|(_, doc_address)| doc_address)
+                .collect::<Vec<DocAddress>>(),
+        };
+        let docs = doc_addresses
+            .into_iter()
+            .map(|doc_address| Ok(searcher.doc(doc_address)?))
+            .collect::<Result<Vec<Document>>>()?;
+        if !by_name {
+            return Ok(docs);
+        }
+
+        let (_, _, location, _) = destructure_schema(&self.schema);
+        let mut named_docs = docs
+            .into_iter()
+            .map(|doc| {
+                let name = doc
+                    .get_first(location)
+                    .and_then(|value| match value {
+                        Value::Facet(facet) => Path::from_facet_value(facet)
+                            .file_name()
+                            .map(|name| name.to_string_lossy().to_lowercase()),
                         _ => None,
                     })
-                    .collect();
-                let body = retrieved_doc.get_all(body).map(|val| val.text()).fold(
-                    String::new(),
-                    |mut acc, x| {
-                        acc.push_str(x.unwrap());
-                        acc.push(' ');
-                        acc
-                    },
+                    .unwrap_or_default();
+                (name, doc)
+            })
+            .collect::<Vec<(String, Document)>>();
+        // The keys are the first bytes of the names, so the names sort in the same order as their keys
+        named_docs.sort_by(|(a, _), (b, _)| {
+            if ascending {
+                a.is_empty().cmp(&b.is_empty()).then_with(|| a.cmp(b))
+            } else {
+                b.cmp(a)
+            }
+        });
+
+        Ok(named_docs
+            .into_iter()
+            .take(RESULT_LIMIT)
+            .map(|(_, doc)| doc)
+            .collect())
+    }
+
+    /// Opens an indexed file, or the folder it's in, with the default application
+    /// Only locations of indexed files are accepted, so a request can't run an arbitrary path
+    /// Returns false if no indexed file has this location
+    pub fn open_file(&self, location: &Path, action: OpenAction) -> Result<bool> {
+        let searcher = self.index_reader.searcher();
+        let (_, hash, _, _) = destructure_schema(&self.schema);
+
+        let doc = match self.doc_by_location(&searcher, location)? {
+            Some((_, doc)) => doc,
+            None => return Ok(false),
+        };
+
+        opener::open(opened_path(location, action)?)?;
+        info!("Opened {:?} with {:?}", location, action);
+
+        if let (Some(open_history), Some(doc_hash)) = (
+            &self.open_history,
+            doc.get_first(hash).and_then(Value::text),
+        ) {
+            open_history.record(doc_hash)?;
+        }
+        Ok(true)
+    }
+
+    /// What the score of a document is multiplied by for its opens, its modification time and its locations
+    fn rank_factor(&self, doc: &Document, relevance: &Relevance, now: i64) -> f32 {
+        let modified = self.schema.get_field("modified").unwrap();
+
+        self.open_boost(doc)
+            * relevance.recency_factor(doc.get_first(modified).and_then(Value::i64_value), now)
+            * relevance.path_factor(&self.locations(doc))
+    }
+
+    fn locations(&self, doc: &Document) -> Vec<PathBuf> {
+        let (_, _, location, _) = destructure_schema(&self.schema);
+        doc.get_all(location)
+            .filter_map(|value| match value {
+                Value::Facet(facet) => Some(Path::from_facet_value(facet)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Documents which were opened more often are ranked higher, by the log of their opens
+    fn open_boost(&self, doc: &Document) -> f32 {
+        let (_, hash, _, _) = destructure_schema(&self.schema);
+        let opens = match (
+            &self.open_history,
+            doc.get_first(hash).and_then(Value::text),
+        ) {
+            (Some(open_history), Some(doc_hash)) => open_history.count(doc_hash),
+            _ => 0,
+        };
+        1.0 + OPEN_BOOST * (1.0 + opens as f32).log2()
+    }
+
+    /// Ranks documents by `(1 - semantic_weight) * bm25 + semantic_weight * similarity`
+    /// BM25 scores are divided by the best one so both parts are between 0 and 1
+    /// Documents which only match by meaning, and none of the query words, are included
+    /// The blended scores are then adjusted for opens, recency and locations like keyword scores
+    #[cfg(feature = "semantic")]
+    pub fn hybrid_search(
+        &self,
+        query_string: &str,
+        semantic_weight: f32,
+        relevance: &Relevance,
+    ) -> Result<QueryResponse> {
+        if !(0.0..=1.0).contains(&semantic_weight) {
+            bail!("The semantic weight has to be between 0 and 1");
+        }
+        let (vector_store, embedder) = match (&self.vector_store, embeddings::embedder()) {
+            (Some(vector_store), Some(embedder)) => (vector_store, embedder),
+            _ => bail!("Hybrid search needs the text embedding model in the models directory"),
+        };
+        let searcher = self.index_reader.searcher();
+
+        let (_, hash, _, body) = destructure_schema(&self.schema);
+        let query_parser = self.query_parser(relevance);
+        info!("Hybrid search for a file with {:?}...", query_string);
+        let (query_string, tags) = split_tag_filters(query_string);
+        let query = parse_words(&query_parser, &query_string, &tags)?;
+        let query = self.filter_by_tags(&searcher, query, &tags)?;
+        let query_words = query_words(&*query, &self.highlighted_fields());
+        let query_vector = embedder.embed(&query_string)?;
+
+        let keyword_hits = searcher.search(&query, &TopDocs::with_limit(HYBRID_CANDIDATES))?;
+        let best_score = keyword_hits
+            .first()
+            .map(|(score, _)| *score)
+            .filter(|score| *score > 0.0)
+            .unwrap_or(1.0);
+        let mut keyword_scores = BTreeMap::new();
+        for (score, doc_address) in keyword_hits {
+            keyword_scores.insert(doc_address, score / best_score);
+        }
+
+        // Vectors whose document was deleted don't match any hash and are skipped
+        // Documents without the tags of the query are skipped too
+        let vector_hits = vector_store
+            .nearest(&query_vector, HYBRID_CANDIDATES)
+            .into_iter()
+            .filter_map(|(doc_hash, _)| {
+                let hash_query = Box::new(TermQuery::new(
+                    Term::from_field_text(hash, &doc_hash),
+                    IndexRecordOption::Basic,
+                ));
+                let hash_query = self.filter_by_tags(&searcher, hash_query, &tags).ok()?;
+                let top_docs = searcher
+                    .search(&*hash_query, &TopDocs::with_limit(1))
+                    .ok()?;
+                top_docs.first().map(|(_, doc_address)| *doc_address)
+            });
+        let candidates: BTreeSet<DocAddress> =
+            keyword_scores.keys().cloned().chain(vector_hits).collect();
+
+        let now = unix_time();
+        let mut ranked = candidates
+            .into_iter()
+            .map(|doc_address| {
+                let doc = searcher.doc(doc_address)?;
+                let keyword_score = keyword_scores.get(&doc_address).cloned().unwrap_or(0.0);
+                let similarity = doc
+                    .get_first(hash)
+                    .and_then(Value::text)
+                    .and_then(|doc_hash| vector_store.similarity(doc_hash, &query_vector))
+                    .unwrap_or(0.0)
+                    .max(0.0);
+                let score = (1.0 - semantic_weight) * keyword_score + semantic_weight * similarity;
+                Ok((score * self.rank_factor(&doc, relevance, now), doc))
+            })
+            .collect::<Result<Vec<(f32, Document)>>>()?;
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        info!("Executed hybrid search");
+
+        let tokenizer = self.index.tokenizer_for_field(body).unwrap();
+        Ok(ranked
+            .into_iter()
+            .take(RESULT_LIMIT)
+            .map(|(_, doc)| self.to_response(&doc, &tokenizer, &query_words))
+            .collect())
+    }
+
+    #[cfg(not(feature = "semantic"))]
+    pub fn hybrid_search(
+        &self,
+        _query_string: &str,
+        _semantic_weight: f32,
+        _relevance: &Relevance,
+    ) -> Result<QueryResponse> {
+        bail!("Hybrid search needs podium to be built with the semantic feature")
+    }
+
+    /// Finds photos by where they were taken
+    /// Results of a radius search are sorted by their distance to its center
+    pub fn geo_search(&self, geo_query: &GeoQuery) -> Result<QueryResponse> {
+        let searcher = self.index_reader.searcher();
+
+        let (title, _, _, body) = destructure_schema(&self.schema);
+        let latitude = self.schema.get_field("latitude").unwrap();
+        let longitude = self.schema.get_field("longitude").unwrap();
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        let mut query_words = HashSet::new();
+
+        if let Some(query_string) = &geo_query.query {
+            let query_parser = QueryParser::for_index(&self.index, vec![title, body]);
+            let query = query_parser.parse_query(query_string)?;
+            query_words = self::query_words(&*query, &self.highlighted_fields());
+            clauses.push((Occur::Must, query));
+        }
+
+        // The place can be at any level, eg: a city or a country
+        if let Some(place) = &geo_query.place {
+            let facet = place_facet(place);
+            let place_clauses = PLACE_FIELDS
+                .iter()
+                .map(|place_field| {
+                    let field = self.schema.get_field(place_field).unwrap();
+                    let query: Box<dyn Query> = Box::new(TermQuery::new(
+                        Term::from_facet(field, &facet),
+                        IndexRecordOption::Basic,
+                    ));
+                    (Occur::Should, query)
+                })
+                .collect();
+            clauses.push((Occur::Must, Box::new(BooleanQuery::new(place_clauses))));
+        }
+
+        if let Some(area) = &geo_query.area {
+            let (min_lat, min_lon, max_lat, max_lon) = area.bounding_box();
+            if min_lat > max_lat || min_lon > max_lon {
+                bail!(
+                    "The bounding box has its minimum above its maximum: {:?}",
+                    area
                 );
-                Response {
-                    title,
-                    location,
-                    body,
+            }
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_f64_bounds(
+                    latitude,
+                    Bound::Included(min_lat),
+                    Bound::Included(max_lat),
+                )),
+            ));
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_f64_bounds(
+                    longitude,
+                    Bound::Included(min_lon),
+                    Bound::Included(max_lon),
+                )),
+            ));
+        }
+
+        if clauses.is_empty() {
+            bail!("A geo search needs an area, a place or a query");
+        }
+        let query = BooleanQuery::new(clauses);
+        info!("Searching for photos with {:?}...", geo_query);
+
+        let doc_addresses: Vec<DocAddress> = match geo_query.area {
+            // The range queries match a box around the circle, the corners are filtered out here
+            Some(GeoArea::Radius {
+                lat,
+                lon,
+                radius_km,
+            }) => {
+                let mut in_radius = searcher
+                    .search(&query, &DocSetCollector)?
+                    .into_iter()
+                    .filter_map(|doc_address| {
+                        let doc = searcher.doc(doc_address).ok()?;
+                        let [doc_lat, doc_lon] = self.coordinates(&doc)?;
+                        let distance = haversine_distance_km((lat, lon), (doc_lat, doc_lon));
+                        if distance <= radius_km {
+                            Some((distance, doc_address))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<(f64, DocAddress)>>();
+                in_radius.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+                in_radius
+                    .into_iter()
+                    .take(GEO_RESULT_LIMIT)
+                    .map(|(_, doc_address)| doc_address)
+                    .collect()
+            }
+            _ => searcher
+                .search(&query, &TopDocs::with_limit(GEO_RESULT_LIMIT))?
+                .into_iter()
+                .map(|(_score, doc_address)| doc_address)
+                .collect(),
+        };
+        info!("Executed geo search");
+
+        let tokenizer = self.index.tokenizer_for_field(body).unwrap();
+        doc_addresses
+            .into_iter()
+            .map(|doc_address| {
+                let retrieved_doc = searcher.doc(doc_address)?;
+                Ok(self.to_response(&retrieved_doc, &tokenizer, &query_words))
+            })
+            .collect()
+    }
+
+    /// The images which look the most like the image at this location, most similar first
+    /// Images are compared by their embeddings when both have one, otherwise by their perceptual hashes
+    /// Images compared by their hashes are left out when more than `MAX_SIMILAR_HASH_DISTANCE` bits differ
+    pub fn similar_images(&self, location: &Path, limit: usize) -> Result<Vec<SimilarImage>> {
+        let searcher = self.index_reader.searcher();
+        let (doc_address, doc) = match self.doc_by_location(&searcher, location)? {
+            Some(found) => found,
+            None => bail!("{:?} isn't indexed", location),
+        };
+        let (perceptual_hash, embedding) = match self.image_features(&doc) {
+            Some(features) => features,
+            None => bail!("{:?} isn't an indexed image", location),
+        };
+        info!("Searching for images similar to {:?}...", location);
+
+        // Images are ranked from their fast fields, only the documents which are returned are loaded
+        let perceptual_hash_field = self.schema.get_field("perceptual_hash").unwrap();
+        let image_embedding_field = self.schema.get_field("image_embedding").unwrap();
+        let segment_features = searcher
+            .segment_readers()
+            .iter()
+            .map(|segment_reader| {
+                let fast_fields = segment_reader.fast_fields();
+                Ok((
+                    fast_fields.i64(perceptual_hash_field)?,
+                    fast_fields.bytes(image_embedding_field)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let query =
+            RangeQuery::new_i64_bounds(perceptual_hash_field, Bound::Unbounded, Bound::Unbounded);
+        let mut similar = searcher
+            .search(&query, &DocSetCollector)?
+            .into_iter()
+            .filter(|other_address| *other_address != doc_address)
+            .filter_map(|other_address| {
+                let (hashes, embeddings) = &segment_features[other_address.segment_ord as usize];
+                let other_embedding = embeddings.get_bytes(other_address.doc_id);
+                let similarity = match &embedding {
+                    Some(embedding) if !other_embedding.is_empty() => Some(dot_product(
+                        embedding,
+                        &embedding_from_bytes(other_embedding),
+                    )),
+                    _ => None,
+                };
+                let hash_distance =
+                    (perceptual_hash ^ hashes.get(other_address.doc_id) as u64).count_ones();
+                if similarity.is_none() && hash_distance > MAX_SIMILAR_HASH_DISTANCE {
+                    return None;
                 }
+                Some((other_address, similarity, hash_distance))
             })
+            .collect::<Vec<(DocAddress, Option<f32>, u32)>>();
+        // Hash distances are turned into a similarity so both kinds of images can be ranked together
+        let score = |similarity: Option<f32>, hash_distance: u32| {
+            similarity.unwrap_or(1.0 - hash_distance as f32 / 64.0)
+        };
+        similar.sort_by(
+            |(_, a_similarity, a_distance), (_, b_similarity, b_distance)| {
+                score(*b_similarity, *b_distance).total_cmp(&score(*a_similarity, *a_distance))
+            },
+        );
+        similar.truncate(limit);
+        info!("Executed similar images search");
+
+        let (_, _, _, body) = destructure_schema(&self.schema);
+        let tokenizer = self.index.tokenizer_for_field(body).unwrap();
+        similar
+            .into_iter()
+            .map(|(other_address, similarity, hash_distance)| {
+                Ok(SimilarImage {
+                    response: self.to_response(
+                        &searcher.doc(other_address)?,
+                        &tokenizer,
+                        &HashSet::new(),
+                    ),
+                    similarity,
+                    hash_distance,
+                })
+            })
+            .collect()
+    }
+
+    /// Groups of images which look the same but aren't identical files, eg: resized or re-encoded copies
+    /// Images are in a group when their perceptual hash is within `max_distance` bits of another image in it
+    /// Largest groups first
+    pub fn image_clusters(&self, max_distance: u32) -> Result<Vec<QueryResponse>> {
+        info!("Clustering similar images...");
+        self.hash_clusters("perceptual_hash", max_distance)
+    }
+
+    /// Files which were found in more than one location, the most wasted space first
+    /// Identical files share a document, so each document with many locations is a group
+    pub fn exact_duplicates(&self) -> Result<Vec<DuplicateGroup>> {
+        let searcher = self.index_reader.searcher();
+        let (title, _, location, _) = destructure_schema(&self.schema);
+        let size_field = self.schema.get_field("size").unwrap();
+        info!("Searching for duplicate files...");
+
+        // Locations are counted from the ordinals of their facets, only the documents with more than one are loaded
+        let mut groups = Vec::new();
+        let mut location_ords = Vec::new();
+        for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+            let locations = segment_reader.facet_reader(location)?;
+            let sizes = segment_reader.fast_fields().i64(size_field)?;
+            for doc_id in segment_reader.doc_ids_alive() {
+                locations.facet_ords(doc_id, &mut location_ords);
+                if location_ords.len() < 2 {
+                    continue;
+                }
+
+                let doc = searcher.doc(DocAddress::new(segment_ord as u32, doc_id))?;
+                // Entries nested in archives have the size they have inside of the archive
+                let size = sizes.get(doc_id).max(0) as u64;
+                groups.push(DuplicateGroup {
+                    title: doc
+                        .get_first(title)
+                        .and_then(Value::text)
+                        .unwrap_or_default()
+                        .to_string(),
+                    wasted_bytes: size * (location_ords.len() as u64 - 1),
+                    size,
+                    locations: self.locations(&doc),
+                });
+            }
+        }
+        groups.sort_by_key(|group| Reverse(group.wasted_bytes));
+        info!("Found {} groups of duplicate files", groups.len());
+        Ok(groups)
+    }
+
+    /// Groups of documents whose text differs trivially, eg: a draft and its final version
+    /// Documents are in a group when their SimHash is within `max_distance` bits of another document in it
+    /// Largest groups first
+    pub fn near_duplicates(&self, max_distance: u32) -> Result<Vec<QueryResponse>> {
+        info!("Clustering nearly duplicate documents...");
+        self.hash_clusters("simhash", max_distance)
+    }
+
+    /// Groups the documents which have a 64 bit hash in this field by how close their hashes are
+    fn hash_clusters(&self, hash_field: &str, max_distance: u32) -> Result<Vec<QueryResponse>> {
+        let searcher = self.index_reader.searcher();
+        let field = self.schema.get_field(hash_field).unwrap();
+        let docs = self
+            .docs_with_field(&searcher, field)?
+            .into_iter()
+            .filter_map(|(_, doc)| Some((doc.get_first(field)?.i64_value()? as u64, doc)))
+            .collect::<Vec<(u64, Document)>>();
+
+        let hashes = docs.iter().map(|(hash, _)| *hash).collect::<Vec<u64>>();
+        let clusters = cluster_by_hash(&hashes, max_distance);
+        info!(
+            "Found {} clusters of {} documents",
+            clusters.len(),
+            docs.len()
+        );
+
+        let (_, _, _, body) = destructure_schema(&self.schema);
+        let tokenizer = self.index.tokenizer_for_field(body).unwrap();
+        Ok(clusters
+            .into_iter()
+            .map(|members| {
+                members
+                    .into_iter()
+                    .map(|member| self.to_response(&docs[member].1, &tokenizer, &HashSet::new()))
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// The document which has this location, if any
+    /// Facet terms also match the locations under them, eg: `/home` or `archive.zip!`, so the stored location has to be the same
+    fn doc_by_location(
+        &self,
+        searcher: &IndexSearcher,
+        location: &Path,
+    ) -> Result<Option<(DocAddress, Document)>> {
+        let (_, _, location_field, _) = destructure_schema(&self.schema);
+        if location.to_str().is_none() {
+            bail!("{:?} isn't a valid location", location);
+        }
+        // Locations are indexed canonicalized, eg: without `..` or symlinks
+        let location_facet = match Facet::from_text(&location.to_facet_value()) {
+            Ok(location_facet) => location_facet,
+            Err(_) => bail!("{:?} isn't an absolute location", location),
+        };
+        let query = TermQuery::new(
+            Term::from_facet(location_field, &location_facet),
+            IndexRecordOption::Basic,
+        );
+        for doc_address in searcher.search(&query, &DocSetCollector)? {
+            let doc = searcher.doc(doc_address)?;
+            let has_location = doc
+                .get_all(location_field)
+                .any(|value| matches!(value, Value::Facet(facet) if *facet == location_facet));
+            if has_location {
+                return Ok(Some((doc_address, doc)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Documents about the same topic as an indexed file, found by the most distinctive words of its title and body
+    /// Copies of a file share a single document, so they're never returned as related to each other
+    pub fn related(&self, file: &IndexedFile, limit: usize) -> Result<QueryResponse> {
+        if limit == 0 {
+            bail!("The limit of related documents has to be at least 1");
+        }
+        let limit = limit.min(MAX_RELATED_LIMIT);
+        let searcher = self.index_reader.searcher();
+        let (title, _, _, body) = destructure_schema(&self.schema);
+
+        let found = match file {
+            IndexedFile::Location(location) => self.doc_by_location(&searcher, location)?,
+            IndexedFile::Hash(doc_hash) => self.doc_by_hash(&searcher, doc_hash)?,
+        };
+        let (doc_address, doc) = match found {
+            Some(found) => found,
+            None => bail!("{:?} isn't indexed", file),
+        };
+        info!("Searching for documents related to {:?}...", file);
+
+        let doc_fields = [title, body]
+            .iter()
+            .map(|field| {
+                let values = doc
+                    .get_all(*field)
+                    .map(|value| FieldValue::new(*field, value.clone()))
+                    .collect();
+                (*field, values)
+            })
+            .collect();
+        let query = MoreLikeThisQuery::builder()
+            .with_min_doc_frequency(RELATED_MIN_DOC_FREQUENCY)
+            .with_min_term_frequency(1)
+            .with_max_query_terms(RELATED_MAX_QUERY_TERMS)
+            .with_document_fields(doc_fields);
+
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit + 1))?;
+        info!("Executed related search");
+
+        let tokenizer = self.index.tokenizer_for_field(body)?;
+        top_docs
+            .into_iter()
+            .filter(|(_, other_address)| *other_address != doc_address)
+            .take(limit)
+            .map(|(_, other_address)| {
+                let other_doc = searcher.doc(other_address)?;
+                Ok(self.to_response(&other_doc, &tokenizer, &HashSet::new()))
+            })
+            .collect()
+    }
+
+    fn doc_by_hash(
+        &self,
+        searcher: &IndexSearcher,
+        doc_hash: &str,
+    ) -> Result<Option<(DocAddress, Document)>> {
+        let (_, hash, _, _) = destructure_schema(&self.schema);
+        let query = TermQuery::new(
+            Term::from_field_text(hash, doc_hash),
+            IndexRecordOption::Basic,
+        );
+        match searcher.search(&query, &TopDocs::with_limit(1))?.first() {
+            Some((_, doc_address)) => Ok(Some((*doc_address, searcher.doc(*doc_address)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// The tags and the note of an indexed file
+    pub fn annotation(&self, file: &IndexedFile) -> Result<Annotation> {
+        let searcher = self.index_reader.searcher();
+        let doc = self.indexed_doc(&searcher, file)?;
+        Ok(self.doc_annotation(&doc)?.unwrap_or_default())
+    }
+
+    /// Changes the tags or the note of an indexed file, they're stored by the hash of its contents and its locations
+    /// Returns the annotation after the change
+    pub fn annotate(&self, file: &IndexedFile, update: AnnotationUpdate) -> Result<Annotation> {
+        let annotations = match &self.annotations {
+            Some(annotations) => annotations,
+            None => bail!("Files can't be annotated without an annotations file"),
+        };
+        let searcher = self.index_reader.searcher();
+        let (_, hash, _, _) = destructure_schema(&self.schema);
+        let doc = self.indexed_doc(&searcher, file)?;
+        let doc_hash = match doc.get_first(hash).and_then(Value::text) {
+            Some(doc_hash) => doc_hash,
+            None => bail!("{:?} has no hash", file),
+        };
+        let locations = self.locations(&doc);
+
+        let mut annotation = annotations.get(doc_hash, &locations)?.unwrap_or_default();
+        if let Some(tags) = update.tags {
+            annotation.tags = normalize_tags(tags);
+        }
+        if let Some(note) = update.note {
+            annotation.note = Some(note).filter(|note| !note.trim().is_empty());
+        }
+        annotations.set(doc_hash, &locations, annotation.clone())?;
+        info!("Annotated {:?} with {:?}", file, annotation);
+        Ok(annotation)
+    }
+
+    fn indexed_doc(&self, searcher: &IndexSearcher, file: &IndexedFile) -> Result<Document> {
+        let found = match file {
+            IndexedFile::Location(location) => self.doc_by_location(searcher, location)?,
+            IndexedFile::Hash(doc_hash) => self.doc_by_hash(searcher, doc_hash)?,
+        };
+        match found {
+            Some((_, doc)) => Ok(doc),
+            None => bail!("{:?} isn't indexed", file),
+        }
+    }
+
+    /// The annotation of a document by its hash, or by its locations if it changed since it was annotated
+    fn doc_annotation(&self, doc: &Document) -> Result<Option<Annotation>> {
+        let (_, hash, _, _) = destructure_schema(&self.schema);
+        match (&self.annotations, doc.get_first(hash).and_then(Value::text)) {
+            (Some(annotations), Some(doc_hash)) => annotations.get(doc_hash, &self.locations(doc)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Only keeps the documents which match the query and have every tag
+    fn filter_by_tags(
+        &self,
+        searcher: &IndexSearcher,
+        query: Box<dyn Query>,
+        tags: &[String],
+    ) -> Result<Box<dyn Query>> {
+        if tags.is_empty() {
+            return Ok(query);
+        }
+        let mut clauses = vec![(Occur::Must, query)];
+        for tag in tags {
+            clauses.push((Occur::Must, self.tag_query(searcher, tag)?));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Matches the documents with the tag in their extended attributes or in their annotation
+    fn tag_query(&self, searcher: &IndexSearcher, tag: &str) -> Result<Box<dyn Query>> {
+        let (_, hash, _, _) = destructure_schema(&self.schema);
+        let tag_field = self.schema.get_field("tag").unwrap();
+
+        let mut tagged_hashes = HashSet::new();
+        if let Some(annotations) = &self.annotations {
+            let (hashes, locations) = annotations.tagged(tag)?;
+            tagged_hashes.extend(hashes);
+            // Files which changed since they were annotated are found by where they were
+            for location in locations {
+                if let Some((_, doc)) = self.doc_by_location(searcher, &location)? {
+                    let is_tagged = matches!(
+                        self.doc_annotation(&doc)?,
+                        Some(annotation) if annotation.tags.iter().any(|other| other == tag)
+                    );
+                    match doc.get_first(hash).and_then(Value::text) {
+                        Some(doc_hash) if is_tagged => {
+                            tagged_hashes.insert(doc_hash.to_string());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let clauses = std::iter::once(Term::from_field_text(tag_field, tag))
+            .chain(
+                tagged_hashes
+                    .iter()
+                    .map(|doc_hash| Term::from_field_text(hash, doc_hash)),
+            )
+            .map(|term| {
+                let query: Box<dyn Query> =
+                    Box::new(TermQuery::new(term, IndexRecordOption::Basic));
+                (Occur::Should, query)
+            })
+            .collect();
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Every document which has a value in this i64 field
+    fn docs_with_field(
+        &self,
+        searcher: &IndexSearcher,
+        field: Field,
+    ) -> Result<Vec<(DocAddress, Document)>> {
+        let query = RangeQuery::new_i64_bounds(field, Bound::Unbounded, Bound::Unbounded);
+        searcher
+            .search(&query, &DocSetCollector)?
+            .into_iter()
+            .map(|doc_address| Ok((doc_address, searcher.doc(doc_address)?)))
+            .collect()
+    }
+
+    /// The perceptual hash of an image and its embedding if it has one
+    fn image_features(&self, doc: &Document) -> Option<(u64, Option<Vec<f32>>)> {
+        let perceptual_hash = self.schema.get_field("perceptual_hash").unwrap();
+        let image_embedding = self.schema.get_field("image_embedding").unwrap();
+        let hash = doc.get_first(perceptual_hash)?.i64_value()? as u64;
+        let embedding = doc
+            .get_first(image_embedding)
+            .and_then(Value::bytes_value)
+            .map(embedding_from_bytes);
+        Some((hash, embedding))
+    }
+
+    fn to_response(
+        &self,
+        retrieved_doc: &Document,
+        tokenizer: &TextAnalyzer,
+        query_words: &HashSet<String>,
+    ) -> Response {
+        let (title, _, location, body) = destructure_schema(&self.schema);
+
+        let title =
+            retrieved_doc
+                .get_all(title)
+                .map(|val| val.text())
+                .fold(String::new(), |mut acc, x| {
+                    acc.push_str(x.unwrap());
+                    acc.push(' ');
+                    acc
+                });
+        let location = retrieved_doc
+            .get_all(location)
+            .filter_map(|val| match &val {
+                Value::Facet(loc_str) => Some(Path::from_facet_value(loc_str)),
+                _ => None,
+            })
+            .collect();
+        let body =
+            retrieved_doc
+                .get_all(body)
+                .map(|val| val.text())
+                .fold(String::new(), |mut acc, x| {
+                    acc.push_str(x.unwrap());
+                    acc.push(' ');
+                    acc
+                });
+        let hits = matching_sections(retrieved_doc, &self.schema, tokenizer, query_words);
+        let coordinates = self.coordinates(retrieved_doc);
+        let modified = retrieved_doc
+            .get_first(self.schema.get_field("modified").unwrap())
+            .and_then(Value::i64_value);
+        let size = retrieved_doc
+            .get_first(self.schema.get_field("size").unwrap())
+            .and_then(Value::i64_value)
+            .map(|size| size as u64);
+        let language = retrieved_doc
+            .get_first(self.schema.get_field("language").unwrap())
+            .and_then(Value::text)
+            .map(String::from);
+        let truncated = retrieved_doc
+            .get_first(self.schema.get_field("truncated").unwrap())
+            .and_then(Value::i64_value)
+            == Some(1);
+        // A file whose annotation can't be read is still returned, without it
+        let annotation = self
+            .doc_annotation(retrieved_doc)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        let xattr_tags = retrieved_doc
+            .get_all(self.schema.get_field("tag").unwrap())
+            .filter_map(Value::text);
+        let tags = normalize_tags(annotation.tags.iter().map(String::as_str).chain(xattr_tags));
+        Response {
+            title,
+            location,
+            body,
+            hits,
+            coordinates,
+            modified,
+            size,
+            language,
+            truncated,
+            tags,
+            note: annotation.note,
+        }
+    }
+
+    fn coordinates(&self, doc: &Document) -> Option<[f64; 2]> {
+        let latitude = self.schema.get_field("latitude").unwrap();
+        let longitude = self.schema.get_field("longitude").unwrap();
+        Some([
+            doc.get_first(latitude)?.f64_value()?,
+            doc.get_first(longitude)?.f64_value()?,
+        ])
+    }
+}
+
+/// Seconds since the unix epoch, modification times are stored the same way
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Splits the `tag:` filters off a query, eg: `invoice tag:work tag:"to pay"` into `invoice` and the tags
+fn split_tag_filters(query_string: &str) -> (String, Vec<String>) {
+    let mut words = Vec::new();
+    let mut tags = Vec::new();
+    let mut rest = query_string.trim_start();
+    while !rest.is_empty() {
+        let remaining = match rest.strip_prefix("tag:") {
+            Some(tagged) => {
+                let (tag, remaining) = match tagged.strip_prefix('"') {
+                    Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+                    None => tagged
+                        .split_once(char::is_whitespace)
+                        .unwrap_or((tagged, "")),
+                };
+                tags.push(tag);
+                remaining
+            }
+            None => {
+                let (word, remaining) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                words.push(word);
+                remaining
+            }
+        };
+        rest = remaining.trim_start();
+    }
+    (words.join(" "), normalize_tags(tags))
+}
+
+/// How a tag is written in a query, quoted if it has spaces
+fn tag_filter(tag: &str) -> String {
+    if tag.contains(char::is_whitespace) {
+        format!("tag:\"{}\"", tag)
+    } else {
+        format!("tag:{}", tag)
+    }
+}
+
+/// Parses the words of a query, a query of only tag filters matches every file with the tags
+fn parse_words(query_parser: &QueryParser, words: &str, tags: &[String]) -> Result<Box<dyn Query>> {
+    if words.is_empty() && !tags.is_empty() {
+        return Ok(Box::new(AllQuery));
+    }
+    Ok(query_parser.parse_query(words)?)
+}
+
+/// The words of a query which are searched for in these fields
+fn query_words(query: &dyn Query, fields: &[Field]) -> HashSet<String> {
+    let mut query_terms = BTreeMap::new();
+    query.query_terms(&mut query_terms);
+    query_terms
+        .keys()
+        .filter(|term| fields.contains(&term.field()))
+        .map(|term| term.text().to_string())
+        .collect()
+}
+
+/// An embedding stored as little endian f32s
+fn embedding_from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+/// The cosine similarity of two embeddings which are already scaled to a length of 1
+fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Groups of the positions of hashes which are within `max_distance` bits of another hash in the group
+/// Hashes which aren't close to any other are left out, largest groups first
+fn cluster_by_hash(hashes: &[u64], max_distance: u32) -> Vec<Vec<usize>> {
+    // Every pair is compared, which is fine for the size of a personal library
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+    for i in 0..hashes.len() {
+        for j in i + 1..hashes.len() {
+            if (hashes[i] ^ hashes[j]).count_ones() <= max_distance {
+                let (root_i, root_j) = (find_root(&mut parents, i), find_root(&mut parents, j));
+                parents[root_j] = root_i;
+            }
+        }
+    }
+
+    let mut clusters: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for member in 0..hashes.len() {
+        let root = find_root(&mut parents, member);
+        clusters.entry(root).or_default().push(member);
+    }
+
+    let mut clusters = clusters
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .collect::<Vec<Vec<usize>>>();
+    clusters.sort_by_key(|members| Reverse(members.len()));
+    clusters
+}
+
+/// The representative of the cluster a member is in, clusters are merged by pointing one root at another
+fn find_root(parents: &mut [usize], mut member: usize) -> usize {
+    while parents[member] != member {
+        parents[member] = parents[parents[member]];
+        member = parents[member];
+    }
+    member
+}
+
+/// The great-circle distance between two (latitude, longitude) points
+fn haversine_distance_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (from_lat, to_lat) = (from.0.to_radians(), to.0.to_radians());
+    let lat_delta = to_lat - from_lat;
+    let lon_delta = (to.1 - from.1).to_radians();
+
+    let a = (lat_delta / 2.0).sin().powi(2)
+        + from_lat.cos() * to_lat.cos() * (lon_delta / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Labels of the sections of a document which contain any of the query words
+/// The sections are tokenized the same way as the body so they match the query terms
+fn matching_sections(
+    doc: &Document,
+    schema: &Schema,
+    tokenizer: &TextAnalyzer,
+    query_words: &HashSet<String>,
+) -> Vec<String> {
+    let section_label = schema.get_field("section_label").unwrap();
+    let section_text = schema.get_field("section_text").unwrap();
+
+    doc.get_all(section_label)
+        .zip(doc.get_all(section_text))
+        .filter_map(|(label, text)| {
+            let label = label.text()?;
+            let mut token_stream = tokenizer.token_stream(text.text()?);
+            let mut is_hit = false;
+            while token_stream.advance() {
+                if query_words.contains(&token_stream.token().text) {
+                    is_hit = true;
+                    break;
+                }
+            }
+            if is_hit {
+                Some(label.to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// What is opened for a location, entries nested inside of a file (eg: a message of an mbox) open the file they're in
+/// Opens are still recorded for the entry, which is its own document
+fn opened_path(location: &Path, action: OpenAction) -> Result<&Path> {
+    let file = containing_file(location);
+    match action {
+        OpenAction::Open => Ok(file),
+        OpenAction::Reveal => match file.parent() {
+            Some(folder) => Ok(folder),
+            None => bail!("{:?} isn't in a folder", location),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_tantivy::{
+        languages::register_tokenizers,
+        utils::{add_filenames, build_schema, name_key},
+    };
+
+    /// A searcher over an index in memory with these documents
+    fn searcher_with(docs: Vec<Document>) -> Searcher {
+        let schema = build_schema();
+        let index = Index::create_in_ram(schema.clone());
+        register_tokenizers(&index);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for doc in docs {
+            index_writer.add_document(doc);
+        }
+        index_writer.commit().unwrap();
+        let index_reader = index.reader().unwrap();
+        Searcher::new(index, index_reader, schema)
+    }
+
+    /// A document titled after its first location, found at every location
+    fn doc(locations: &[&str]) -> Document {
+        let (title, _, location_field, _) = destructure_schema(&build_schema());
+        let mut doc = Document::default();
+        doc.add_text(
+            title,
+            Path::new(locations[0])
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap(),
+        );
+        for location in locations {
+            doc.add_facet(location_field, Facet::from_text(location).unwrap());
+        }
+        add_filenames(&mut doc, &build_schema());
+        doc
+    }
+
+    fn text(location: &str, simhash: u64) -> Document {
+        let mut doc = doc(&[location]);
+        doc.add_i64(build_schema().get_field("simhash").unwrap(), simhash as i64);
+        doc
+    }
+
+    fn image(location: &str, perceptual_hash: u64, embedding: Option<&[f32]>) -> Document {
+        let schema = build_schema();
+        let mut doc = doc(&[location]);
+        doc.add_i64(
+            schema.get_field("perceptual_hash").unwrap(),
+            perceptual_hash as i64,
+        );
+        if let Some(embedding) = embedding {
+            let bytes = embedding
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect::<Vec<u8>>();
+            doc.add_bytes(schema.get_field("image_embedding").unwrap(), bytes);
+        }
+        doc
+    }
+
+    fn titles(responses: &[Response]) -> Vec<&str> {
+        responses
+            .iter()
+            .map(|response| response.title.trim_end())
+            .collect()
+    }
+
+    fn locations(images: &[SimilarImage]) -> Vec<PathBuf> {
+        images
+            .iter()
+            .map(|image| image.response.location[0].clone())
             .collect()
     }
+
+    #[test]
+    fn test_similar_images_are_ranked() {
+        let searcher = searcher_with(vec![
+            image("/photos/cat.jpg", 0, Some(&[1.0, 0.0])),
+            image("/photos/dog.jpg", u64::MAX, Some(&[0.0, 1.0])),
+            image("/photos/kitten.jpg", u64::MAX, Some(&[0.8, 0.6])),
+            // Without an embedding the hash is compared, 2 bits away is a similarity of 0.97
+            image("/photos/cat_resized.jpg", 0b11, None),
+        ]);
+
+        let similar = searcher
+            .similar_images(Path::new("/photos/cat.jpg"), 10)
+            .unwrap();
+        assert_eq!(
+            locations(&similar),
+            vec![
+                PathBuf::from("/photos/cat_resized.jpg"),
+                PathBuf::from("/photos/kitten.jpg"),
+                PathBuf::from("/photos/dog.jpg"),
+            ]
+        );
+        assert_eq!(similar[0].similarity, None);
+        assert_eq!(similar[0].hash_distance, 2);
+        assert!((similar[1].similarity.unwrap() - 0.8).abs() < 1e-6);
+
+        let similar = searcher
+            .similar_images(Path::new("/photos/cat.jpg"), 1)
+            .unwrap();
+        assert_eq!(
+            locations(&similar),
+            vec![PathBuf::from("/photos/cat_resized.jpg")]
+        );
+    }
+
+    #[test]
+    fn test_similar_images_hash_distance_cutoff() {
+        let searcher = searcher_with(vec![
+            image("/photos/cat.jpg", 0, None),
+            image(
+                "/photos/cropped.jpg",
+                (1 << MAX_SIMILAR_HASH_DISTANCE) - 1,
+                None,
+            ),
+            image(
+                "/photos/other.jpg",
+                (1 << (MAX_SIMILAR_HASH_DISTANCE + 1)) - 1,
+                None,
+            ),
+            // The embedding doesn't count when the image searched from has none
+            image("/photos/dog.jpg", u64::MAX, Some(&[1.0, 0.0])),
+        ]);
+
+        let similar = searcher
+            .similar_images(Path::new("/photos/cat.jpg"), 10)
+            .unwrap();
+        assert_eq!(
+            locations(&similar),
+            vec![PathBuf::from("/photos/cropped.jpg")]
+        );
+        assert_eq!(similar[0].hash_distance, MAX_SIMILAR_HASH_DISTANCE);
+        assert!(searcher
+            .similar_images(Path::new("/photos/missing.jpg"), 10)
+            .is_err());
+    }
+
+    #[test]
+    fn test_doc_by_location_is_exact() {
+        let searcher = searcher_with(vec![
+            doc(&["/home/cats/notes.txt"]),
+            doc(&["/home/cats.zip!/inner/notes.txt"]),
+        ]);
+        let index_searcher = searcher.index_reader.searcher();
+        let found = |location: &str| {
+            searcher
+                .doc_by_location(&index_searcher, Path::new(location))
+                .unwrap()
+                .map(|(_, doc)| searcher.locations(&doc))
+        };
+
+        assert_eq!(
+            found("/home/cats/notes.txt"),
+            Some(vec![PathBuf::from("/home/cats/notes.txt")])
+        );
+        assert_eq!(found("/home"), None);
+        assert_eq!(found("/home/cats"), None);
+        assert_eq!(found("/home/cats.zip!"), None);
+        assert!(found("/home/cats.zip!/inner/notes.txt").is_some());
+    }
+
+    #[test]
+    fn test_suggest() {
+        let (title, _, _, body) = destructure_schema(&build_schema());
+        let mut budget = doc(&["/docs/budget_2021.xlsx", "/backup/old.xlsx"]);
+        budget.add_text(body, "quarterly budget");
+        let mut report = doc(&["/docs/report.pdf"]);
+        report.add_text(title, "Quarterly budget report");
+        let searcher = searcher_with(vec![budget, report]);
+
+        let suggestions = searcher.suggest("bud", 10).unwrap();
+        assert_eq!(suggestions.completions, vec!["budget"]);
+        assert_eq!(suggestions.filenames, vec!["budget_2021.xlsx"]);
+
+        // Every word has to be in the file name, the title of the report isn't its file name
+        let suggestions = searcher.suggest("quarterly rep", 10).unwrap();
+        assert_eq!(suggestions.completions, vec!["quarterly report"]);
+        assert!(suggestions.filenames.is_empty());
+        assert!(searcher.suggest("quar", 10).unwrap().filenames.is_empty());
+        assert_eq!(
+            searcher.suggest("rep", 10).unwrap().filenames,
+            vec!["report.pdf"]
+        );
+
+        let suggestions = searcher.suggest("b", 10).unwrap();
+        assert_eq!(suggestions.completions, vec!["budget"]);
+    }
+
+    #[test]
+    fn test_suggesting_a_single_character_walks_fewer_words() {
+        let (_, _, _, body) = destructure_schema(&build_schema());
+        let mut words = doc(&["/docs/list.txt"]);
+        let text = (0..SHORT_PREFIX_TERMS + 100)
+            .map(|number| format!("w{:05}", number))
+            .collect::<Vec<String>>()
+            .join(" ");
+        words.add_text(body, &text);
+        let searcher = searcher_with(vec![words]);
+
+        let suggestions = searcher.suggest("w", usize::MAX).unwrap();
+        assert_eq!(suggestions.completions.len(), SHORT_PREFIX_TERMS);
+        let suggestions = searcher.suggest("w0", usize::MAX).unwrap();
+        assert_eq!(suggestions.completions.len(), SHORT_PREFIX_TERMS + 100);
+    }
+
+    #[test]
+    fn test_exact_duplicates() {
+        let size_field = build_schema().get_field("size").unwrap();
+        let sized = |locations: &[&str], size: i64| {
+            let mut doc = doc(locations);
+            doc.add_i64(size_field, size);
+            doc
+        };
+        let searcher = searcher_with(vec![
+            sized(&["/notes/cats.txt"], 5000),
+            sized(&["/backup/a.txt", "/backup/b.txt", "/backup/c.txt"], 10),
+            sized(&["/docs/Cats.pdf", "/backup/Cats.pdf"], 1000),
+        ]);
+
+        let groups = searcher.exact_duplicates().unwrap();
+        assert_eq!(groups.len(), 2);
+
+        // The group wasting the most comes first
+        assert_eq!(groups[0].title, "Cats.pdf");
+        assert_eq!(
+            groups[0].locations,
+            vec![
+                PathBuf::from("/docs/Cats.pdf"),
+                PathBuf::from("/backup/Cats.pdf")
+            ]
+        );
+        assert_eq!(groups[0].size, 1000);
+        assert_eq!(groups[0].wasted_bytes, 1000);
+
+        assert_eq!(groups[1].locations.len(), 3);
+        assert_eq!(groups[1].wasted_bytes, 20);
+    }
+
+    #[test]
+    fn test_near_duplicates() {
+        let searcher = searcher_with(vec![
+            text("/docs/draft.txt", 0),
+            text("/docs/edited.txt", 0xFF),
+            // More than the distance away from the draft, but close enough to the edited version
+            text("/docs/final.txt", 0xFFFF),
+            text("/docs/other.txt", 0x1FF << 32),
+            text("/docs/unrelated.txt", u64::MAX),
+        ]);
+
+        let clusters = searcher
+            .near_duplicates(DEFAULT_MAX_SIMHASH_DISTANCE)
+            .unwrap();
+        assert_eq!(clusters.len(), 1);
+        let mut cluster_titles = titles(&clusters[0]);
+        cluster_titles.sort_unstable();
+        assert_eq!(cluster_titles, vec!["draft.txt", "edited.txt", "final.txt"]);
+
+        assert!(searcher.near_duplicates(7).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cluster_by_hash() {
+        let hashes = [0, 0b1, 0b11, u64::MAX, u64::MAX - 1, 0b111 << 40];
+        assert_eq!(cluster_by_hash(&hashes, 1), vec![vec![0, 1, 2], vec![3, 4]]);
+        assert_eq!(cluster_by_hash(&hashes, 0), Vec::<Vec<usize>>::new());
+    }
+
+    #[test]
+    fn test_a_body_match_counts_once_whatever_the_language() {
+        let schema = build_schema();
+        let (_, _, _, body) = destructure_schema(&schema);
+        let language = schema.get_field("language").unwrap();
+        let text = "The cats are sleeping on the sofa while the children play";
+        let mut english = doc(&["/docs/english.txt"]);
+        english.add_text(body, text);
+        english.add_text(language, "eng");
+        english.add_text(schema.get_field("body_eng").unwrap(), text);
+        let mut unknown = doc(&["/docs/unknown.txt"]);
+        unknown.add_text(body, text);
+        unknown.add_text(schema.get_field("body_other").unwrap(), text);
+        let searcher = searcher_with(vec![english, unknown]);
+
+        let query = searcher
+            .query_parser(&Relevance::default())
+            .parse_query("sofa")
+            .unwrap();
+        let index_searcher = searcher.index_reader.searcher();
+        let scores = index_searcher
+            .search(&query, &TopDocs::with_limit(2))
+            .unwrap()
+            .into_iter()
+            .map(|(score, _)| score)
+            .collect::<Vec<f32>>();
+        assert_eq!(scores.len(), 2);
+        assert!((scores[0] - scores[1]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_related_limit() {
+        let (_, _, _, body) = destructure_schema(&build_schema());
+        let docs = [
+            ("/docs/cats.txt", "cats purr and cats nap"),
+            ("/docs/more cats.txt", "cats purr loudly"),
+            ("/docs/dogs.txt", "dogs bark"),
+        ]
+        .iter()
+        .map(|(location, text)| {
+            let mut doc = doc(&[location]);
+            doc.add_text(body, text);
+            doc
+        })
+        .collect();
+        let searcher = searcher_with(docs);
+        let file = IndexedFile::Location(PathBuf::from("/docs/cats.txt"));
+
+        assert!(searcher.related(&file, 0).is_err());
+        // Every title has "txt", so every other document is related
+        let related = searcher.related(&file, usize::MAX).unwrap();
+        assert_eq!(titles(&related), vec!["more cats.txt", "dogs.txt"]);
+        let related = searcher.related(&file, 1).unwrap();
+        assert_eq!(titles(&related), vec!["more cats.txt"]);
+    }
+
+    #[test]
+    fn test_opening_an_entry_opens_its_file() {
+        let archive = std::fs::canonicalize("test_files/Animals.zip").unwrap();
+        let entry = PathBuf::from(format!("{}!/animals/data.csv", archive.display()));
+
+        assert_eq!(opened_path(&entry, OpenAction::Open).unwrap(), archive);
+        assert_eq!(
+            opened_path(&entry, OpenAction::Reveal).unwrap(),
+            archive.parent().unwrap()
+        );
+        assert_eq!(opened_path(&archive, OpenAction::Open).unwrap(), archive);
+    }
+
+    #[test]
+    fn test_sorting_by_name_compares_whole_names() {
+        let name_key_field = build_schema().get_field("name_key").unwrap();
+        let named = |location: &str| {
+            let mut doc = doc(&[location]);
+            let name = Path::new(location).file_name().unwrap().to_string_lossy();
+            doc.add_u64(name_key_field, name_key(&name));
+            doc
+        };
+        // More names than are returned share their first bytes, the last ones are added first
+        let mut docs = vec![doc(&["/docs/unnamed"])];
+        docs.extend(
+            (0..60)
+                .rev()
+                .map(|n| named(&format!("/shots/screenshot_{:02}.png", n))),
+        );
+        docs.push(named("/docs/a.txt"));
+        let searcher = searcher_with(docs);
+        let sorted = |order: SortOrder| {
+            let index_searcher = searcher.index_reader.searcher();
+            let sort = Sort {
+                key: SortKey::Filename,
+                order,
+            };
+            searcher
+                .sorted_search(&index_searcher, &AllQuery, sort)
+                .unwrap()
+                .iter()
+                .map(|doc| {
+                    doc.get_first(build_schema().get_field("title").unwrap())
+                        .unwrap()
+                        .text()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<String>>()
+        };
+
+        let mut expected = vec![String::from("a.txt")];
+        expected.extend((0..9).map(|n| format!("screenshot_{:02}.png", n)));
+        assert_eq!(sorted(SortOrder::Asc), expected);
+        let expected = (50..60)
+            .rev()
+            .map(|n| format!("screenshot_{:02}.png", n))
+            .collect::<Vec<String>>();
+        assert_eq!(sorted(SortOrder::Desc), expected);
+    }
+
+    #[test]
+    fn test_invalid_queries_are_errors() {
+        let searcher = searcher_with(vec![doc(&["/notes/cats.txt"])]);
+        let search = |query: &str| {
+            searcher.search(query.to_string(), &Relevance::default(), Sort::default())
+        };
+
+        assert_eq!(titles(&search("cats").unwrap()), vec!["cats.txt"]);
+        assert!(search("title:(cats").is_err());
+    }
+
+    #[test]
+    fn test_split_tag_filters() {
+        assert_eq!(
+            split_tag_filters("invoice tag:Work  2021 tag:\"to pay\""),
+            (
+                String::from("invoice 2021"),
+                vec![String::from("work"), String::from("to pay")]
+            )
+        );
+        assert_eq!(
+            split_tag_filters("tag:\"to pay"),
+            (String::new(), vec![String::from("to pay")])
+        );
+        assert_eq!(
+            split_tag_filters("invoice"),
+            (String::from("invoice"), Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        assert_eq!(tag_filter("work"), "tag:work");
+        assert_eq!(tag_filter("to pay"), "tag:\"to pay\"");
+        let (_, tags) = split_tag_filters(&tag_filter("to pay"));
+        assert_eq!(tags, vec![String::from("to pay")]);
+    }
+
+    #[test]
+    fn test_parse_words() {
+        let searcher = searcher_with(vec![]);
+        let query_parser = searcher.query_parser(&Relevance::default());
+        let tags = vec![String::from("work")];
+
+        let query = parse_words(&query_parser, "", &tags).unwrap();
+        assert!(query.as_any().is::<AllQuery>());
+        let query = parse_words(&query_parser, "invoice", &tags).unwrap();
+        assert!(!query.as_any().is::<AllQuery>());
+        assert!(parse_words(&query_parser, "", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_find_root() {
+        let mut parents = vec![0, 0, 1, 2, 4];
+        assert_eq!(find_root(&mut parents, 3), 0);
+        assert_eq!(find_root(&mut parents, 4), 4);
+        // The path walked is shortened for the next lookups
+        assert_eq!(parents[3], 1);
+    }
 }
@@ -1,17 +1,29 @@
-use crate::contracts::file_to_process::new_file_to_process;
-use crate::custom_tantivy::{utils::build_schema, wrapper::*};
+use crate::annotations::Annotations;
+use crate::custom_tantivy::{
+    languages::register_tokenizers,
+    utils::{build_schema, SCHEMA_VERSION},
+    wrapper::*,
+};
 use crate::file_watcher::*;
 use crate::indexers::Analyzer;
+use crate::open_history::OpenHistory;
 use crate::searcher::Searcher;
+#[cfg(feature = "semantic")]
+use embeddings::VectorStore;
 
 use common::tantivy;
 use common::tantivy::directory::*;
-use common::tantivy::{Index, ReloadPolicy};
+use common::tantivy::schema::Schema;
+use common::tantivy::{Index, IndexReader, ReloadPolicy};
 use common::tracing::{info, span, Level};
 use walkdir::WalkDir;
 
 use std::fs;
 use std::path::PathBuf;
+use std::sync::Arc;
+
+/// How many files are indexed by the initial processing between commits, committing every file is slow
+const FILES_PER_COMMIT: usize = 1000;
 
 pub struct TantivyConfig {
     pub scan_directories: Vec<PathBuf>,
@@ -37,6 +49,7 @@ pub async fn start_tantivy(
         info!("Initial processing was not previously done, doing now");
         let initial_processing_span = span!(Level::INFO, "initial_processing");
         let _initial_processing_entry = initial_processing_span.enter();
+        let mut uncommitted_files = 0;
         for directory in directories {
             let walker = WalkDir::new(directory).into_iter();
             for entry in walker.filter_entry(|e| !is_hidden(e)) {
@@ -51,25 +64,20 @@ pub async fn start_tantivy(
                 let entry_path = entry.path().to_str().unwrap();
                 let process_file_span = span!(Level::INFO, "processing_file", entry_path);
                 let _process_file_entry = process_file_span.enter();
-                if !entry.file_type().is_dir() {
-                    let entry_path = entry.path();
-
-                    match entry_path.extension() {
-                        None => continue,
-                        Some(extension) => {
-                            if !analyzer.supported_extensions.contains(extension) {
-                                continue;
-                            }
-                        }
-                    }
-
-                    let file_to_process = new_file_to_process(entry_path).await;
+                // Sockets, pipes and other special files can't be read
+                if entry.file_type().is_file() {
+                    let file_to_process = read_file(entry.into_path(), &analyzer).await;
 
                     tantivy_wrapper.process_file(file_to_process).await;
-                    tantivy_wrapper.index_writer.commit()?;
+                    uncommitted_files += 1;
+                    if uncommitted_files == FILES_PER_COMMIT {
+                        tantivy_wrapper.commit()?;
+                        uncommitted_files = 0;
+                    }
                 }
             }
         }
+        tantivy_wrapper.commit()?;
         fs::File::create(&settings.initial_processing_file).unwrap();
     } else {
         info!("Initial processing already done! Starting a reader");
@@ -81,22 +89,113 @@ pub async fn start_tantivy(
 }
 
 pub fn tantivy_init(settings: &TantivyConfig) -> tantivy::Result<(Searcher, TantivyWrapper)> {
-    let index_path = &settings.index_path;
+    migrate_index(settings)?;
+    let (index, index_reader, schema) = open_index(settings)?;
+
+    let index_writer = index.writer(50_000_000)?;
+
+    let searcher = Searcher::new(index, index_reader.clone(), schema.clone())
+        .with_open_history(open_history(settings)?)
+        .with_annotations(annotations(settings)?);
+
+    let tantivy_wrapper = TantivyWrapper::new(index_reader, index_writer, schema);
+
+    // The vectors are stored next to the index, eg: `Podium/vectors` for `Podium/index`
+    #[cfg(feature = "semantic")]
+    let (searcher, tantivy_wrapper) = {
+        let vector_store = VectorStore::open(&settings.index_path.with_file_name("vectors"))
+            .map(Arc::new)
+            .map_err(|e| tantivy::TantivyError::SystemError(e.to_string()))?;
+        (
+            searcher.with_vector_store(vector_store.clone()),
+            tantivy_wrapper.with_vector_store(vector_store),
+        )
+    };
 
+    Ok((searcher, tantivy_wrapper))
+}
+
+/// A searcher which doesn't take the index writer lock, so it works while podium is running
+/// Eg: for reports printed from the command line
+/// The index isn't rebuilt when it's out of date, since podium may be running and using it
+pub fn searcher_init(settings: &TantivyConfig) -> tantivy::Result<Searcher> {
+    if schema_version(settings) != Some(SCHEMA_VERSION) {
+        return Err(tantivy::TantivyError::SchemaError(String::from(
+            "The index was built by another version of podium, start podium to rebuild it",
+        )));
+    }
+    let (index, index_reader, schema) = open_index(settings)?;
+    Ok(Searcher::new(index, index_reader, schema)
+        .with_open_history(open_history(settings)?)
+        .with_annotations(annotations(settings)?))
+}
+
+/// The opens are stored next to the index, eg: `Podium/opens` for `Podium/index`
+fn open_history(settings: &TantivyConfig) -> tantivy::Result<Arc<OpenHistory>> {
+    OpenHistory::open(&settings.index_path.with_file_name("opens"))
+        .map(Arc::new)
+        .map_err(|e| tantivy::TantivyError::SystemError(e.to_string()))
+}
+
+/// The tags and notes of files are stored next to the index, eg: `Podium/annotations` for `Podium/index`
+fn annotations(settings: &TantivyConfig) -> tantivy::Result<Arc<Annotations>> {
+    Annotations::open(&settings.index_path.with_file_name("annotations"))
+        .map(Arc::new)
+        .map_err(|e| tantivy::TantivyError::SystemError(e.to_string()))
+}
+
+/// The version of the schema the index was built with is stored next to it, eg: `Podium/schema_version` for `Podium/index`
+fn schema_version_path(settings: &TantivyConfig) -> PathBuf {
+    settings.index_path.with_file_name("schema_version")
+}
+
+/// Indexes built before the version was stored have none
+fn schema_version(settings: &TantivyConfig) -> Option<u32> {
+    fs::read_to_string(schema_version_path(settings))
+        .ok()
+        .and_then(|version| version.trim().parse().ok())
+}
+
+/// Deletes an index built with another schema, eg: by an older podium, so every file is indexed again
+/// Opens, annotations and vectors are stored by hash next to the index, so they're kept
+fn migrate_index(settings: &TantivyConfig) -> tantivy::Result<()> {
+    let version = schema_version(settings);
+    if version == Some(SCHEMA_VERSION) {
+        return Ok(());
+    }
+
+    let index_exists = fs::read_dir(&settings.index_path)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if index_exists {
+        info!(
+            "The index was built with schema version {:?}, rebuilding it with version {}",
+            version, SCHEMA_VERSION
+        );
+        fs::remove_dir_all(&settings.index_path)?;
+        fs::create_dir_all(&settings.index_path)?;
+        if settings.initial_processing_file.exists() {
+            fs::remove_file(&settings.initial_processing_file)?;
+        }
+    }
+    fs::write(schema_version_path(settings), SCHEMA_VERSION.to_string())?;
+    Ok(())
+}
+
+fn open_index(settings: &TantivyConfig) -> tantivy::Result<(Index, IndexReader, Schema)> {
     let schema = build_schema();
 
-    let index = Index::open_or_create(MmapDirectory::open(index_path).unwrap(), schema.clone())?;
+    let index = Index::open_or_create(
+        MmapDirectory::open(&settings.index_path).unwrap(),
+        schema.clone(),
+    )?;
+    // The analyzers of the language fields aren't saved with the index
+    register_tokenizers(&index);
 
     let index_reader = index
         .reader_builder()
         .reload_policy(ReloadPolicy::OnCommit)
         .try_into()?;
 
-    let index_writer = index.writer(50_000_000)?;
-
-    let searcher = Searcher::new(index, index_reader.clone(), schema.clone());
-
-    let tantivy_wrapper = TantivyWrapper::new(index_reader, index_writer, schema);
-
-    Ok((searcher, tantivy_wrapper))
+    Ok((index, index_reader, schema))
 }